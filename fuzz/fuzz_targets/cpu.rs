@@ -0,0 +1,6 @@
+#![no_main]
+
+// Arbitrary memory images through the CPU: cargo fuzz run cpu
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    astrobyte::fuzzing::cpu(data);
+});
@@ -0,0 +1,6 @@
+#![no_main]
+
+// Arbitrary text through the script reader: cargo fuzz run script
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    astrobyte::fuzzing::script(data);
+});
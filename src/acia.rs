@@ -0,0 +1,256 @@
+use crate::console::stdin_reader;
+use crate::devices::Device;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Registers, as offsets from the address the ACIA is attached at
+pub const DATA: u16 = 0x0;
+pub const STATUS: u16 = 0x1; // Writing here resets the chip
+pub const COMMAND: u16 = 0x2;
+pub const CONTROL: u16 = 0x3;
+
+// Bits of the status register
+pub const STATUS_OVERRUN: u8 = 0b0000_0100;
+pub const STATUS_RECEIVE_FULL: u8 = 0b0000_1000;
+pub const STATUS_TRANSMIT_EMPTY: u8 = 0b0001_0000;
+pub const STATUS_IRQ: u8 = 0b1000_0000;
+
+// Bits of the command register
+pub const COMMAND_DTR: u8 = 0b0000_0001; // Receiver on
+pub const COMMAND_RECEIVE_IRQ_OFF: u8 = 0b0000_0010;
+pub const COMMAND_ECHO: u8 = 0b0001_0000;
+
+// Where the other end of the serial line is
+#[derive(Clone, PartialEq, Debug)]
+pub enum Backend {
+    Terminal, // stdin and stdout
+    Tcp(u16), // A TCP listener on localhost, for connecting with telnet, netcat or a test harness
+    Pipe(PathBuf, PathBuf), // Read from one file (usually a named pipe) and write to another
+}
+
+/*
+    A 6551 ACIA (serial port). Bytes written to the data register are sent out, and bytes coming in
+    show up in the data register with the receive-full bit set in the status register.
+
+    The baud rate and format in the control register are kept but don't matter, bytes go through as fast as they come.
+    Sending is instant, so the transmit-empty bit is always set. A byte that arrives while the last one
+    hasn't been read yet waits its turn instead of being lost.
+
+    With the receiver on (bit 0 of the command register) and receive interrupts not disabled (bit 1), every byte
+    that comes in pulls the IRQ line until the status register is read
+*/
+pub struct Acia {
+    base: u16,
+    input: Receiver<u8>,
+    output: Box<dyn Write>,
+    received: u8,
+    status: u8,
+    command: u8,
+    control: u8,
+}
+
+impl Acia {
+    pub fn new(base: u16, input: Receiver<u8>, output: Box<dyn Write>) -> Self {
+        Acia {
+            base,
+            input,
+            output,
+            received: 0,
+            status: STATUS_TRANSMIT_EMPTY,
+            command: 0,
+            control: 0,
+        }
+    }
+
+    pub fn with_backend(base: u16, backend: &Backend) -> io::Result<Self> {
+        match backend {
+            Backend::Terminal => Ok(Acia::new(base, stdin_reader(), Box::new(io::stdout()))),
+            Backend::Tcp(port) => Acia::tcp(base, *port),
+            Backend::Pipe(input, output) => Ok(Acia::pipe(base, input, output)),
+        }
+    }
+
+    /*
+        Listens on 127.0.0.1 and talks to whoever connects. A new connection takes over from the last one,
+        and anything sent while nobody is connected is dropped
+    */
+    pub fn tcp(base: u16, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("ACIA listening on 127.0.0.1:{}", listener.local_addr()?.port());
+
+        let (sender, receiver) = mpsc::channel();
+        let connection: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+        let accepted = connection.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(writer) = stream.try_clone() {
+                    *accepted.lock().unwrap() = Some(writer);
+                }
+
+                let sender = sender.clone();
+                thread::spawn(move || forward_bytes(stream, sender));
+            }
+        });
+
+        Ok(Acia::new(base, receiver, Box::new(TcpOutput(connection))))
+    }
+
+    /*
+        Reads from one file and writes to another. Made for named pipes (mkfifo), which block when opened until
+        the other side opens them too, so the output is only opened on the first byte sent
+    */
+    pub fn pipe(base: u16, input: &Path, output: &Path) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let input = input.to_path_buf();
+
+        thread::spawn(move || {
+            if let Ok(file) = File::open(&input) {
+                forward_bytes(file, sender);
+            }
+        });
+
+        Acia::new(base, receiver, Box::new(PipeOutput { path: output.to_path_buf(), file: None }))
+    }
+
+    // Take the next byte off the line once the last one has been read
+    fn receive(&mut self) {
+        if self.status & STATUS_RECEIVE_FULL != 0 {
+            return;
+        }
+
+        if let Ok(byte) = self.input.try_recv() {
+            self.received = byte;
+            self.status |= STATUS_RECEIVE_FULL;
+
+            if self.command & COMMAND_ECHO != 0 {
+                self.transmit(byte);
+            }
+
+            if self.command & (COMMAND_DTR | COMMAND_RECEIVE_IRQ_OFF) == COMMAND_DTR {
+                self.status |= STATUS_IRQ;
+            }
+        }
+    }
+
+    fn transmit(&mut self, data: u8) {
+        let _ = self.output.write_all(&[data]);
+        let _ = self.output.flush();
+    }
+}
+
+// Send everything read from a stream into the channel, until either side closes
+fn forward_bytes<R: Read>(reader: R, sender: Sender<u8>) {
+    for byte in BufReader::new(reader).bytes() {
+        match byte {
+            Ok(byte) => {
+                if sender.send(byte).is_err() {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+struct TcpOutput(Arc<Mutex<Option<TcpStream>>>);
+
+impl Write for TcpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut connection = self.0.lock().unwrap();
+        if let Some(stream) = connection.as_mut() {
+            if stream.write_all(buf).is_err() {
+                *connection = None; // They hung up
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct PipeOutput {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Write for PipeOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() {
+            self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        }
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Device for Acia {
+    fn maps(&self, address: u16) -> bool {
+        address >= self.base && address - self.base < 4
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        match address - self.base {
+            DATA => {
+                self.status &= !(STATUS_RECEIVE_FULL | STATUS_OVERRUN);
+                self.received
+            }
+
+            // Reading the status is what clears the interrupt
+            STATUS => {
+                self.receive();
+                let status = self.status;
+                self.status &= !STATUS_IRQ;
+                status
+            }
+
+            register => self.peek(self.base + register),
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address - self.base {
+            DATA => self.transmit(data),
+
+            // Programmed reset, the low bits of the command register are cleared
+            STATUS => {
+                self.command &= 0b1110_0000;
+                self.status &= !STATUS_OVERRUN;
+            }
+
+            COMMAND => self.command = data,
+            CONTROL => self.control = data,
+            _ => {}
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        match address - self.base {
+            DATA => self.received,
+            STATUS => self.status,
+            COMMAND => self.command,
+            _ => self.control,
+        }
+    }
+
+    fn tick(&mut self, _cycles: u8) {
+        self.receive();
+    }
+
+    fn irq(&self) -> bool {
+        self.status & STATUS_IRQ != 0
+    }
+}
@@ -0,0 +1,270 @@
+use crate::cli::{self, Options};
+use crate::console::stdin_reader;
+use crate::dump;
+use crate::devices::{Device, Rom, Unmapped};
+use crate::processor::CPU;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// The PIA (6821) that connects the keyboard and the display
+pub const KBD: u16 = 0xD010; // Last key typed, with bit 7 set
+pub const KBDCR: u16 = 0xD011; // Bit 7 is set while a key is waiting to be read
+pub const DSP: u16 = 0xD012; // Characters written here show up on the display
+pub const DSPCR: u16 = 0xD013;
+
+pub const WOZMON_START: u16 = 0xFF00;
+pub const RAM_END: u16 = 0x0FFF; // The stock Apple I came with 4K of RAM
+
+const SPEED_CHECK: u64 = 1000; // Sleep for a millisecond every 1000 instructions, about as fast as the real 1 MHz machine
+
+/*
+    Steve Wozniak's monitor from 1976, the only ROM the Apple I shipped with. It sits at 0xFF00 - 0xFFFF,
+    so it also holds the NMI, reset and IRQ vectors. Typing an address examines it, 300.30F examines a range,
+    300: A9 01 stores bytes starting at 0x0300, and 300R runs the code there
+*/
+pub const WOZMON: [u8; 256] = [
+    0xD8, 0x58, 0xA0, 0x7F, 0x8C, 0x12, 0xD0, 0xA9, 0xA7, 0x8D, 0x11, 0xD0, 0x8D, 0x13, 0xD0, 0xC9,
+    0xDF, 0xF0, 0x13, 0xC9, 0x9B, 0xF0, 0x03, 0xC8, 0x10, 0x0F, 0xA9, 0xDC, 0x20, 0xEF, 0xFF, 0xA9,
+    0x8D, 0x20, 0xEF, 0xFF, 0xA0, 0x01, 0x88, 0x30, 0xF6, 0xAD, 0x11, 0xD0, 0x10, 0xFB, 0xAD, 0x10,
+    0xD0, 0x99, 0x00, 0x02, 0x20, 0xEF, 0xFF, 0xC9, 0x8D, 0xD0, 0xD4, 0xA0, 0xFF, 0xA9, 0x00, 0xAA,
+    0x0A, 0x85, 0x2B, 0xC8, 0xB9, 0x00, 0x02, 0xC9, 0x8D, 0xF0, 0xD4, 0xC9, 0xAE, 0x90, 0xF4, 0xF0,
+    0xF0, 0xC9, 0xBA, 0xF0, 0xEB, 0xC9, 0xD2, 0xF0, 0x3B, 0x86, 0x28, 0x86, 0x29, 0x84, 0x2A, 0xB9,
+    0x00, 0x02, 0x49, 0xB0, 0xC9, 0x0A, 0x90, 0x06, 0x69, 0x88, 0xC9, 0xFA, 0x90, 0x11, 0x0A, 0x0A,
+    0x0A, 0x0A, 0xA2, 0x04, 0x0A, 0x26, 0x28, 0x26, 0x29, 0xCA, 0xD0, 0xF8, 0xC8, 0xD0, 0xE0, 0xC4,
+    0x2A, 0xF0, 0x97, 0x24, 0x2B, 0x50, 0x10, 0xA5, 0x28, 0x81, 0x26, 0xE6, 0x26, 0xD0, 0xB5, 0xE6,
+    0x27, 0x4C, 0x44, 0xFF, 0x6C, 0x24, 0x00, 0x30, 0x2B, 0xA2, 0x02, 0xB5, 0x27, 0x95, 0x25, 0x95,
+    0x23, 0xCA, 0xD0, 0xF7, 0xD0, 0x14, 0xA9, 0x8D, 0x20, 0xEF, 0xFF, 0xA5, 0x25, 0x20, 0xDC, 0xFF,
+    0xA5, 0x24, 0x20, 0xDC, 0xFF, 0xA9, 0xBA, 0x20, 0xEF, 0xFF, 0xA9, 0xA0, 0x20, 0xEF, 0xFF, 0xA1,
+    0x24, 0x20, 0xDC, 0xFF, 0x86, 0x2B, 0xA5, 0x24, 0xC5, 0x28, 0xA5, 0x25, 0xE5, 0x29, 0xB0, 0xC1,
+    0xE6, 0x24, 0xD0, 0x02, 0xE6, 0x25, 0xA5, 0x24, 0x29, 0x07, 0x10, 0xC8, 0x48, 0x4A, 0x4A, 0x4A,
+    0x4A, 0x20, 0xE5, 0xFF, 0x68, 0x29, 0x0F, 0x09, 0xB0, 0xC9, 0xBA, 0x90, 0x02, 0x69, 0x06, 0x2C,
+    0x12, 0xD0, 0x30, 0xFB, 0x8D, 0x12, 0xD0, 0x60, 0x00, 0x00, 0x00, 0x0F, 0x00, 0xFF, 0x00, 0x00,
+];
+
+/*
+    The Apple I's PIA. Keys come in through a channel (from the terminal, or any other source) and are turned
+    into what the Apple I keyboard would send: uppercase, with bit 7 set, Enter as a carriage return.
+    The Apple I had no lowercase and no real backspace, WozMon uses _ as a rubout.
+
+    Writing to DSP only prints once the control register selects the data register (bit 2 of DSPCR),
+    before that it's the data direction register, which is how WozMon's first write of 0x7F gets swallowed.
+    The display is never busy, so bit 7 of DSP always reads as clear
+*/
+pub struct Pia {
+    keyboard: Receiver<u8>,
+    output: Box<dyn Write>,
+    key: u8,
+    key_ready: bool,
+    keyboard_control: u8,
+    display_control: u8,
+    input_closed: Arc<AtomicBool>, // Set once the keyboard channel is closed and every key has been read
+}
+
+impl Pia {
+    pub fn new(keyboard: Receiver<u8>, output: Box<dyn Write>) -> Self {
+        Pia {
+            keyboard,
+            output,
+            key: 0,
+            key_ready: false,
+            keyboard_control: 0,
+            display_control: 0,
+            input_closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn input_closed(&self) -> Arc<AtomicBool> {
+        self.input_closed.clone()
+    }
+
+    // Take the next key off the channel if there isn't one waiting already
+    fn poll_keyboard(&mut self) {
+        while !self.key_ready {
+            match self.keyboard.try_recv() {
+                Ok(byte) => {
+                    if let Some(key) = apple1_key(byte) {
+                        self.key = key;
+                        self.key_ready = true;
+                    }
+                }
+
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.input_closed.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// What the Apple I keyboard sends for a byte typed on ours, if it has that key at all
+fn apple1_key(byte: u8) -> Option<u8> {
+    match byte {
+        b'\n' | b'\r' => Some(b'\r'),
+        0x08 | 0x7F => Some(b'_'),
+        b'a'..=b'z' => Some(byte.to_ascii_uppercase()),
+        0x00..=0x7F => Some(byte),
+        _ => None,
+    }
+}
+
+impl Device for Pia {
+    fn maps(&self, address: u16) -> bool {
+        (KBD..=DSPCR).contains(&address)
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            KBD => {
+                self.key_ready = false;
+                self.key | 0x80
+            }
+
+            KBDCR => {
+                self.poll_keyboard();
+                self.peek(address)
+            }
+
+            _ => self.peek(address),
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            KBDCR => self.keyboard_control = data,
+            DSPCR => self.display_control = data,
+
+            DSP if self.display_control & 0b0000_0100 != 0 => {
+                // Raw mode terminals need the \r as well, and it doesn't hurt anywhere else
+                let _ = match data & 0x7F {
+                    b'\r' => self.output.write_all(b"\r\n"),
+                    ch @ 0x20..=0x7E => self.output.write_all(&[ch]),
+                    _ => Ok(()),
+                };
+                let _ = self.output.flush();
+            }
+
+            _ => {}
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            KBD => self.key | 0x80,
+            KBDCR => (self.keyboard_control & 0x7F) | if self.key_ready { 0x80 } else { 0 },
+            DSPCR => self.display_control,
+            _ => 0,
+        }
+    }
+}
+
+/*
+    An Apple I with WozMon in ROM, ready to go after reset. RAM is 0x0000 - 0x0FFF, everything
+    else outside the PIA and the ROM reads as 0. Also returns the flag that's set once the keyboard runs out of keys
+*/
+pub fn apple1(keyboard: Receiver<u8>, output: Box<dyn Write>) -> (CPU, Arc<AtomicBool>) {
+    let pia = Pia::new(keyboard, output);
+    let input_closed = pia.input_closed();
+
+    let mut cpu = CPU::new();
+    cpu.attach(Box::new(pia));
+    cpu.attach(Box::new(Rom::new(WOZMON_START, &WOZMON)));
+    cpu.attach(Box::new(Unmapped::new(RAM_END + 1, WOZMON_START - 1)));
+    cpu.reset();
+
+    (cpu, input_closed)
+}
+
+/*
+    Run the Apple I in the terminal. Keys are read one at a time in raw mode, so WozMon does all the echoing
+    just like on the real machine. Ctrl+C quits (Esc is a key WozMon uses). When stdin isn't a terminal,
+    everything piped in is typed and the machine stops once it has all been read. --profile, --coverage,
+    --stack-check, --dump and --diff work here too
+*/
+pub fn run_apple1(options: &Options) -> Result<(), String> {
+    let interactive = io::stdin().is_terminal();
+
+    let _raw_mode = if interactive {
+        Some(RawMode::enter().map_err(|e| format!("Failed to set up the terminal: {}", e))?)
+    } else {
+        None
+    };
+
+    let keyboard = if interactive { terminal_reader() } else { stdin_reader() };
+    let (mut cpu, input_closed) = apple1(keyboard, Box::new(io::stdout()));
+    options.configure(&mut cpu);
+    let before = dump::snapshots(&cpu, &options.diffs); // For --diff
+
+    if interactive {
+        print!("Apple I with WozMon, Ctrl+C quits\r\n");
+    }
+
+    let mut count: u64 = 0;
+    while !input_closed.load(Ordering::Relaxed) && cpu.step() {
+        count += 1;
+        if count.is_multiple_of(SPEED_CHECK) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    drop(_raw_mode); // So the reports below come out on lines of their own
+    if let Some(error) = cpu.calls.error.take() {
+        eprintln!("{}", error);
+    }
+    cli::finish(&cpu, options, &before);
+    Ok(())
+}
+
+// Raw mode without the alternate screen, the Apple I output should stay in the scrollback afterwards
+struct RawMode;
+
+impl RawMode {
+    fn enter() -> io::Result<RawMode> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        println!();
+    }
+}
+
+// Keys from the terminal, one at a time. Ctrl+C closes the channel, which stops the machine
+fn terminal_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event {
+                if kind == KeyEventKind::Release {
+                    continue;
+                }
+
+                let byte = match code {
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break,
+                    KeyCode::Char(ch) if ch.is_ascii() => ch as u8,
+                    KeyCode::Enter => b'\r',
+                    KeyCode::Backspace => 0x08,
+                    KeyCode::Esc => 0x1B,
+                    _ => continue,
+                };
+
+                if sender.send(byte).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    receiver
+}
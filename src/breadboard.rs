@@ -0,0 +1,216 @@
+use crate::capture::save_image;
+use crate::call_stack::StackCheck;
+use crate::cli::{self, Options};
+use crate::dump;
+use crate::devices::Rom;
+use crate::lcd::{Lcd, LcdPins, Wiring, ROWS, COLUMNS, DOTS_W, DOTS_H};
+use crate::processor::CPU;
+use crate::tui::RawTerminal;
+use crate::via::Via;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::{cursor, queue};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "sdl")]
+use sdl2::{event::Event as SdlEvent, keyboard::Keycode, pixels::PixelFormatEnum};
+
+pub const VIA_ADDRESS: u16 = 0x6000;
+pub const ROM_START: u16 = 0x8000;
+pub const ROM_SIZE: usize = 0x8000;
+
+const SPEED_CHECK: u64 = 1000; // Sleep for a millisecond every 1000 instructions, about as fast as the 1 MHz clock
+const LCD_SCALE: u32 = 6; // Size of the window, and of screenshots unless --scale says otherwise
+
+/*
+    Read a ROM image the way an EEPROM programmer would: a raw binary, byte 0 goes at 0x8000.
+    The assemblers used in the breadboard tutorials output exactly 32K with the vectors at the end.
+    A shorter image is padded with 0xFF, like a blank EEPROM
+*/
+pub fn load_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let mut rom = fs::read(path).map_err(|e| format!("Failed to read ROM {}: {}", path.display(), e))?;
+    if rom.len() > ROM_SIZE {
+        return Err(format!("ROM {} is {} bytes, it has to fit in 32K", path.display(), rom.len()));
+    }
+
+    rom.resize(ROM_SIZE, 0xFF);
+    Ok(rom)
+}
+
+/*
+    The breadboard computer: 32K of RAM at 0x0000 - 0x7FFF, a 6522 VIA at 0x6000 (in front of the RAM),
+    and 32K of ROM at 0x8000 - 0xFFFF. The LCD hangs off the VIA's ports and is handed back so it can be drawn
+*/
+pub fn breadboard(rom: &[u8], wiring: Wiring) -> (CPU, Rc<RefCell<Lcd>>) {
+    let lcd = Rc::new(RefCell::new(Lcd::new()));
+
+    let mut via = Via::new(VIA_ADDRESS);
+    via.connect(Box::new(LcdPins::new(lcd.clone(), wiring)));
+
+    let mut cpu = CPU::new();
+    cpu.attach(Box::new(via));
+    cpu.attach(Box::new(Rom::new(ROM_START, rom)));
+    cpu.reset();
+
+    (cpu, lcd)
+}
+
+/*
+    Runs the breadboard computer with the ROM from --rom, the LCD is shown in an SDL window or in the terminal with --tui.
+    With --screenshot, the LCD is saved as a PNG once the run is over. --profile, --coverage, --stack-check, --dump
+    and --diff work here too (in the terminal, only stack errors are checked for)
+*/
+pub fn run_breadboard(options: &Options) -> Result<(), String> {
+    let path = options.rom.as_ref().ok_or("The breadboard computer needs a ROM image, give it one with --rom")?;
+    let rom = load_rom(path)?;
+    let (mut cpu, lcd) = breadboard(&rom, options.lcd_wiring);
+    options.configure(&mut cpu);
+    let before = dump::snapshots(&cpu, &options.diffs); // For --diff
+
+    #[cfg(feature = "sdl")]
+    let result = if options.tui { run_terminal(&mut cpu, lcd.clone()) } else { run_sdl(&mut cpu, lcd.clone()) };
+    #[cfg(not(feature = "sdl"))]
+    let result = run_terminal(&mut cpu, lcd.clone());
+    result?;
+
+    if let Some(error) = cpu.calls.error.take() {
+        eprintln!("{}", error);
+    }
+    cli::finish(&cpu, options, &before);
+
+    if let Some(path) = &options.screenshot {
+        let scale = options.scale.unwrap_or(LCD_SCALE);
+        save_image(&lcd.borrow().render(), DOTS_W as u32, DOTS_H as u32, scale, path)?;
+        println!("Screenshot saved to {}", path.display());
+    }
+
+    Ok(())
+}
+
+/*
+    Draws the LCD in the terminal, redrawn whenever it changes. Esc (or Ctrl+C) quits.
+    Most firmware never ends (it loops forever once the text is up), if it does hit a BRK the LCD stays up until a key is pressed
+*/
+fn run_terminal(cpu: &mut CPU, lcd: Rc<RefCell<Lcd>>) -> Result<(), String> {
+    // Warnings would scribble over the LCD, errors are shown once the terminal is back
+    if cpu.calls.check == StackCheck::Warn {
+        cpu.calls.check = StackCheck::Off;
+    }
+
+    let _terminal = RawTerminal::enter().map_err(|e| format!("Failed to set up the terminal: {}", e))?;
+    let mut out = io::stdout();
+    let mut count: u64 = 0;
+
+    loop {
+        let running = cpu.step();
+
+        if lcd.borrow_mut().take_changed() || !running {
+            draw(&mut out, &lcd.borrow(), running).map_err(|e| e.to_string())?;
+        }
+
+        if !running {
+            wait_for_key().map_err(|e| e.to_string())?;
+            return Ok(());
+        }
+
+        count += 1;
+        if count.is_multiple_of(SPEED_CHECK) {
+            if quit_pressed().map_err(|e| e.to_string())? {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+fn draw<T: Write>(out: &mut T, lcd: &Lcd, running: bool) -> io::Result<()> {
+    let border = "─".repeat(COLUMNS);
+    queue!(out, cursor::MoveTo(0, 0), Print(format!("┌{}┐", border)))?;
+    for row in 0..ROWS {
+        queue!(out, cursor::MoveTo(0, row as u16 + 1), Print(format!("│{}│", lcd.line(row))))?;
+    }
+    queue!(out, cursor::MoveTo(0, ROWS as u16 + 1), Print(format!("└{}┘", border)))?;
+
+    let status = if running { "Esc: quit                      " } else { "Program finished, press any key" };
+    queue!(out, cursor::MoveTo(0, ROWS as u16 + 3), Print(status))?;
+    out.flush()
+}
+
+fn quit_pressed() -> io::Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        if let Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) = event::read()? {
+            match code {
+                KeyCode::Esc => return Ok(true),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(true),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+fn wait_for_key() -> io::Result<()> {
+    loop {
+        if let Event::Key(KeyEvent { kind: KeyEventKind::Press, .. }) = event::read()? {
+            return Ok(());
+        }
+    }
+}
+
+// The LCD dot for dot in an SDL window, until the window is closed or Esc is pressed
+#[cfg(feature = "sdl")]
+fn run_sdl(cpu: &mut CPU, lcd: Rc<RefCell<Lcd>>) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let window = video_subsystem
+        .window("6502 Breadboard", DOTS_W as u32 * LCD_SCALE, DOTS_H as u32 * LCD_SCALE)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let mut event_pump = sdl_context.event_pump()?;
+    canvas.set_scale(LCD_SCALE as f32, LCD_SCALE as f32)?;
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator.create_texture_target(PixelFormatEnum::RGB24, DOTS_W as u32, DOTS_H as u32)
+        .map_err(|e| e.to_string())?;
+
+    let mut running = true;
+    let mut count: u64 = 0;
+
+    loop {
+        // Once the program is over, just keep the window up
+        if running {
+            running = cpu.step();
+        } else {
+            thread::sleep(Duration::from_millis(16));
+        }
+
+        if lcd.borrow_mut().take_changed() {
+            texture.update(None, &lcd.borrow().render(), DOTS_W * 3).map_err(|e| e.to_string())?;
+            canvas.copy(&texture, None, None)?;
+            canvas.present();
+        }
+
+        count += 1;
+        if count.is_multiple_of(SPEED_CHECK) || !running {
+            for event in event_pump.poll_iter() {
+                match event {
+                    SdlEvent::Quit { .. } | SdlEvent::KeyDown { keycode: Some(Keycode::Escape), .. } => return Ok(()),
+                    _ => {}
+                }
+            }
+
+            if running {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
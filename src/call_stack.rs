@@ -0,0 +1,115 @@
+use std::fmt;
+
+const MAX_FRAMES: usize = 256; // More than the 6502 stack can hold, for programs that JSR and never return
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameKind {
+    Subroutine, // JSR, ended by RTS
+    Interrupt,  // IRQ or NMI, ended by RTI
+}
+
+// One call that hasn't returned yet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub caller: u16,         // The JSR, or where the program was when the interrupt came in
+    pub target: u16,         // The subroutine or interrupt handler
+    pub return_address: u16, // What was pushed: the JSR's last byte (RTS adds 1), or where the interrupt returns to
+    pub stack_pointer: u8,   // The stack pointer once everything was pushed, which is where it should be again at the return
+}
+
+// What to do when something's wrong with the stack (--stack-check)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StackCheck {
+    #[default]
+    Off,
+    Warn,  // Print a warning and keep going
+    Error, // Stop the program
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    Overflow,  // Pushed past $0100, the stack pointer wrapped around to $FF
+    Underflow, // Pulled past $01FF, the stack pointer wrapped around to $00
+    Unmatched(FrameKind), // An RTS or RTI with no JSR or interrupt to return from
+    Manipulated { frame: Frame, address: u16, stack_pointer: u8 }, // Returned somewhere else, or with the stack moved
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Overflow => write!(f, "stack overflow, pushed past $0100 and wrapped around to $01FF"),
+            Problem::Underflow => write!(f, "stack underflow, pulled past $01FF and wrapped around to $0100"),
+            Problem::Unmatched(FrameKind::Subroutine) => write!(f, "RTS without a matching JSR"),
+            Problem::Unmatched(FrameKind::Interrupt) => write!(f, "RTI without an interrupt to return from"),
+            Problem::Manipulated { frame, address, .. } if *address != frame.return_address => write!(f,
+                "return address changed, the call at ${:04X} pushed ${:04X} but ${:04X} was pulled", frame.caller, frame.return_address, address),
+            Problem::Manipulated { frame, stack_pointer, .. } => write!(f,
+                "stack moved by {} bytes since the call at ${:04X}", *stack_pointer as i16 - frame.stack_pointer as i16, frame.caller),
+        }
+    }
+}
+
+/*
+    A shadow copy of the calls the program is in the middle of. Every JSR and interrupt adds a frame and every
+    RTS and RTI takes one away, which is what the debugger's backtrace shows.
+
+    Since the real return addresses are on the stack, they can be compared: an RTS that pulls something other
+    than what its JSR pushed means the program changed the return address (or pushed its own, like jump tables
+    that PHA an address and RTS to it). Those, returns with nothing to return from and the stack pointer
+    wrapping around are all reported if --stack-check asks for it
+*/
+#[derive(Default)]
+pub struct CallStack {
+    pub frames: Vec<Frame>,
+    pub check: StackCheck,
+    pub error: Option<String>, // With StackCheck::Error, what stopped the program. Whoever is running it takes it
+}
+
+impl CallStack {
+    pub fn call(&mut self, frame: Frame) {
+        // Frames at or below the new one were abandoned when the stack pointer went back up past them
+        while self.frames.last().is_some_and(|top| top.stack_pointer <= frame.stack_pointer) {
+            self.frames.pop();
+        }
+
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // An RTS or RTI pulled this address, with the stack pointer where it was before pulling anything
+    pub fn ret(&mut self, kind: FrameKind, address: u16, stack_pointer: u8) -> Option<Problem> {
+        let matches = |frame: &Frame| frame.kind == kind && frame.return_address == address && frame.stack_pointer == stack_pointer;
+
+        // A return to an outer call (like an error handler unwinding) drops everything in between
+        if let Some(index) = self.frames.iter().rposition(matches) {
+            let top = self.frames.len() - 1;
+            let frame = self.frames[top];
+            self.frames.truncate(index);
+            return if index == top { None } else { Some(Problem::Manipulated { frame, address, stack_pointer }) };
+        }
+
+        match self.frames.last() {
+            Some(top) if top.kind == kind => {
+                let frame = self.frames.pop().unwrap();
+                Some(Problem::Manipulated { frame, address, stack_pointer })
+            }
+            _ => Some(Problem::Unmatched(kind)),
+        }
+    }
+
+    // Warn or stop, depending on the check. At is the instruction that caused it
+    pub fn report(&mut self, at: u16, problem: Problem) {
+        match self.check {
+            StackCheck::Off => {}
+            StackCheck::Warn => eprintln!("Stack warning at ${:04X}: {}", at, problem),
+            StackCheck::Error => {
+                if self.error.is_none() {
+                    self.error = Some(format!("Stack error at ${:04X}: {}", at, problem));
+                }
+            }
+        }
+    }
+}
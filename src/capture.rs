@@ -0,0 +1,197 @@
+use crate::cli::Options;
+use crate::screen::{W, H, FRAME_SIZE, PALETTE};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/*
+    Screenshots and recordings of the 32 x 32 screen. Everything works straight from an RGB frame
+    (the same buffer snake hands to SDL), so it doesn't need a window and also works while replaying a movie.
+
+    Pixels are scaled up by repeating them, a scale of 20 gives the same 640 x 640 picture as the snake window
+*/
+
+// Blow the frame up so that every pixel becomes a scale x scale square
+fn scale_frame(frame: &[u8], frame_width: u32, frame_height: u32, scale: u32) -> Vec<u8> {
+    let width = (frame_width * scale) as usize;
+    let height = (frame_height * scale) as usize;
+    let mut scaled = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        for x in 0..width {
+            let idx = ((y / scale as usize) * frame_width as usize + (x / scale as usize)) * 3;
+            scaled.extend_from_slice(&frame[idx..idx + 3]);
+        }
+    }
+
+    scaled
+}
+
+// Dump the frame into a PNG file
+pub fn save_png(frame: &[u8; FRAME_SIZE], scale: u32, path: &Path) -> Result<(), String> {
+    save_image(frame, W, H, scale, path)
+}
+
+// Same as save_png, for RGB images of any size (the LCD of the breadboard computer, for example)
+pub fn save_image(frame: &[u8], width: u32, height: u32, scale: u32, path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width * scale, height * scale);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| format!("Failed to write PNG: {}", e))?;
+    writer.write_image_data(&scale_frame(frame, width, height, scale)).map_err(|e| format!("Failed to write PNG: {}", e))?;
+
+    Ok(())
+}
+
+/*
+    Records a range of frames, either into an animated GIF (if the path ends in .gif)
+    or as a sequence of raw RGB files (frame_00000.rgb, frame_00001.rgb...) in the directory at the path.
+    Raw frames are never scaled, they are exactly 32 x 32 x 3 bytes
+*/
+pub struct Recorder {
+    output: Output,
+    scale: u32,
+    start: u64, // First and last frame to record (inclusive)
+    end: u64,
+    frames_written: u64,
+}
+
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Raw(PathBuf),
+}
+
+impl Recorder {
+    pub fn new(path: &Path, scale: u32, start: u64, end: u64) -> Result<Recorder, String> {
+        let output = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif")) {
+            let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+            // Every frame uses the same handful of colors, so they all share one global palette
+            let palette: Vec<u8> = PALETTE.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+            let mut encoder = gif::Encoder::new(BufWriter::new(file), (W * scale) as u16, (H * scale) as u16, &palette)
+                .map_err(|e| format!("Failed to write GIF: {}", e))?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| format!("Failed to write GIF: {}", e))?;
+
+            Output::Gif(encoder)
+        } else {
+            fs::create_dir_all(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            Output::Raw(path.to_path_buf())
+        };
+
+        Ok(Recorder {
+            output,
+            scale,
+            start,
+            end,
+            frames_written: 0,
+        })
+    }
+
+    // Call this whenever the screen changes, frames outside of the range are ignored
+    pub fn capture(&mut self, frame_number: u64, frame: &[u8; FRAME_SIZE]) -> Result<(), String> {
+        if frame_number < self.start || frame_number > self.end {
+            return Ok(());
+        }
+
+        match &mut self.output {
+            Output::Gif(encoder) => {
+                // Turn each RGB pixel back into its palette index
+                let pixels: Vec<u8> = scale_frame(frame, W, H, self.scale)
+                    .chunks(3)
+                    .map(|rgb| PALETTE.iter().position(|&(r, g, b)| rgb == [r, g, b]).unwrap_or(0) as u8)
+                    .collect();
+
+                let gif_frame = gif::Frame {
+                    width: (W * self.scale) as u16,
+                    height: (H * self.scale) as u16,
+                    buffer: pixels.into(),
+                    delay: 5, // In hundredths of a second
+                    ..gif::Frame::default()
+                };
+
+                encoder.write_frame(&gif_frame).map_err(|e| format!("Failed to write GIF: {}", e))?;
+            }
+
+            Output::Raw(directory) => {
+                let path = directory.join(format!("frame_{:05}.rgb", self.frames_written));
+                fs::write(&path, &frame[..]).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            }
+        }
+
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
+    }
+}
+
+/*
+    Everything the command line asked to capture for one run: the recording (--capture), the final screenshot
+    (--screenshot) and any screenshots taken with the hotkey. Front-ends feed it every frame that changed
+*/
+pub struct Capture {
+    recorder: Option<Recorder>,
+    screenshot: Option<PathBuf>,
+    scale: u32,
+    screenshots_taken: u32,
+}
+
+impl Capture {
+    pub fn new(options: &Options) -> Result<Capture, String> {
+        let scale = options.scale();
+        let recorder = match &options.capture {
+            Some(path) => {
+                let (start, end) = options.capture_frames.unwrap_or((0, u64::MAX));
+                Some(Recorder::new(path, scale, start, end)?)
+            }
+            None => None,
+        };
+
+        Ok(Capture {
+            recorder,
+            screenshot: options.screenshot.clone(),
+            scale,
+            screenshots_taken: 0,
+        })
+    }
+
+    pub fn frame_changed(&mut self, frame_number: u64, frame: &[u8; FRAME_SIZE]) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            // Stop recording rather than stopping the program
+            if let Err(e) = recorder.capture(frame_number, frame) {
+                eprintln!("{}", e);
+                self.recorder = None;
+            }
+        }
+    }
+
+    // Hotkey screenshots go into the current directory as screenshot_0.png, screenshot_1.png...
+    pub fn take_screenshot(&mut self, frame: &[u8; FRAME_SIZE]) {
+        let path = PathBuf::from(format!("screenshot_{}.png", self.screenshots_taken));
+        match save_png(frame, self.scale, &path) {
+            Ok(()) => println!("Screenshot saved to {}", path.display()),
+            Err(e) => eprintln!("{}", e),
+        }
+
+        self.screenshots_taken += 1;
+    }
+
+    // The run is over, save the last frame if --screenshot was given
+    pub fn finish(&mut self, frame: &[u8; FRAME_SIZE]) {
+        if let Some(path) = &self.screenshot {
+            match save_png(frame, self.scale, path) {
+                Ok(()) => println!("Screenshot saved to {}", path.display()),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        if let Some(recorder) = &self.recorder {
+            println!("Recorded {} frames", recorder.frames_written());
+        }
+    }
+}
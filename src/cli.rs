@@ -0,0 +1,407 @@
+use crate::rng::RandomDevice;
+use crate::snake::SCALE;
+use crate::console::{CONSOLE_OUTPUT, CONSOLE_INPUT};
+use crate::acia::Backend;
+use crate::lcd::Wiring;
+use crate::profiler::Profiler;
+use crate::coverage::Coverage;
+use crate::history::{History, DEFAULT_CAPACITY};
+use crate::call_stack::StackCheck;
+use crate::dump::{self, Snapshot};
+use crate::processor::CPU;
+use crate::{coverage, profiler};
+use std::path::PathBuf;
+
+/*
+    Command line options. Everything here is optional, running the program without any
+    arguments still brings up the menu. Giving it a script runs that script straight away. For example:
+
+    astrobyte script.txt
+    astrobyte hello.txt --console --no-trace
+    astrobyte game.prg
+    astrobyte monitor.hex --console
+    astrobyte code.bin --origin C000
+    astrobyte game.prg --symbols game.lbl --break init
+    astrobyte script.txt --debug --history 1000000
+    astrobyte game.prg --stack-check error
+    astrobyte script.txt --no-trace --dump 0000-01FF --diff 0200-05FF
+    astrobyte script.txt --no-trace --no-dummy-accesses
+    astrobyte test scripts/
+    astrobyte script.txt --gdb 1234 --no-trace
+    astrobyte hello.txt --console F001,F004
+    astrobyte timer.txt --via 6000
+    astrobyte monitor.txt --acia 8000 tcp:6551
+    astrobyte --seed 1234 --full-range
+    astrobyte script.txt --random
+    astrobyte --record run.movie
+    astrobyte --replay run.movie
+    astrobyte --replay run.movie --capture run.gif --capture-frames 1000-20000 --scale 10
+    astrobyte --replay run.movie --profile --profile-folded snake.folded
+    astrobyte test.bin --origin 8000 --coverage test.cdl --coverage-listing test.lst
+    astrobyte --screenshot last.png
+    astrobyte --tui
+    astrobyte --machine apple1
+    astrobyte --machine breadboard --rom a.out
+    astrobyte --machine breadboard --rom lcd4.bin --lcd-wiring 4bit --tui
+*/
+#[derive(Default)]
+pub struct Options {
+    pub random: bool, // Attach the random byte device at 0xFE to programs too, not just snake (--seed and --full-range imply it)
+    pub seed: Option<u64>, // Seed for the random byte device at 0xFE, a random one is picked if not given
+    pub full_random_range: bool, // Random bytes go from 0 - 255 instead of 1 - 15
+    pub record: Option<PathBuf>, // Record snake's seed and key presses into this movie file
+    pub replay: Option<PathBuf>, // Replay this movie file without a window and check that it ends the same way
+    pub screenshot: Option<PathBuf>, // Save the last frame into this PNG file when the run ends
+    pub capture: Option<PathBuf>, // Record frames into an animated GIF (.gif) or a directory of raw RGB frames
+    pub capture_frames: Option<(u64, u64)>, // Only record frames in this range (inclusive)
+    pub scale: Option<u32>, // How much screenshots and GIFs are scaled up
+    pub tui: bool, // Draw the screen in the terminal instead of an SDL window
+    pub program: Option<PathBuf>, // Program to run right away (script, raw binary, Intel HEX, S-record or .prg), instead of showing the menu
+    pub origin: Option<u16>, // Where raw binaries are loaded, 0x0600 if not given
+    pub console: Option<(u16, u16)>, // Character console, (output address, input address)
+    pub no_trace: bool, // Don't print the registers after every instruction
+    pub no_dummy_accesses: bool, // Skip the 6502's extra reads and writes (dummy reads on indexing, the old value written back by INC and friends)
+    pub symbols: Vec<PathBuf>, // Label files (VICE .lbl, ld65 .dbg or label = $addr lists) for naming addresses
+    pub debug: bool, // Run the program in the command-line debugger
+    pub breakpoints: Vec<String>, // Addresses or labels to stop at in the debugger, resolved once the symbols are loaded
+    pub history: Option<usize>, // How many instructions the debugger and the SDL window keep for going backwards
+    pub stack_check: StackCheck, // Warn about (or stop at) stack wraparound, unmatched returns and changed return addresses
+    pub gdb: Option<u16>, // Wait for GDB to connect on this port and let it run the program
+    pub profile: bool, // Count hits and cycles per address and subroutine, and print the hot spots when the run ends
+    pub profile_folded: Option<PathBuf>, // Also save the call stacks in the folded format for flame graph tools
+    pub coverage: Option<PathBuf>, // Save which bytes were run, read and written into this file, one byte of flags per address
+    pub coverage_listing: Option<PathBuf>, // Save a disassembly of everything that was run (and the data that was touched) into this file
+    pub dumps: Vec<(u16, u16)>, // Memory ranges to print as hex and ASCII when the run is over
+    pub diffs: Vec<(u16, u16)>, // Memory ranges to compare before and after the run
+    pub via: Option<u16>, // Attach a 6522 VIA at this address
+    pub acia: Option<(u16, Backend)>, // Attach a 6551 ACIA at this address, connected to the terminal, a TCP port or pipes
+    pub machine: Option<Machine>, // Run one of the built-in machines instead of a script or snake
+    pub rom: Option<PathBuf>, // ROM image for the machine (a raw binary)
+    pub lcd_wiring: Wiring, // How the breadboard computer's LCD is wired to the VIA
+    pub test: Option<PathBuf>, // Run the //! expect checks in every script in this directory (astrobyte test DIR)
+}
+
+// Machines that come with their own memory map, devices and ROM
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Machine {
+    Apple1, // Apple I running WozMon
+    Breadboard, // Ben Eater style breadboard computer with a 6522 and an LCD
+}
+
+impl Options {
+    // Arguments should not include the program name
+    pub fn parse<I>(args: I) -> Result<Options, String> where I: IntoIterator<Item = String>, {
+        let mut options = Options::default();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    let value = args.next().ok_or("--seed needs a number after it")?;
+                    let seed = parse_number(&value).ok_or(format!("Invalid seed: {}", value))?;
+                    options.seed = Some(seed);
+                    options.random = true;
+                }
+
+                "--full-range" => {
+                    options.full_random_range = true;
+                    options.random = true;
+                }
+
+                "--random" => options.random = true,
+
+                "--record" => {
+                    let path = args.next().ok_or("--record needs a file name after it")?;
+                    options.record = Some(PathBuf::from(path));
+                }
+
+                "--replay" => {
+                    let path = args.next().ok_or("--replay needs a file name after it")?;
+                    options.replay = Some(PathBuf::from(path));
+                }
+
+                "--screenshot" => {
+                    let path = args.next().ok_or("--screenshot needs a file name after it")?;
+                    options.screenshot = Some(PathBuf::from(path));
+                }
+
+                "--capture" => {
+                    let path = args.next().ok_or("--capture needs a file or directory name after it")?;
+                    options.capture = Some(PathBuf::from(path));
+                }
+
+                "--capture-frames" => {
+                    let value = args.next().ok_or("--capture-frames needs a range like 100-200 after it")?;
+                    let range = parse_range(&value).ok_or(format!("Invalid frame range: {}", value))?;
+                    options.capture_frames = Some(range);
+                }
+
+                "--scale" => {
+                    let value = args.next().ok_or("--scale needs a number after it")?;
+                    let scale = parse_number(&value).filter(|scale| (1..=64).contains(scale))
+                        .ok_or(format!("Invalid scale (should be 1 to 64): {}", value))?;
+                    options.scale = Some(scale as u32);
+                }
+
+                "--tui" => options.tui = true,
+
+                // The addresses are optional, the defaults are the Kowalski simulator's
+                "--console" => {
+                    let mut ports = (CONSOLE_OUTPUT, CONSOLE_INPUT);
+                    if let Some(value) = args.next_if(|value| !value.starts_with("--") && value.contains(',')) {
+                        ports = parse_address_pair(&value).ok_or(format!("Invalid console addresses: {}", value))?;
+                    }
+                    options.console = Some(ports);
+                }
+
+                "--no-trace" => options.no_trace = true,
+
+                "--no-dummy-accesses" => options.no_dummy_accesses = true,
+
+                "--origin" => {
+                    let value = args.next().ok_or("--origin needs an address after it")?;
+                    let origin = parse_address(&value).ok_or(format!("Invalid origin: {}", value))?;
+                    options.origin = Some(origin);
+                }
+
+                "--symbols" => {
+                    let path = args.next().ok_or("--symbols needs a file name after it")?;
+                    options.symbols.push(PathBuf::from(path));
+                }
+
+                "--debug" => options.debug = true,
+
+                // Setting a breakpoint means debugging
+                "--break" => {
+                    let value = args.next().ok_or("--break needs an address or label after it")?;
+                    options.breakpoints.push(value);
+                    options.debug = true;
+                }
+
+                "--history" => {
+                    let value = args.next().ok_or("--history needs a number of instructions after it")?;
+                    let count = parse_number(&value).filter(|count| *count > 0).ok_or(format!("Invalid history length: {}", value))?;
+                    options.history = Some(count as usize);
+                }
+
+                "--stack-check" => {
+                    let value = args.next().ok_or("--stack-check needs warn or error after it")?;
+                    options.stack_check = match value.as_str() {
+                        "warn" => StackCheck::Warn,
+                        "error" => StackCheck::Error,
+                        "off" => StackCheck::Off,
+                        _ => return Err(format!("Invalid stack check (should be warn, error or off): {}", value)),
+                    };
+                }
+
+                "--gdb" => {
+                    let value = args.next().ok_or("--gdb needs a port number after it")?;
+                    let port = value.parse().map_err(|_| format!("Invalid port: {}", value))?;
+                    options.gdb = Some(port);
+                }
+
+                "--profile" => options.profile = true,
+
+                "--profile-folded" => {
+                    let path = args.next().ok_or("--profile-folded needs a file name after it")?;
+                    options.profile_folded = Some(PathBuf::from(path));
+                    options.profile = true;
+                }
+
+                "--coverage" => {
+                    let path = args.next().ok_or("--coverage needs a file name after it")?;
+                    options.coverage = Some(PathBuf::from(path));
+                }
+
+                "--coverage-listing" => {
+                    let path = args.next().ok_or("--coverage-listing needs a file name after it")?;
+                    options.coverage_listing = Some(PathBuf::from(path));
+                }
+
+                "--dump" => {
+                    let value = args.next().ok_or("--dump needs an address range like 0000-01FF after it")?;
+                    let range = parse_address_range(&value).ok_or(format!("Invalid address range: {}", value))?;
+                    options.dumps.push(range);
+                }
+
+                "--diff" => {
+                    let value = args.next().ok_or("--diff needs an address range like 0000-01FF after it")?;
+                    let range = parse_address_range(&value).ok_or(format!("Invalid address range: {}", value))?;
+                    options.diffs.push(range);
+                }
+
+                "--via" => {
+                    let value = args.next().ok_or("--via needs an address after it")?;
+                    let address = parse_address(&value).filter(|address| *address <= 0xFFF0)
+                        .ok_or(format!("Invalid VIA address: {}", value))?;
+                    options.via = Some(address);
+                }
+
+                // The backend is optional, the ACIA talks to the terminal unless told otherwise
+                "--acia" => {
+                    let value = args.next().ok_or("--acia needs an address after it")?;
+                    let address = parse_address(&value).filter(|address| *address <= 0xFFFC)
+                        .ok_or(format!("Invalid ACIA address: {}", value))?;
+
+                    let mut backend = Backend::Terminal;
+                    if let Some(value) = args.next_if(|value| value == "terminal" || value.starts_with("tcp:") || value.starts_with("pipe:")) {
+                        backend = parse_backend(&value).ok_or(format!("Invalid ACIA backend (terminal, tcp:PORT or pipe:IN,OUT): {}", value))?;
+                    }
+                    options.acia = Some((address, backend));
+                }
+
+                "--machine" => {
+                    let name = args.next().ok_or("--machine needs a machine name after it (apple1 or breadboard)")?;
+                    let machine = match name.to_lowercase().as_str() {
+                        "apple1" => Machine::Apple1,
+                        "breadboard" => Machine::Breadboard,
+                        _ => return Err(format!("Unknown machine: {}", name)),
+                    };
+                    options.machine = Some(machine);
+                }
+
+                "--rom" => {
+                    let path = args.next().ok_or("--rom needs a file name after it")?;
+                    options.rom = Some(PathBuf::from(path));
+                }
+
+                "--lcd-wiring" => {
+                    let value = args.next().ok_or("--lcd-wiring needs 8bit or 4bit after it")?;
+                    options.lcd_wiring = match value.as_str() {
+                        "8bit" => Wiring::Parallel,
+                        "4bit" => Wiring::Nibble,
+                        _ => return Err(format!("Invalid LCD wiring (should be 8bit or 4bit): {}", value)),
+                    };
+                }
+
+                "test" if options.program.is_none() && options.test.is_none() => {
+                    let path = args.next().ok_or("test needs a directory or script after it")?;
+                    options.test = Some(PathBuf::from(path));
+                }
+
+                _ if !arg.starts_with("--") && options.program.is_none() => options.program = Some(PathBuf::from(arg)),
+
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+
+    // Same size as the snake window unless told otherwise
+    pub fn scale(&self) -> u32 {
+        self.scale.unwrap_or(SCALE as u32)
+    }
+
+    // A fresh profiler if the run should be profiled
+    pub fn profiler(&self) -> Option<Profiler> {
+        if self.profile {
+            Some(Profiler::new())
+        } else {
+            None
+        }
+    }
+
+    // A blank coverage map if one of the coverage files was asked for
+    pub fn coverage(&self) -> Option<Coverage> {
+        if self.coverage.is_some() || self.coverage_listing.is_some() {
+            Some(Coverage::new())
+        } else {
+            None
+        }
+    }
+
+    // An empty history, as long as --history asked for (or the default)
+    pub fn history(&self) -> History {
+        History::new(self.history.unwrap_or(DEFAULT_CAPACITY))
+    }
+
+    // The random byte device these options describe
+    pub fn random_device(&self) -> RandomDevice {
+        match self.seed {
+            Some(seed) => RandomDevice::new(seed, self.full_random_range),
+            None => RandomDevice::from_entropy(self.full_random_range),
+        }
+    }
+
+    // What the options ask of every CPU, whichever front-end or machine is running it
+    pub fn configure(&self, cpu: &mut CPU) {
+        cpu.profiler = self.profiler();
+        cpu.coverage = self.coverage();
+        cpu.calls.check = self.stack_check;
+        cpu.dummy_accesses = !self.no_dummy_accesses;
+    }
+
+    // Programs other than snake only get the random byte device if they asked for it, 0xFE is theirs otherwise
+    pub fn program_random_device(&self) -> Option<RandomDevice> {
+        self.random.then(|| self.random_device())
+    }
+}
+
+/*
+    Everything the options ask for once a run is over: the profile, the coverage files, and the memory dumps and
+    diffs (against before, the snapshots taken when the program was loaded). Every front-end ends with this
+*/
+pub fn finish(cpu: &CPU, options: &Options, before: &[Snapshot]) {
+    profiler::finish(cpu, options.profile_folded.as_deref());
+    coverage::finish(cpu, options.coverage.as_deref(), options.coverage_listing.as_deref());
+    dump::finish(cpu, &options.dumps, before);
+}
+
+// Numbers can be written in decimal, or in hex with a 0x or $ in front
+pub fn parse_number(text: &str) -> Option<u64> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+// A range of numbers written as START-END, both ends included
+pub fn parse_range(text: &str) -> Option<(u64, u64)> {
+    let (start, end) = text.split_once('-')?;
+    let start = parse_number(start.trim())?;
+    let end = parse_number(end.trim())?;
+
+    if start <= end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+// Addresses are written in hex, with or without a $ or 0x in front
+pub fn parse_address(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix('$')).unwrap_or(text);
+    u16::from_str_radix(hex, 16).ok()
+}
+
+// An address range like 0000-01FF (both ends included), or a single address
+pub fn parse_address_range(text: &str) -> Option<(u16, u16)> {
+    let (start, end) = text.split_once('-').unwrap_or((text, text));
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if start <= end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+// Two addresses separated by a comma, like F001,F004
+fn parse_address_pair(text: &str) -> Option<(u16, u16)> {
+    let (first, second) = text.split_once(',')?;
+    Some((parse_address(first)?, parse_address(second)?))
+}
+
+// Where an ACIA connects to: terminal, tcp:PORT or pipe:IN,OUT
+fn parse_backend(text: &str) -> Option<Backend> {
+    if text == "terminal" {
+        Some(Backend::Terminal)
+    } else if let Some(port) = text.strip_prefix("tcp:") {
+        port.parse().ok().map(Backend::Tcp)
+    } else {
+        let (input, output) = text.strip_prefix("pipe:")?.split_once(',')?;
+        Some(Backend::Pipe(PathBuf::from(input), PathBuf::from(output)))
+    }
+}
@@ -0,0 +1,82 @@
+use crate::devices::Device;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub const CONSOLE_OUTPUT: u16 = 0xF001; // Same addresses as the Kowalski simulator, so programs written for it work as-is
+pub const CONSOLE_INPUT: u16 = 0xF004;
+
+/*
+    A character console. Writing a byte to the output address prints it, and reading the input address
+    returns the next byte typed into stdin (or 0 if nothing is waiting), so programs never block on input.
+
+    Stdin is read on a separate thread, so bytes show up as soon as they are typed (after enter, on most terminals)
+*/
+pub struct ConsoleDevice {
+    output_address: u16,
+    input_address: u16,
+    input: Receiver<u8>,
+    output: Box<dyn Write>,
+}
+
+impl ConsoleDevice {
+    // Console hooked up to the real stdin and stdout
+    pub fn stdio(output_address: u16, input_address: u16) -> Self {
+        ConsoleDevice::new(output_address, input_address, stdin_reader(), Box::new(io::stdout()))
+    }
+
+    pub fn new(output_address: u16, input_address: u16, input: Receiver<u8>, output: Box<dyn Write>) -> Self {
+        ConsoleDevice {
+            output_address,
+            input_address,
+            input,
+            output,
+        }
+    }
+}
+
+// Every byte typed into stdin, read on its own thread. The channel closes when stdin does
+pub fn stdin_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            match byte {
+                Ok(byte) => {
+                    if sender.send(byte).is_err() {
+                        break; // Nobody is listening anymore
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    receiver
+}
+
+impl Device for ConsoleDevice {
+    fn maps(&self, address: u16) -> bool {
+        address == self.output_address || address == self.input_address
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        if address == self.input_address {
+            self.input.try_recv().unwrap_or(0)
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if address == self.output_address {
+            // Flush every character, programs usually print prompts without a newline
+            let _ = self.output.write_all(&[data]);
+            let _ = self.output.flush();
+        }
+    }
+
+    fn peek(&self, _address: u16) -> u8 {
+        0
+    }
+}
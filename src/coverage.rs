@@ -0,0 +1,131 @@
+use crate::disassembler::disassemble;
+use crate::processor::CPU;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// What happened to a byte, one bit each. A byte can have any mix of them (code that gets written to, for example)
+pub const EXECUTED: u8 = 0b0000_0001; // Fetched as an opcode
+pub const OPERAND: u8 = 0b0000_0010; // Fetched as part of an instruction, after the opcode
+pub const READ: u8 = 0b0000_0100; // Read as data by an instruction
+pub const WRITTEN: u8 = 0b0000_1000; // Written by an instruction
+
+const BYTES_PER_LINE: usize = 8; // Data bytes are listed 8 to a line
+
+/*
+    Which bytes of memory were run, read or written (--coverage), a bit like the code/data logs of NES emulators.
+    Only what instructions do is counted: the random byte the emulator writes into 0xFE after every instruction
+    and key presses written in by the front-ends don't show up.
+
+    The map is saved as 64K of flags, one byte per address. The listing disassembles everything that was run
+    and shows everything else that was touched as data, skipping memory nobody went near
+*/
+pub struct Coverage {
+    pub map: Vec<u8>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage { map: vec![0; 0x10000] }
+    }
+
+    pub fn mark(&mut self, address: u16, flag: u8) {
+        self.map[address as usize] |= flag;
+    }
+
+    // An instruction was fetched: the opcode and the bytes after it
+    pub fn instruction(&mut self, address: u16, length: u16) {
+        self.mark(address, EXECUTED);
+        for offset in 1..length {
+            self.mark(address.wrapping_add(offset), OPERAND);
+        }
+    }
+
+    // How many bytes have each flag
+    pub fn count(&self, flag: u8) -> usize {
+        self.map.iter().filter(|flags| *flags & flag != 0).count()
+    }
+
+    pub fn listing(&self, cpu: &CPU) -> String {
+        let mut listing = String::new();
+        let _ = writeln!(listing, "; {} opcodes, {} operand bytes, {} bytes read, {} bytes written", self.count(EXECUTED),
+            self.count(OPERAND), self.count(READ), self.count(WRITTEN));
+
+        let mut address = 0usize;
+        let mut gap = true;
+        while address < self.map.len() {
+            let flags = self.map[address];
+            if flags == 0 {
+                gap = true;
+                address += 1;
+                continue;
+            }
+
+            // A blank line wherever untouched memory was skipped
+            if gap {
+                listing.push('\n');
+                gap = false;
+            }
+
+            let length = if flags & EXECUTED != 0 {
+                disassemble(cpu, address as u16).1 as usize
+            } else {
+                // Data bytes with the same flags share a line
+                (address..self.map.len()).take(BYTES_PER_LINE).take_while(|next| self.map[*next] == flags).count()
+            }.min(self.map.len() - address);
+
+            if let Some(name) = cpu.symbols.name(address as u16) {
+                let _ = writeln!(listing, "{}:", name);
+            }
+
+            let bytes: Vec<String> = (address..address + length).map(|at| format!("{:02X}", cpu.peek_memory_u8(at as u16))).collect();
+            let text = if flags & EXECUTED != 0 {
+                disassemble(cpu, address as u16).0
+            } else {
+                format!(".byte {}", bytes.iter().map(|byte| format!("${}", byte)).collect::<Vec<String>>().join(", "))
+            };
+
+            let _ = writeln!(listing, "{:04X}  {:<9} {:<40} ; {}", address, bytes.iter().take(3).cloned().collect::<Vec<String>>().join(" "), text, describe(flags));
+            address += length;
+        }
+
+        listing
+    }
+
+    pub fn save_map(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, &self.map).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn save_listing(&self, cpu: &CPU, path: &Path) -> Result<(), String> {
+        fs::write(path, self.listing(cpu)).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+// The flags in words, for the listing
+fn describe(flags: u8) -> String {
+    let names = [(EXECUTED, "code"), (OPERAND, "operand"), (READ, "read"), (WRITTEN, "written")];
+    names.iter().filter(|(flag, _)| flags & flag != 0).map(|(_, name)| *name).collect::<Vec<&str>>().join(", ")
+}
+
+// Save the map and the listing if the CPU was keeping track
+pub fn finish(cpu: &CPU, map: Option<&Path>, listing: Option<&Path>) {
+    if let Some(coverage) = &cpu.coverage {
+        let saved = [
+            map.map(|path| (path, coverage.save_map(path))),
+            listing.map(|path| (path, coverage.save_listing(cpu, path))),
+        ];
+
+        for (path, result) in saved.into_iter().flatten() {
+            match result {
+                Ok(()) => println!("Coverage saved to {}", path.display()),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
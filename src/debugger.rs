@@ -0,0 +1,541 @@
+use crate::disassembler::disassemble;
+use crate::dump::{self, Snapshot};
+use crate::call_stack::FrameKind;
+use crate::expression::Expression;
+use crate::processor::CPU;
+use crate::watch::{self, Hit, Source};
+use std::io::{self, BufRead, Write};
+
+const LIST_LINES: usize = 10;
+const DUMP_BYTES: u16 = 128; // What dump shows when it's only given an address
+
+/*
+    A small command-line debugger (--debug). The program stops before its first instruction and waits for commands.
+    Addresses can be typed in hex ($0606 or 0606) or by label, if symbols were loaded with --symbols:
+
+    break init      stop when the program counter gets to init (break on its own lists the breakpoints)
+    watch $0200     stop after an instruction writes to $0200 (rwatch for reads, awatch for both)
+    delete init     remove the breakpoints at init (delete #2 removes breakpoint 2, delete on its own removes them all)
+    step 5          run 5 instructions (1 if no number is given)
+    continue        run until a breakpoint or the end of the program
+    back 5          step 5 instructions backwards (1 if no number is given)
+    reverse         run backwards until a breakpoint, a write to a watched address or the start of the history
+    regs            show the registers
+    backtrace       show the subroutines and interrupt handlers the program is in, innermost first
+    list init       disassemble 10 instructions from init (from the program counter if no address is given)
+    dump $0200      show memory in hex and ASCII, 128 bytes or a range like $0200-$02FF
+    snapshot        save all of memory, so that diff (diff $0200-$02FF for part of it) shows what changed since
+    quit            stop debugging
+
+    Most commands have a short form: b (break), w (watch), d (delete), s (step), c (continue), bs (back),
+    rc (reverse), r (regs), bt (backtrace), l (list), x (dump), h (help) and q (quit). rwatch, awatch, snapshot
+    and diff don't. Going backwards needs the CPU to keep a history (--history sets how long it is). Breakpoints
+    and watchpoints can cover a range of addresses, stop only when a condition holds and let a number of hits
+    go by first:
+
+    watch $0200-$02FF if A == $10 && [$02] & 4 after 3
+
+    Conditions are checked once the instruction has finished (see expression.rs for what they can contain).
+    Watchpoints say whether the access came from an instruction or from a device (the emulator or a front-end)
+*/
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    next_number: usize,
+    finished: bool, // The program ran into a BRK, so there's nothing left to run
+    undone: Vec<(u16, u8)>, // The writes the last step back put back, (address, old value)
+    snapshot: Option<Snapshot>, // All of memory, as it was when snapshot was typed
+}
+
+pub struct Breakpoint {
+    pub number: usize,
+    pub start: u16,
+    pub end: u16,
+    pub access: u8, // watch::EXECUTE for breakpoints, watch::READ and watch::WRITE for watchpoints
+    pub condition: Option<(String, Expression)>, // As typed, and parsed
+    pub after: u64, // How many hits to let go by before stopping
+    pub hits: u64,  // Times it was reached with the condition holding
+}
+
+impl Breakpoint {
+    fn covers(&self, address: u16, access: u8) -> bool {
+        self.access & access != 0 && (self.start..=self.end).contains(&address)
+    }
+
+    // Count a hit if the condition holds. Returns true if it's time to stop
+    fn hit(&mut self, cpu: &CPU) -> bool {
+        if let Some((_, condition)) = &self.condition {
+            if !condition.is_true(cpu) {
+                return false;
+            }
+        }
+
+        self.hits += 1;
+        self.hits > self.after
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger { breakpoints: Vec::new(), next_number: 1, finished: false, undone: Vec::new(), snapshot: None }
+    }
+
+    /*
+        Add a breakpoint (watch::EXECUTE) or watchpoint from text like  $0200-$02FF if A == 3 after 2
+        Only the address is needed, the range, condition and count are optional. Returns its number
+    */
+    pub fn add(&mut self, cpu: &mut CPU, access: u8, text: &str) -> Result<usize, String> {
+        let mut text = text.trim();
+
+        let mut after = 0;
+        if let Some((rest, count)) = text.rsplit_once(" after ") {
+            after = count.trim().parse().map_err(|_| format!("Invalid number of hits: {}", count.trim()))?;
+            text = rest.trim();
+        }
+
+        let (range, condition) = match text.split_once(" if ") {
+            Some((range, condition)) => {
+                let parsed = Expression::parse(condition.trim(), &cpu.symbols)?;
+                (range.trim(), Some((condition.trim().to_string(), parsed)))
+            }
+            None => (text, None),
+        };
+
+        let (start, end) = parse_range(cpu, range)?;
+
+        let number = self.next_number;
+        self.next_number += 1;
+        self.breakpoints.push(Breakpoint { number, start, end, access, condition, after, hits: 0 });
+        self.update_watches(cpu);
+        Ok(number)
+    }
+
+    // The CPU only needs to look out for the addresses the watchpoints cover
+    fn update_watches(&self, cpu: &mut CPU) {
+        cpu.watches.points.clear();
+        for breakpoint in &self.breakpoints {
+            if breakpoint.access & (watch::READ | watch::WRITE) != 0 {
+                cpu.watches.add(breakpoint.start, breakpoint.end, breakpoint.access);
+            }
+        }
+    }
+
+    // Read commands until quit or the end of the input
+    pub fn run<R: BufRead, W: Write>(&mut self, cpu: &mut CPU, input: R, output: &mut W) -> io::Result<()> {
+        self.show_location(cpu, output)?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(astrobyte) ")?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            if !self.command(cpu, &line, output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Run a single command. Returns false once it's time to quit
+    pub fn command<W: Write>(&mut self, cpu: &mut CPU, line: &str, output: &mut W) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let argument = words.next();
+        let rest = line.trim_start()[command.len()..].trim();
+
+        // Turn the argument into an address, complaining if it isn't one
+        let resolve = |cpu: &CPU, output: &mut W, text: &str| -> io::Result<Option<u16>> {
+            let address = cpu.symbols.resolve(text);
+            if address.is_none() {
+                writeln!(output, "Unknown address or label: {}", text)?;
+            }
+            Ok(address)
+        };
+
+        match command {
+            "b" | "break" | "w" | "watch" | "rwatch" | "awatch" if !rest.is_empty() => {
+                let access = match command {
+                    "b" | "break" => watch::EXECUTE,
+                    "rwatch" => watch::READ,
+                    "awatch" => watch::READ | watch::WRITE,
+                    _ => watch::WRITE,
+                };
+
+                match self.add(cpu, access, rest) {
+                    Ok(number) => {
+                        let breakpoint = self.breakpoints.iter().find(|breakpoint| breakpoint.number == number);
+                        writeln!(output, "{}", describe_breakpoint(cpu, breakpoint.unwrap()))?;
+                    }
+                    Err(e) => writeln!(output, "{}", e)?,
+                }
+            }
+
+            "b" | "break" | "w" | "watch" | "rwatch" | "awatch" => {
+                if self.breakpoints.is_empty() {
+                    writeln!(output, "No breakpoints")?;
+                }
+                for breakpoint in &self.breakpoints {
+                    writeln!(output, "{}", describe_breakpoint(cpu, breakpoint))?;
+                }
+            }
+
+            "d" | "delete" => {
+                let count = self.breakpoints.len();
+                match argument {
+                    Some(text) if text.starts_with('#') => {
+                        let number = text[1..].parse::<usize>().ok();
+                        self.breakpoints.retain(|breakpoint| Some(breakpoint.number) != number);
+                        if self.breakpoints.len() == count {
+                            writeln!(output, "No breakpoint {}", text)?;
+                        }
+                    }
+
+                    Some(text) => {
+                        if let Some(address) = resolve(cpu, output, text)? {
+                            self.breakpoints.retain(|breakpoint| breakpoint.start != address);
+                            if self.breakpoints.len() == count {
+                                writeln!(output, "No breakpoint at {}", describe(cpu, address))?;
+                            }
+                        }
+                    }
+
+                    None => self.breakpoints.clear(),
+                }
+                self.update_watches(cpu);
+            }
+
+            "s" | "step" => {
+                let count = match argument.map(|text| text.parse::<u64>()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        writeln!(output, "Invalid number of steps: {}", argument.unwrap())?;
+                        return Ok(true);
+                    }
+                };
+
+                for _ in 0..count {
+                    if !self.step(cpu, output)? {
+                        return Ok(true);
+                    }
+
+                    if self.stopped(cpu, output)? {
+                        break;
+                    }
+                }
+                self.show_location(cpu, output)?;
+            }
+
+            // Always take one step first, so continuing from a breakpoint doesn't stop at it straight away
+            "c" | "continue" => loop {
+                if !self.step(cpu, output)? {
+                    break;
+                }
+
+                if self.stopped(cpu, output)? {
+                    self.show_location(cpu, output)?;
+                    break;
+                }
+            },
+
+            "bs" | "back" | "step-back" => {
+                let count = match argument.map(|text| text.parse::<u64>()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        writeln!(output, "Invalid number of steps: {}", argument.unwrap())?;
+                        return Ok(true);
+                    }
+                };
+
+                for _ in 0..count {
+                    if !self.step_back(cpu, output)? {
+                        break;
+                    }
+                }
+                self.show_location(cpu, output)?;
+            }
+
+            // Like continue, one step back is always taken first
+            "rc" | "reverse" | "reverse-continue" => {
+                while self.step_back(cpu, output)? {
+                    if self.stopped_backwards(cpu, output)? {
+                        break;
+                    }
+                }
+                self.show_location(cpu, output)?;
+            }
+
+            "r" | "regs" => {
+                writeln!(output, "A=${:02X} X=${:02X} Y=${:02X} SP=${:02X} P=%{:08b} PC={}", cpu.register_a, cpu.register_x,
+                    cpu.register_y, cpu.stack_pointer, cpu.status_flags, describe(cpu, cpu.program_counter))?;
+            }
+
+            "bt" | "backtrace" => {
+                for (level, line) in backtrace(cpu).iter().enumerate() {
+                    writeln!(output, "#{:<2} {}", level, line)?;
+                }
+            }
+
+            // A single address shows the 128 bytes from there
+            "x" | "dump" => match argument.ok_or(String::from("dump needs an address or a range")).and_then(|text| parse_range(cpu, text)) {
+                Ok((start, end)) => {
+                    let end = if start == end { start.saturating_add(DUMP_BYTES - 1) } else { end };
+                    write!(output, "{}", dump::dump(&Snapshot::take(cpu, start, end)))?;
+                }
+                Err(e) => writeln!(output, "{}", e)?,
+            },
+
+            "snapshot" => {
+                self.snapshot = Some(Snapshot::take(cpu, 0x0000, 0xFFFF));
+                writeln!(output, "Memory saved, diff shows what changes from here")?;
+            }
+
+            "diff" => {
+                let range = match argument {
+                    Some(text) => parse_range(cpu, text),
+                    None => Ok((0x0000, 0xFFFF)),
+                };
+
+                match (&self.snapshot, range) {
+                    (None, _) => writeln!(output, "No snapshot to compare with, take one with snapshot first")?,
+                    (_, Err(e)) => writeln!(output, "{}", e)?,
+                    (Some(snapshot), Ok((start, end))) => {
+                        let before = Snapshot { start, bytes: snapshot.bytes[start as usize..=end as usize].to_vec() };
+                        write!(output, "{}", dump::diff(&before, &Snapshot::take(cpu, start, end), false))?;
+                    }
+                }
+            }
+
+            "l" | "list" => {
+                let mut address = match argument {
+                    Some(text) => match resolve(cpu, output, text)? {
+                        Some(address) => address,
+                        None => return Ok(true),
+                    },
+                    None => cpu.program_counter,
+                };
+
+                for _ in 0..LIST_LINES {
+                    address = address.wrapping_add(write_instruction(cpu, address, output)?);
+                }
+            }
+
+            "h" | "help" => {
+                writeln!(output, "break ADDR, watch/rwatch/awatch ADDR, delete ADDR or #N, step [N], continue, back [N], reverse, regs, backtrace, list [ADDR], dump ADDR, snapshot, diff [RANGE], quit")?;
+                writeln!(output, "Short forms: b, w, d, s, c, bs (back), rc (reverse), r (regs), bt (backtrace), l, x (dump), h, q")?;
+                writeln!(output, "Addresses are hex ($0606) or labels, ranges look like $0200-$02FF")?;
+                writeln!(output, "Breakpoints can end with  if CONDITION  (like A == $10 && [$02] & 4) and  after N  (hits to skip)")?;
+            }
+
+            "q" | "quit" => return Ok(false),
+
+            _ => writeln!(output, "Unknown command: {} (type help for a list)", command)?,
+        }
+
+        Ok(true)
+    }
+
+    // Run one instruction. Returns false if the program is over
+    fn step<W: Write>(&mut self, cpu: &mut CPU, output: &mut W) -> io::Result<bool> {
+        if self.finished {
+            writeln!(output, "The program has finished")?;
+            return Ok(false);
+        }
+
+        cpu.watches.hits.clear();
+        if !cpu.step() {
+            // A stack error stops the program, but it can carry on from there
+            if let Some(error) = cpu.calls.error.take() {
+                writeln!(output, "{}", error)?;
+                return Ok(false);
+            }
+
+            self.finished = true;
+            writeln!(output, "Program finished, BRK at {}", describe(cpu, cpu.program_counter.wrapping_sub(1)))?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    // Undo one instruction. Returns false if there's nothing left to undo
+    fn step_back<W: Write>(&mut self, cpu: &mut CPU, output: &mut W) -> io::Result<bool> {
+        match cpu.step_back() {
+            Some(delta) => {
+                self.finished = false;
+                self.undone = delta.writes;
+                Ok(true)
+            }
+            None if cpu.history.is_none() => {
+                writeln!(output, "There's no history to go back through")?;
+                Ok(false)
+            }
+            None => {
+                writeln!(output, "Reached the start of the history")?;
+                Ok(false)
+            }
+        }
+    }
+
+    /*
+        After a step back, is the program counter on a breakpoint, or is the instruction there one that wrote to a
+        watched address? Hit counts are left alone, they count what happened going forwards
+    */
+    fn stopped_backwards<W: Write>(&self, cpu: &CPU, output: &mut W) -> io::Result<bool> {
+        let holds = |breakpoint: &Breakpoint| breakpoint.condition.as_ref().is_none_or(|(_, condition)| condition.is_true(cpu));
+        let mut stop = false;
+
+        for (address, old) in &self.undone {
+            for breakpoint in self.breakpoints.iter().filter(|breakpoint| breakpoint.covers(*address, watch::WRITE) && holds(breakpoint)) {
+                writeln!(output, "Watchpoint #{}: this instruction writes to {} (it held ${:02X} before)", breakpoint.number, describe(cpu, *address), old)?;
+                stop = true;
+            }
+        }
+
+        for breakpoint in self.breakpoints.iter().filter(|breakpoint| breakpoint.covers(cpu.program_counter, watch::EXECUTE) && holds(breakpoint)) {
+            writeln!(output, "Breakpoint #{} at {}", breakpoint.number, describe(cpu, cpu.program_counter))?;
+            stop = true;
+        }
+
+        Ok(stop)
+    }
+
+    // After a step, did the instruction hit a watchpoint or land on a breakpoint? Says which if so
+    fn stopped<W: Write>(&mut self, cpu: &CPU, output: &mut W) -> io::Result<bool> {
+        let mut stop = false;
+
+        for hit in cpu.watches.hits.iter().filter(|hit| !hit.dummy) {
+            for breakpoint in self.breakpoints.iter_mut().filter(|breakpoint| breakpoint.covers(hit.address, hit.access)) {
+                if breakpoint.hit(cpu) {
+                    writeln!(output, "Watchpoint #{}: {}", breakpoint.number, describe_hit(cpu, hit))?;
+                    stop = true;
+                }
+            }
+        }
+
+        for breakpoint in self.breakpoints.iter_mut().filter(|breakpoint| breakpoint.covers(cpu.program_counter, watch::EXECUTE)) {
+            if breakpoint.hit(cpu) {
+                writeln!(output, "Breakpoint #{} at {}", breakpoint.number, describe(cpu, cpu.program_counter))?;
+                stop = true;
+            }
+        }
+
+        Ok(stop)
+    }
+
+    fn show_location<W: Write>(&self, cpu: &CPU, output: &mut W) -> io::Result<()> {
+        write_instruction(cpu, cpu.program_counter, output).map(|_| ())
+    }
+}
+
+// An address or a range like $0200-$02FF, either end can be a label
+fn parse_range(cpu: &CPU, text: &str) -> Result<(u16, u16), String> {
+    let resolve = |text: &str| cpu.symbols.resolve(text).ok_or(format!("Unknown address or label: {}", text.trim()));
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (resolve(start)?, resolve(end)?),
+        None => (resolve(text)?, resolve(text)?),
+    };
+
+    if end < start {
+        return Err(format!("The range ends before it starts: {}", text));
+    }
+    Ok((start, end))
+}
+
+// An address along with its label, like $0606 (init)
+fn describe(cpu: &CPU, address: u16) -> String {
+    match cpu.symbols.name(address) {
+        Some(name) => format!("${:04X} ({})", address, name),
+        None => format!("${:04X}", address),
+    }
+}
+
+/*
+    Where the program is and the calls that got it there, innermost first:
+
+    $0610 in inner
+    $0606 in outer, JSR inner
+    $0600, JSR outer
+*/
+fn backtrace(cpu: &CPU) -> Vec<String> {
+    let name = |address: u16| cpu.symbols.format(address, false);
+    let within = |level: usize| match level {
+        0 => String::new(),
+        _ => format!(" in {}", name(cpu.calls.frames[level - 1].target)),
+    };
+
+    let frames = &cpu.calls.frames;
+    let mut lines = vec![format!("${:04X}{}", cpu.program_counter, within(frames.len()))];
+    for (level, frame) in frames.iter().enumerate().rev() {
+        let how = match frame.kind {
+            FrameKind::Subroutine => format!("JSR {}", name(frame.target)),
+            FrameKind::Interrupt => format!("interrupted, handler at {}", name(frame.target)),
+        };
+        lines.push(format!("${:04X}{}, {}", frame.caller, within(level), how));
+    }
+    lines
+}
+
+// Like  #2 watch $0200-$02FF if A == 3 after 1, hit 4 times
+fn describe_breakpoint(cpu: &CPU, breakpoint: &Breakpoint) -> String {
+    let kind = match breakpoint.access {
+        watch::EXECUTE => "break",
+        watch::READ => "rwatch",
+        watch::WRITE => "watch",
+        _ => "awatch",
+    };
+
+    let mut text = format!("#{} {} {}", breakpoint.number, kind, describe(cpu, breakpoint.start));
+    if breakpoint.end != breakpoint.start {
+        text += &format!("-{}", describe(cpu, breakpoint.end));
+    }
+    if let Some((condition, _)) = &breakpoint.condition {
+        text += &format!(" if {}", condition);
+    }
+    if breakpoint.after > 0 {
+        text += &format!(" after {}", breakpoint.after);
+    }
+    if breakpoint.hits > 0 {
+        text += &format!(", hit {} time{}", breakpoint.hits, if breakpoint.hits == 1 { "" } else { "s" });
+    }
+    text
+}
+
+// Like  write of $01 to $0200 by the instruction at $0603
+fn describe_hit(cpu: &CPU, hit: &Hit) -> String {
+    let access = if hit.access == watch::WRITE { "write of" } else { "read of" };
+    let preposition = if hit.access == watch::WRITE { "to" } else { "from" };
+    let source = match hit.source {
+        Source::Instruction(address) => format!("the instruction at {}", describe(cpu, address)),
+        Source::Device => String::from("a device"),
+    };
+    format!("{} ${:02X} {} {} by {}", access, hit.value, preposition, describe(cpu, hit.address), source)
+}
+
+// One line of disassembly, with the label above it and the script line next to it if there are any. Returns the length of the instruction
+fn write_instruction<W: Write>(cpu: &CPU, address: u16, output: &mut W) -> io::Result<u16> {
+    if let Some(name) = cpu.symbols.name(address) {
+        writeln!(output, "{}:", name)?;
+    }
+
+    let (text, length) = disassemble(cpu, address);
+    let marker = if address == cpu.program_counter { ">" } else { " " };
+    match cpu.source.lookup(address) {
+        Some(line) => writeln!(output, "{} {:04X}  {:<16}; {}", marker, address, text, line.describe())?,
+        None => writeln!(output, "{} {:04X}  {}", marker, address, text)?,
+    }
+    Ok(length)
+}
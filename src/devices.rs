@@ -0,0 +1,80 @@
+/*
+    Memory-mapped devices. Once a device is attached to the CPU, reads and writes to the addresses it maps
+    go to the device instead of RAM, which is how the 6502 talks to the outside world (keyboards, screens, timers...).
+
+    maps - Whether the device answers at this address
+    read - The CPU reads from the device, this can have side effects (like taking a character out of a buffer)
+    write - The CPU writes to the device
+    peek - Look at what a read would return without any side effects, for debuggers and memory dumps
+    tick - Time passing, called after every instruction with the number of cycles it took (for timers)
+    irq - Whether the device is pulling the IRQ line, the CPU checks this between instructions
+*/
+pub trait Device {
+    fn maps(&self, address: u16) -> bool;
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, data: u8);
+    fn peek(&self, address: u16) -> u8;
+
+    fn tick(&mut self, _cycles: u8) {}
+
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+// Read-only memory starting at an address, writes to it are ignored
+pub struct Rom {
+    start: u16,
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(start: u16, data: &[u8]) -> Self {
+        Rom { start, data: data.to_vec() }
+    }
+}
+
+impl Device for Rom {
+    fn maps(&self, address: u16) -> bool {
+        address >= self.start && ((address - self.start) as usize) < self.data.len()
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
+
+    fn write(&mut self, _address: u16, _data: u8) {}
+
+    fn peek(&self, address: u16) -> u8 {
+        self.data[(address - self.start) as usize]
+    }
+}
+
+// Addresses with nothing behind them (start and end included). Reads give 0 and writes go nowhere,
+// for machines that have less than 64K of RAM
+pub struct Unmapped {
+    start: u16,
+    end: u16,
+}
+
+impl Unmapped {
+    pub fn new(start: u16, end: u16) -> Self {
+        Unmapped { start, end }
+    }
+}
+
+impl Device for Unmapped {
+    fn maps(&self, address: u16) -> bool {
+        address >= self.start && address <= self.end
+    }
+
+    fn read(&mut self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _address: u16, _data: u8) {}
+
+    fn peek(&self, _address: u16) -> u8 {
+        0
+    }
+}
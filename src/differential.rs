@@ -0,0 +1,278 @@
+use crate::disassembler::disassemble;
+use crate::opcode_info::OPCODES_TABLE;
+use crate::processor::CPU;
+use crate::reference::{self, Machine, Mode};
+use crate::watch;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt::Write as _;
+use std::panic::{self, AssertUnwindSafe};
+
+pub const PROGRAM_START: u16 = 0x0600;
+const RANDOM_MEMORY: usize = 0x0300; // The zero page, the stack and page 2 start out random, everything else is 0
+const MAX_STEPS: usize = 64; // Enough for every instruction plus a few loops, then the case is over
+const P_MASK: u8 = !reference::PUSHED; // Bits 4 and 5 of P aren't real, so they're left out of the comparison
+
+// Where a case starts: the registers, memory and the program, which goes at start
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub s: u8,
+    pub start: u16,             // Where the program goes and where it starts running
+    pub memory: Vec<(u16, u8)>, // Bytes that aren't 0 outside the program
+    pub program: Vec<Vec<u8>>,  // One instruction each, laid out one after another
+}
+
+// The first place the emulator and the reference disagree
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub step: usize,    // Instructions run before this one
+    pub address: u16,   // Where the instruction that went wrong is
+    pub message: String,
+}
+
+impl Case {
+    pub fn random(rng: &mut StdRng, opcodes: &[u8], length: usize) -> Self {
+        let memory = (0..RANDOM_MEMORY as u16).map(|address| (address, rng.gen())).filter(|(_, value)| *value != 0).collect();
+        let program = (0..length).map(|_| {
+            let opcode = opcodes[rng.gen_range(0, opcodes.len())];
+            let mode = reference::decode(opcode).map_or(Mode::Implied, |(_, mode)| mode);
+            let mut instruction = vec![opcode];
+            instruction.extend((1..mode.length()).map(|_| rng.gen::<u8>()));
+            instruction
+        }).collect();
+
+        // Half the programs go right at the top of memory, so they run (and branch and index) across $FFFF
+        let start = if rng.gen() { PROGRAM_START } else { 0u16.wrapping_sub(rng.gen_range(1, 0x40)) };
+        Case { a: rng.gen(), x: rng.gen(), y: rng.gen(), p: rng.gen::<u8>() & P_MASK, s: rng.gen(), start, memory, program }
+    }
+
+    // All 64K, as the case starts
+    fn image(&self) -> Vec<u8> {
+        let mut image = vec![0; 0x10000];
+        for (address, value) in &self.memory {
+            image[*address as usize] = *value;
+        }
+
+        // A program that runs past $FFFF carries on at $0000
+        for (offset, byte) in self.program.concat().into_iter().enumerate() {
+            image[self.start.wrapping_add(offset as u16) as usize] = byte;
+        }
+        image
+    }
+}
+
+/*
+    Run the case on the emulator and on the reference side by side, one instruction at a time. After every one
+    the registers have to match, and so does every byte either of them wrote. A case is over when both reach a
+    BRK, an opcode that isn't being tested or MAX_STEPS
+*/
+pub fn check(case: &Case, opcodes: &[u8]) -> Result<(), Divergence> {
+    let image = case.image();
+    let mut machine = Machine::new(image.clone());
+    (machine.a, machine.x, machine.y, machine.p, machine.s, machine.pc) = (case.a, case.x, case.y, case.p, case.s, case.start);
+
+    let mut cpu = CPU::new();
+    cpu.load_at(0, &image);
+    (cpu.register_a, cpu.register_x, cpu.register_y, cpu.status_flags, cpu.stack_pointer) = (case.a, case.x, case.y, case.p, case.s);
+    cpu.program_counter = case.start;
+    cpu.watches.add(0x0000, 0xFFFF, watch::WRITE);
+
+    for step in 0..MAX_STEPS {
+        let address = machine.pc;
+        let opcode = machine.memory[address as usize];
+        let divergence = |message: String| Divergence { step, address, message };
+
+        if !opcodes.contains(&opcode) {
+            return Ok(());
+        }
+
+        let running = machine.step();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cpu.step()));
+        let cpu_running = result.map_err(|error| {
+            let text = error.downcast_ref::<&str>().map(|text| text.to_string()).or_else(|| error.downcast_ref::<String>().cloned());
+            divergence(format!("the emulator panicked: {}", text.unwrap_or_default()))
+        })?;
+
+        if running != cpu_running {
+            let (stopped, kept_going) = if running { ("The emulator", "the reference") } else { ("The reference", "the emulator") };
+            return Err(divergence(format!("{} stopped here but {} kept going", stopped, kept_going)));
+        }
+
+        let mut differences = Vec::new();
+        let mut compare = |name: &str, actual: u16, expected: u16, width: usize| {
+            if actual != expected {
+                differences.push(format!("{} is ${:0width$X} here and ${:0width$X} in the reference", name, actual, expected, width = width));
+            }
+        };
+        compare("A", cpu.register_a as u16, machine.a as u16, 2);
+        compare("X", cpu.register_x as u16, machine.x as u16, 2);
+        compare("Y", cpu.register_y as u16, machine.y as u16, 2);
+        compare("P", (cpu.status_flags & P_MASK) as u16, (machine.p & P_MASK) as u16, 2);
+        compare("SP", cpu.stack_pointer as u16, machine.s as u16, 2);
+        if running {
+            compare("PC", cpu.program_counter, machine.pc, 4);
+        }
+
+        let mut written: Vec<u16> = cpu.watches.take().iter().map(|hit| hit.address).chain(machine.writes.drain(..)).collect();
+        written.sort();
+        written.dedup();
+        for written in written {
+            compare(&format!("[${:04X}]", written), cpu.peek_memory_u8(written) as u16, machine.memory[written as usize] as u16, 2);
+        }
+
+        if !differences.is_empty() {
+            return Err(divergence(differences.join(", ")));
+        }
+        if !running {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/*
+    Make a failing case as small as possible while it still fails: fewer instructions, fewer bytes of memory that
+    aren't 0, and registers that are 0 and a program at $0600 if they don't matter. Each pass tries big pieces
+    first and then smaller ones, and it keeps going until nothing more can be taken away
+*/
+pub fn minimize<F: Fn(&Case) -> bool>(case: &Case, fails: F) -> Case {
+    let mut case = case.clone();
+
+    loop {
+        let before = case.clone();
+
+        case.program = shrink(&case.program, |program| fails(&Case { program: program.to_vec(), ..case.clone() }));
+        case.memory = shrink(&case.memory, |memory| fails(&Case { memory: memory.to_vec(), ..case.clone() }));
+
+        for register in 0..5 {
+            let mut simpler = case.clone();
+            *[&mut simpler.a, &mut simpler.x, &mut simpler.y, &mut simpler.p, &mut simpler.s][register] = 0;
+            if simpler != case && fails(&simpler) {
+                case = simpler;
+            }
+        }
+
+        // A program that only goes wrong at the top of memory stays there, the rest move back to $0600
+        let simpler = Case { start: PROGRAM_START, ..case.clone() };
+        if simpler != case && fails(&simpler) {
+            case = simpler;
+        }
+
+        // Operands go to 0 too, so the reproducer doesn't point at random places
+        for index in 0..case.program.len() {
+            for byte in 1..case.program[index].len() {
+                let mut simpler = case.clone();
+                simpler.program[index][byte] = 0;
+                if simpler != case && fails(&simpler) {
+                    case = simpler;
+                }
+            }
+        }
+
+        if case == before {
+            return case;
+        }
+    }
+}
+
+// Take away chunks of the list, halving their size, as long as what's left still fails
+fn shrink<T: Clone, F: Fn(&[T]) -> bool>(items: &[T], fails: F) -> Vec<T> {
+    let mut items = items.to_vec();
+    let mut size = items.len().max(1);
+
+    loop {
+        let mut start = 0;
+        while start < items.len() {
+            let end = (start + size).min(items.len());
+            let smaller: Vec<T> = items[..start].iter().chain(&items[end..]).cloned().collect();
+            if fails(&smaller) {
+                items = smaller;
+            } else {
+                start = end;
+            }
+        }
+
+        if size == 1 {
+            return items;
+        }
+        size /= 2;
+    }
+}
+
+/*
+    The case as something that can be run again: the registers to start with, and a script with the memory and
+    the program (with each instruction disassembled) followed by what went wrong
+*/
+pub fn reproducer(case: &Case, divergence: &Divergence) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "// Start with A=${:02X} X=${:02X} Y=${:02X} P=${:02X} SP=${:02X}", case.a, case.x, case.y, case.p, case.s);
+
+    // Memory, in runs of bytes next to each other
+    let mut previous: Option<u16> = None;
+    for (address, value) in &case.memory {
+        if previous.is_none_or(|previous| previous.wrapping_add(1) != *address) {
+            let _ = writeln!(text, "{}* = ${:04X}", if previous.is_some() { "\n" } else { "" }, address);
+        }
+        let _ = write!(text, "{:02x} ", value);
+        previous = Some(*address);
+    }
+    if previous.is_some() {
+        text.push('\n');
+    }
+
+    let mut cpu = CPU::new();
+    cpu.load_at(0, &case.image());
+    let _ = writeln!(text, "* = ${:04X}", case.start);
+    let mut address = case.start;
+    for instruction in &case.program {
+        let hex: Vec<String> = instruction.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        // Scripts can't run past $FFFF, so an instruction that does is split in two with the rest at $0000
+        let fits = (0x10000 - address as usize).min(hex.len());
+        let (here, wrapped) = hex.split_at(fits);
+        let _ = writeln!(text, "{:<9} // ${:04X} {}", here.join(" "), address, disassemble(&cpu, address).0);
+        address = address.wrapping_add(instruction.len() as u16);
+        if address < instruction.len() as u16 {
+            let _ = writeln!(text, "* = $0000");
+            if !wrapped.is_empty() {
+                let _ = writeln!(text, "{}", wrapped.join(" "));
+            }
+        }
+    }
+
+    let _ = write!(text, "// Instruction {}, at ${:04X}: {}", divergence.step + 1, divergence.address, divergence.message);
+    text
+}
+
+// Every opcode the emulator has, except BRK (which ends a case)
+pub fn tested_opcodes() -> Vec<u8> {
+    let mut opcodes: Vec<u8> = OPCODES_TABLE.keys().copied().filter(|opcode| *opcode != 0x00).collect();
+    opcodes.sort();
+    opcodes
+}
+
+/*
+    Run this many random cases from the seed. The first one that fails is minimized, and its reproducer is the
+    error. Every case gets its own seed (from the first one), so a failure can be run again on its own
+*/
+pub fn run(seed: u64, cases: usize, opcodes: &[u8]) -> Result<(), String> {
+    let mut seeds = StdRng::seed_from_u64(seed);
+    for _ in 0..cases {
+        let case_seed: u64 = seeds.gen();
+        let mut rng = StdRng::seed_from_u64(case_seed);
+        let length = rng.gen_range(1, 17);
+        let case = Case::random(&mut rng, opcodes, length);
+
+        if check(&case, opcodes).is_err() {
+            let case = minimize(&case, |case| check(case, opcodes).is_err());
+            let divergence = check(&case, opcodes).unwrap_err();
+            return Err(format!("Case seed {}:\n{}", case_seed, reproducer(&case, &divergence)));
+        }
+    }
+    Ok(())
+}
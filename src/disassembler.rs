@@ -0,0 +1,45 @@
+use crate::opcode_info::OPCODES_TABLE;
+use crate::processor::{AddressingMode, CPU};
+
+/*
+    Turns the bytes at an address back into assembly, for example a9 0a -> LDA #$0A.
+    Returns the text along with the length of the instruction, so the next one can be found.
+    Bytes that aren't a known opcode are shown as data (.byte $FF), and addresses with a label are shown by name (JSR init)
+*/
+pub fn disassemble(cpu: &CPU, address: u16) -> (String, u16) {
+    let opcode = cpu.peek_memory_u8(address);
+    let opcode_info = match OPCODES_TABLE.get(&opcode) {
+        Some(info) => info,
+        None => return (format!(".byte ${:02X}", opcode), 1),
+    };
+
+    let byte = cpu.peek_memory_u8(address.wrapping_add(1));
+    let word = (cpu.peek_memory_u8(address.wrapping_add(2)) as u16) << 8 | byte as u16;
+
+    let symbols = &cpu.symbols;
+    let zero_page = symbols.format(byte as u16, true);
+    let absolute = symbols.format(word, false);
+
+    let operand = match opcode_info.mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => String::from(" A"),
+        AddressingMode::Immediate => format!(" #${:02X}", byte),
+        AddressingMode::ZeroPage => format!(" {}", zero_page),
+        AddressingMode::ZeroPageX => format!(" {},X", zero_page),
+        AddressingMode::ZeroPageY => format!(" {},Y", zero_page),
+        AddressingMode::Absolute => format!(" {}", absolute),
+        AddressingMode::AbsoluteX => format!(" {},X", absolute),
+        AddressingMode::AbsoluteY => format!(" {},Y", absolute),
+        AddressingMode::Indirect => format!(" ({})", absolute),
+        AddressingMode::IndexedIndirect => format!(" ({},X)", zero_page),
+        AddressingMode::IndirectIndexed => format!(" ({}),Y", zero_page),
+
+        // Show where the branch goes rather than the offset
+        AddressingMode::Relative => {
+            let target = address.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            format!(" {}", symbols.format(target, false))
+        }
+    };
+
+    (format!("{}{}", opcode_info.mnemonic, operand), opcode_info.byte_length as u16)
+}
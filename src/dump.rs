@@ -0,0 +1,130 @@
+use crate::processor::CPU;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal};
+
+const BYTES_PER_ROW: usize = 16;
+const HIGHLIGHT: &str = "\x1b[1;31m"; // Changed bytes are bold red on a terminal
+const RESET: &str = "\x1b[0m";
+
+// A copy of a range of memory, taken without disturbing devices
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub start: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl Snapshot {
+    // Both ends are included
+    pub fn take(cpu: &CPU, start: u16, end: u16) -> Self {
+        Snapshot { start, bytes: (start..=end).map(|address| cpu.peek_memory_u8(address)).collect() }
+    }
+
+    pub fn end(&self) -> u16 {
+        (self.start as usize + self.bytes.len()).saturating_sub(1) as u16
+    }
+
+    fn get(&self, address: usize) -> Option<u8> {
+        address.checked_sub(self.start as usize).and_then(|offset| self.bytes.get(offset)).copied()
+    }
+}
+
+/*
+    The classic hex and ASCII listing, 16 bytes to a row. Rows start on a multiple of 16, and addresses
+    outside the range are left blank:
+
+    0200  01 02 03 04 05 06 07 08  48 65 6C 6C 6F 00 00 00  |........Hello...|
+*/
+pub fn dump(snapshot: &Snapshot) -> String {
+    let mut text = String::new();
+    for row in rows(snapshot) {
+        let _ = writeln!(text, "{}", format_row(row, |address| snapshot.get(address), |_| false, false));
+    }
+    text
+}
+
+/*
+    Only the rows where something changed, before and then after, with a ^ under every byte that changed
+    (and the changed bytes highlighted if color is on). Both snapshots should cover the same range
+*/
+pub fn diff(before: &Snapshot, after: &Snapshot, color: bool) -> String {
+    let mut text = String::new();
+    let changed = |address: usize| before.get(address) != after.get(address);
+
+    let mut count = 0;
+    for row in rows(after) {
+        if !(row..row + BYTES_PER_ROW).any(changed) {
+            continue;
+        }
+
+        let _ = writeln!(text, "{}", format_row(row, |address| before.get(address), |_| false, false));
+        let _ = writeln!(text, "{}", format_row(row, |address| after.get(address), changed, color));
+
+        // The markers line up under the hex digits and the ASCII column
+        let mut markers = String::new();
+        let mut ascii = String::new();
+        for (column, address) in (row..row + BYTES_PER_ROW).enumerate() {
+            let mark = if changed(address) { '^' } else { ' ' };
+            markers.push_str(&format!("{}{} ", mark, mark));
+            if column == BYTES_PER_ROW / 2 - 1 {
+                markers.push(' ');
+            }
+            ascii.push(mark);
+            count += changed(address) as usize;
+        }
+        let _ = writeln!(text, "{}\n", format!("      {}  {}", markers, ascii).trim_end());
+    }
+
+    let _ = writeln!(text, "{} byte{} changed between ${:04X} and ${:04X}", count, if count == 1 { "" } else { "s" }, after.start, after.end());
+    text
+}
+
+// The addresses each row starts at
+fn rows(snapshot: &Snapshot) -> impl Iterator<Item = usize> {
+    let first = snapshot.start as usize / BYTES_PER_ROW * BYTES_PER_ROW;
+    let last = snapshot.start as usize + snapshot.bytes.len();
+    (first..last).step_by(BYTES_PER_ROW)
+}
+
+fn format_row<B, H>(row: usize, byte: B, highlight: H, color: bool) -> String where B: Fn(usize) -> Option<u8>, H: Fn(usize) -> bool, {
+    let mut hex = String::new();
+    let mut ascii = String::new();
+
+    for (column, address) in (row..row + BYTES_PER_ROW).enumerate() {
+        let (text, character) = match byte(address) {
+            Some(value) => (format!("{:02X}", value), if value.is_ascii_graphic() || value == b' ' { value as char } else { '.' }),
+            None => (String::from("  "), ' '),
+        };
+
+        if color && highlight(address) {
+            hex.push_str(&format!("{}{}{} ", HIGHLIGHT, text, RESET));
+            ascii.push_str(&format!("{}{}{}", HIGHLIGHT, character, RESET));
+        } else {
+            hex.push_str(&format!("{} ", text));
+            ascii.push(character);
+        }
+
+        if column == BYTES_PER_ROW / 2 - 1 {
+            hex.push(' ');
+        }
+    }
+
+    format!("{:04X}  {} |{}|", row, hex, ascii)
+}
+
+// Snapshots of the --diff ranges, taken before the program runs
+pub fn snapshots(cpu: &CPU, ranges: &[(u16, u16)]) -> Vec<Snapshot> {
+    ranges.iter().map(|(start, end)| Snapshot::take(cpu, *start, *end)).collect()
+}
+
+// Print the --dump ranges, and what changed in the --diff ranges since their snapshots were taken
+pub fn finish(cpu: &CPU, dumps: &[(u16, u16)], before: &[Snapshot]) {
+    for (start, end) in dumps {
+        println!("\n Memory ${:04X}-${:04X} \n", start, end);
+        print!("{}", dump(&Snapshot::take(cpu, *start, *end)));
+    }
+
+    for snapshot in before {
+        println!("\n Changes in ${:04X}-${:04X} \n", snapshot.start, snapshot.end());
+        print!("{}", diff(snapshot, &Snapshot::take(cpu, snapshot.start, snapshot.end()), io::stdout().is_terminal()));
+    }
+}
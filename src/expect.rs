@@ -0,0 +1,285 @@
+use crate::cli::parse_number;
+use crate::expression::Expression;
+use crate::loader::{self, Format};
+use crate::processor::CPU;
+use crate::rng::RandomDevice;
+use crate::symbols::Symbols;
+use crate::script::{read_script, strip_comment};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_INSTRUCTIONS: u64 = 1_000_000; // A script that hasn't hit its BRK by then is taken to be stuck in a loop
+
+// When an expectation is checked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum After {
+    Brk,               // Once the program finishes
+    Instructions(u64), // Once this many instructions have run
+}
+
+// One thing that should be true, like [$0200]=$01
+pub struct Check {
+    pub text: String,
+    left: Expression,
+    right: Expression,
+}
+
+// Why a script couldn't be tested
+#[derive(Debug, PartialEq)]
+pub enum TestError {
+    NoExpectations, // Nothing to check, so it's skipped rather than failed
+    Broken(String), // It couldn't be read, loaded or its //! lines made no sense
+}
+
+impl std::fmt::Display for TestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TestError::NoExpectations => write!(f, "no //! expect lines"),
+            TestError::Broken(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// One //! expect line
+pub struct Expectation {
+    pub line: usize,
+    pub after: After,
+    pub checks: Vec<Check>,
+}
+
+/*
+    Scripts can say what they should leave behind in //! comments, so they can check themselves:
+
+    a9 0a aa 8d 00 02 00  //! expect A=$0A X=$0A [$0200]=$0A after BRK
+
+    Each check is NAME=VALUE with no spaces in it, where both sides can be anything a breakpoint condition can be
+    (registers, flags, [address] for memory, numbers and labels). A check is made once the program reaches its
+    BRK, or with  after 100  once 100 instructions have run. A script can have as many expect lines as it likes,
+    and they can go anywhere in it. A script that uses the random byte at 0xFE asks for it with  //! random,
+    or  //! random 1234  for a seed of its own
+*/
+pub fn parse(text: &str, symbols: &Symbols) -> Result<Vec<Expectation>, String> {
+    let mut expectations = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let Some(directive) = directive(line) else {
+            continue;
+        };
+
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        let mut words = directive.split_whitespace();
+        match words.next() {
+            Some("expect") => {}
+            Some("random") => {
+                if let Some(seed) = words.next() {
+                    parse_number(seed).ok_or(error(format!("random takes a seed, not '{}'", seed)))?;
+                }
+                continue;
+            }
+            Some(word) => return Err(error(format!("Unknown //! directive '{}'", word))),
+            None => return Err(error(String::from("//! needs a directive after it, like expect A=$0A"))),
+        }
+
+        let mut after = After::Brk;
+        let mut checks = Vec::new();
+        while let Some(word) = words.next() {
+            if word == "after" {
+                after = match words.next() {
+                    Some(when) if when.eq_ignore_ascii_case("brk") => After::Brk,
+                    Some(when) => After::Instructions(when.parse().map_err(|_| error(format!("after should be BRK or a number of instructions, not '{}'", when)))?),
+                    None => return Err(error(String::from("after needs BRK or a number of instructions"))),
+                };
+                continue;
+            }
+
+            let (left, right) = word.split_once('=').ok_or(error(format!("Checks look like NAME=VALUE, not '{}'", word)))?;
+            checks.push(Check {
+                text: word.to_string(),
+                left: Expression::parse(left, symbols).map_err(&error)?,
+                right: Expression::parse(right, symbols).map_err(&error)?,
+            });
+        }
+
+        if checks.is_empty() {
+            return Err(error(String::from("expect needs at least one check, like A=$0A")));
+        }
+        expectations.push(Expectation { line: number + 1, after, checks });
+    }
+
+    Ok(expectations)
+}
+
+// What comes after //! on a line, if anything
+fn directive(line: &str) -> Option<&str> {
+    line[strip_comment(line).len()..].strip_prefix("//!").map(str::trim)
+}
+
+// The seed for the random byte device if the script asked for one with //! random, the given one if it didn't say which
+pub fn random_seed(text: &str, seed: u64) -> Option<u64> {
+    text.lines().filter_map(directive).find_map(|directive| {
+        let mut words = directive.split_whitespace();
+        (words.next() == Some("random")).then(|| words.next().and_then(parse_number).unwrap_or(seed))
+    })
+}
+
+/*
+    Run the program in the CPU until its BRK, making every check when its time comes. Returns the checks that
+    failed, in words, or nothing if they all held. A program that stops anywhere but a BRK (an invalid opcode,
+    or a stack error with --stack-check error) has crashed, and every check still waiting fails with why
+*/
+pub fn run(cpu: &mut CPU, expectations: &[Expectation]) -> Vec<String> {
+    let mut failures = Vec::new();
+    let mut count: u64 = 0;
+    let mut finished = false;
+    let mut crashed: Option<String> = None;
+
+    // Checks that go after a number of instructions come first, in order
+    let mut pending: Vec<&Expectation> = expectations.iter().collect();
+    pending.sort_by_key(|expectation| match expectation.after {
+        After::Instructions(count) => count,
+        After::Brk => u64::MAX,
+    });
+
+    for expectation in pending {
+        match expectation.after {
+            After::Instructions(target) => {
+                while !finished && count < target {
+                    finished = !step(cpu, &mut crashed);
+                    count += 1;
+                }
+
+                if let Some(reason) = &crashed {
+                    failures.push(format!("line {}: {}", expectation.line, reason));
+                    continue;
+                }
+                if count < target {
+                    failures.push(format!("line {}: the program finished after {} instructions, before getting to {}", expectation.line, count, target));
+                    continue;
+                }
+            }
+
+            After::Brk => {
+                while !finished && count < MAX_INSTRUCTIONS {
+                    finished = !step(cpu, &mut crashed);
+                    count += 1;
+                }
+
+                if let Some(reason) = &crashed {
+                    failures.push(format!("line {}: {}", expectation.line, reason));
+                    continue;
+                }
+                if !finished {
+                    failures.push(format!("line {}: no BRK within {} instructions", expectation.line, MAX_INSTRUCTIONS));
+                    continue;
+                }
+            }
+        }
+
+        failures.extend(check(cpu, expectation));
+    }
+
+    failures
+}
+
+// One instruction, false once the program has stopped. Stopping anywhere but a BRK says why in crashed
+fn step(cpu: &mut CPU, crashed: &mut Option<String>) -> bool {
+    let (address, opcode) = (cpu.program_counter, cpu.peek_memory_u8(cpu.program_counter));
+    if cpu.step() {
+        return true;
+    }
+
+    if let Some(error) = cpu.calls.error.take() {
+        *crashed = Some(format!("the program crashed: {}", error));
+    } else if opcode != 0x00 {
+        *crashed = Some(format!("the program crashed: ${:02X} at ${:04X} is an invalid opcode", opcode, address));
+    }
+    false
+}
+
+fn check(cpu: &CPU, expectation: &Expectation) -> Vec<String> {
+    let mut failures = Vec::new();
+    for check in &expectation.checks {
+        let (actual, expected) = (check.left.evaluate(cpu), check.right.evaluate(cpu));
+        if actual != expected {
+            let name = check.text.split('=').next().unwrap_or("");
+            failures.push(format!("line {}: {} is {}, expected {}", expectation.line, name, hex(actual), hex(expected)));
+        }
+    }
+    failures
+}
+
+// Bytes as $XX, anything bigger as $XXXX
+fn hex(value: i64) -> String {
+    match value {
+        0..=0xFF => format!("${:02X}", value),
+        0x100..=0xFFFF => format!("${:04X}", value),
+        _ => value.to_string(),
+    }
+}
+
+// Read, load and run one script. Ok has the checks that failed, empty if it passed
+pub fn test_script(path: &Path, seed: u64) -> Result<Vec<String>, TestError> {
+    let text = fs::read_to_string(path).map_err(|e| TestError::Broken(e.to_string()))?;
+    let file = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let image = read_script(&text, &file).map_err(|e| TestError::Broken(e.to_string()))?;
+
+    // Always the same random bytes, so a script passes or fails the same way every time
+    let mut cpu = CPU::new();
+    cpu.random = random_seed(&text, seed).map(|seed| RandomDevice::new(seed, false));
+    image.load_into(&mut cpu);
+    let expectations = parse(&text, &cpu.symbols).map_err(TestError::Broken)?;
+    if expectations.is_empty() {
+        return Err(TestError::NoExpectations);
+    }
+
+    Ok(run(&mut cpu, &expectations))
+}
+
+/*
+    astrobyte test DIR: run every script in the directory (or just the one, if given a file) and say which passed.
+    Scripts without any //! expect lines are skipped. Returns true if nothing failed
+*/
+pub fn run_tests(path: &Path, seed: u64) -> Result<bool, String> {
+    let mut scripts: Vec<PathBuf> = if path.is_dir() {
+        fs::read_dir(path).map_err(|e| format!("{}: {}", path.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && fs::read(path).is_ok_and(|bytes| loader::detect(path, &bytes) == Format::Script))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+    scripts.sort();
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for script in &scripts {
+        let name = script.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        match test_script(script, seed) {
+            Ok(failures) if failures.is_empty() => {
+                println!("PASS  {}", name);
+                passed += 1;
+            }
+
+            Ok(failures) => {
+                println!("FAIL  {}", name);
+                for failure in failures {
+                    println!("      {}", failure);
+                }
+                failed += 1;
+            }
+
+            Err(e @ TestError::NoExpectations) => {
+                println!("SKIP  {} ({})", name, e);
+                skipped += 1;
+            }
+
+            Err(e) => {
+                println!("FAIL  {}", name);
+                println!("      {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("\n{} scripts: {} passed, {} failed, {} skipped", scripts.len(), passed, failed, skipped);
+    Ok(failed == 0)
+}
@@ -0,0 +1,245 @@
+use crate::processor::CPU;
+use crate::symbols::Symbols;
+
+/*
+    Expressions over the registers and memory, for breakpoint conditions like  A == $10 && [$02] & 4
+
+    Numbers        $10 (hex), %1010 (binary), 16 (decimal) or a label
+    Registers      A, X, Y, SP, P, PC, and the flags on their own: N, V, D, I, Z, C (0 or 1)
+    Memory         [$0200] is the byte at $0200, the address can be any expression
+    Operators      + - & | ^ == != < <= > >= && || ! ~ and parentheses. Unlike C, & | and ^ go before the
+                   comparisons, so [$02] & 4 == 4 means what it looks like
+
+    Anything that isn't 0 counts as true. Memory is only peeked at, so devices don't notice
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expression>),
+    Not(Box<Expression>),
+    Complement(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    SP,
+    P,
+    PC,
+    Flag(u8), // A single bit of P
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Subtract,
+}
+
+// Operators from lowest to highest precedence, longer ones first so == isn't read as =
+const LEVELS: [&[(&str, Operator)]; 6] = [
+    &[("||", Operator::Or)],
+    &[("&&", Operator::And)],
+    &[("==", Operator::Equal), ("!=", Operator::NotEqual), ("<=", Operator::LessEqual), (">=", Operator::GreaterEqual), ("<", Operator::Less), (">", Operator::Greater)],
+    &[("|", Operator::BitOr), ("^", Operator::BitXor)],
+    &[("&", Operator::BitAnd)],
+    &[("+", Operator::Add), ("-", Operator::Subtract)],
+];
+
+impl Expression {
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Expression, String> {
+        let mut parser = Parser { text, position: 0, symbols };
+        let expression = parser.binary(0)?;
+
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(format!("Unexpected '{}' in {}", &text[parser.position..], text));
+        }
+        Ok(expression)
+    }
+
+    pub fn evaluate(&self, cpu: &CPU) -> i64 {
+        match self {
+            Expression::Number(number) => *number,
+            Expression::Register(register) => match register {
+                Register::A => cpu.register_a as i64,
+                Register::X => cpu.register_x as i64,
+                Register::Y => cpu.register_y as i64,
+                Register::SP => cpu.stack_pointer as i64,
+                Register::P => cpu.status_flags as i64,
+                Register::PC => cpu.program_counter as i64,
+                Register::Flag(bit) => ((cpu.status_flags >> bit) & 1) as i64,
+            },
+            Expression::Memory(address) => cpu.peek_memory_u8(address.evaluate(cpu) as u16) as i64,
+            Expression::Not(value) => (value.evaluate(cpu) == 0) as i64,
+            Expression::Complement(value) => !value.evaluate(cpu),
+            Expression::Binary(left, operator, right) => {
+                let left = left.evaluate(cpu);
+
+                // Short circuit, like in C
+                match operator {
+                    Operator::Or if left != 0 => return 1,
+                    Operator::And if left == 0 => return 0,
+                    _ => {}
+                }
+
+                let right = right.evaluate(cpu);
+                match operator {
+                    Operator::Or | Operator::And => (right != 0) as i64,
+                    Operator::Equal => (left == right) as i64,
+                    Operator::NotEqual => (left != right) as i64,
+                    Operator::Less => (left < right) as i64,
+                    Operator::LessEqual => (left <= right) as i64,
+                    Operator::Greater => (left > right) as i64,
+                    Operator::GreaterEqual => (left >= right) as i64,
+                    Operator::BitOr => left | right,
+                    Operator::BitXor => left ^ right,
+                    Operator::BitAnd => left & right,
+                    Operator::Add => left.wrapping_add(right),
+                    Operator::Subtract => left.wrapping_sub(right),
+                }
+            }
+        }
+    }
+
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        self.evaluate(cpu) != 0
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    // Take the text if it comes next
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.text[self.position..].starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Operators at this level and above, left to right
+    fn binary(&mut self, level: usize) -> Result<Expression, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            for (token, operator) in LEVELS[level] {
+                // | and & on their own, not the first half of || or &&
+                let doubled = format!("{}{}", token, token);
+                self.skip_whitespace();
+                if token.len() == 1 && "|&".contains(*token) && self.text[self.position..].starts_with(&doubled) {
+                    continue;
+                }
+
+                if self.eat(token) {
+                    let right = self.binary(level + 1)?;
+                    left = Expression::Binary(Box::new(left), *operator, Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.eat("!") {
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Expression::Complement(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            let value = self.unary()?;
+            return Ok(Expression::Binary(Box::new(Expression::Number(0)), Operator::Subtract, Box::new(value)));
+        }
+
+        if self.eat("(") {
+            let expression = self.binary(0)?;
+            return if self.eat(")") { Ok(expression) } else { Err(String::from("Missing ')'")) };
+        }
+
+        if self.eat("[") {
+            let address = self.binary(0)?;
+            return if self.eat("]") { Ok(Expression::Memory(Box::new(address))) } else { Err(String::from("Missing ']'")) };
+        }
+
+        self.atom()
+    }
+
+    // A number, register or label
+    fn atom(&mut self) -> Result<Expression, String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+        let length = rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || "$%_@.".contains(ch))).unwrap_or(rest.len());
+        let word = &rest[..length];
+        if word.is_empty() {
+            return Err(if rest.is_empty() { String::from("Expression ends too early") } else { format!("Unexpected '{}'", rest) });
+        }
+        self.position += length;
+
+        let number = if let Some(hex) = word.strip_prefix('$') {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = word.strip_prefix('%') {
+            i64::from_str_radix(binary, 2).ok()
+        } else if word.starts_with(|ch: char| ch.is_ascii_digit()) {
+            word.parse().ok()
+        } else {
+            None
+        };
+
+        if let Some(number) = number {
+            return Ok(Expression::Number(number));
+        }
+
+        let register = match word.to_uppercase().as_str() {
+            "A" => Some(Register::A),
+            "X" => Some(Register::X),
+            "Y" => Some(Register::Y),
+            "SP" | "S" => Some(Register::SP),
+            "P" => Some(Register::P),
+            "PC" => Some(Register::PC),
+            "N" => Some(Register::Flag(7)),
+            "V" => Some(Register::Flag(6)),
+            "D" => Some(Register::Flag(3)),
+            "I" => Some(Register::Flag(2)),
+            "Z" => Some(Register::Flag(1)),
+            "C" => Some(Register::Flag(0)),
+            _ => None,
+        };
+
+        match (register, self.symbols.address(word)) {
+            (Some(register), _) => Ok(Expression::Register(register)),
+            (None, Some(address)) => Ok(Expression::Number(address as i64)),
+            (None, None) => Err(format!("Unknown name or number: {}", word)),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::expect;
+use crate::processor::CPU;
+use crate::script::read_script;
+
+const MAX_STEPS: usize = 10_000; // Arbitrary memory loops forever more often than not
+
+/*
+    What the fuzz targets in fuzz/ run, kept here so the tests can run the same thing on inputs that used to
+    crash. Neither of them should ever panic, whatever the input: bad input is an error or a stopped program
+*/
+
+// Any text through the script reader, and if it's a script, into memory and through the //! expect reader too
+pub fn script(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    if let Ok(image) = read_script(text, "fuzz.txt") {
+        let mut cpu = CPU::new();
+        image.load_into(&mut cpu);
+        let _ = expect::parse(text, &cpu.symbols);
+    }
+}
+
+/*
+    Any memory image through the CPU. The first byte picks how it's loaded: with bit 0 set the rest goes through
+    load like snake (at 0x0600, however big it is), otherwise the next 7 bytes are A, X, Y, P, SP and the program
+    counter and the rest is memory from 0x0000
+*/
+pub fn cpu(data: &[u8]) {
+    let Some((&mode, rest)) = data.split_first() else {
+        return;
+    };
+
+    let mut cpu = CPU::new();
+    if mode & 1 == 1 {
+        cpu.load(&rest.to_vec());
+    } else {
+        let (registers, memory) = rest.split_at(rest.len().min(7));
+        let mut registers = registers.to_vec();
+        registers.resize(7, 0);
+
+        cpu.load_at(0, memory);
+        cpu.register_a = registers[0];
+        cpu.register_x = registers[1];
+        cpu.register_y = registers[2];
+        cpu.status_flags = registers[3];
+        cpu.stack_pointer = registers[4];
+        cpu.program_counter = u16::from_le_bytes([registers[5], registers[6]]);
+    }
+
+    for _ in 0..MAX_STEPS {
+        if !cpu.step() {
+            break;
+        }
+    }
+}
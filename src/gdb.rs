@@ -0,0 +1,358 @@
+use crate::processor::CPU;
+use crate::watch::{self, Hit};
+use std::collections::BTreeSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const REGISTERS: usize = 6; // A, X, Y, SP, P and PC
+const MAX_READ: usize = 0x1000; // Longest memory read answered in one packet
+const INTERRUPT_CHECK: u64 = 1000; // While running, check for Ctrl+C from GDB every 1000 instructions
+const CTRL_C: u8 = 0x03;
+
+/*
+    A GDB remote serial protocol stub (--gdb PORT), so GDB (or anything else that speaks RSP) can debug a program
+    over TCP on localhost. Registers go A, X, Y, SP, P (one byte each) then PC (two bytes, little-endian), and
+    memory reads don't disturb devices. Supported:
+
+    g/G, p/P        read and write registers
+    m/M             read and write memory
+    Z0/z0 (and Z1)  set and remove breakpoints
+    Z2/Z3/Z4        write, read and access watchpoints
+    s/c             step and continue (Ctrl+C stops a running program)
+    ?, D, k         why the program stopped, detach and kill
+
+    For example, in GDB:  target remote localhost:1234
+*/
+pub struct GdbStub {
+    pub breakpoints: BTreeSet<u16>,
+    finished: bool, // The program ran into a BRK
+}
+
+// Why the program stopped running
+enum Stop {
+    Step, // A step finished or a breakpoint was reached
+    Watch(Hit),
+    Interrupted,
+    Finished,
+}
+
+impl Default for GdbStub {
+    fn default() -> Self {
+        GdbStub::new()
+    }
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        GdbStub { breakpoints: BTreeSet::new(), finished: false }
+    }
+
+    // Wait for GDB to connect, then answer its packets until it detaches or hangs up
+    pub fn serve(&mut self, cpu: &mut CPU, listener: TcpListener) -> io::Result<()> {
+        println!("Waiting for GDB on {}", listener.local_addr()?);
+        let (mut stream, address) = listener.accept()?;
+        println!("GDB connected from {}", address);
+        stream.set_nodelay(true)?;
+
+        while let Some(packet) = read_packet(&mut stream)? {
+            stream.write_all(b"+")?;
+
+            match self.handle(cpu, &packet, &mut stream)? {
+                Some(reply) => send_packet(&mut stream, &reply)?,
+                None => {
+                    send_packet(&mut stream, "OK")?;
+                    break;
+                }
+            }
+        }
+
+        println!("GDB disconnected");
+        Ok(())
+    }
+
+    // The reply to a packet, or None when GDB is done with us
+    fn handle(&mut self, cpu: &mut CPU, packet: &str, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, |ch| ch.len_utf8()));
+
+        let reply = match command {
+            "?" => self.stop_reply(Stop::Step),
+
+            "g" => registers(cpu).iter().map(|byte| format!("{:02x}", byte)).collect(),
+
+            "G" => match hex_bytes(arguments) {
+                Some(bytes) if bytes.len() == REGISTERS + 1 => {
+                    set_registers(cpu, &bytes);
+                    String::from("OK")
+                }
+                _ => String::from("E01"),
+            },
+
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(5) => format!("{:02x}{:02x}", cpu.program_counter & 0xFF, cpu.program_counter >> 8),
+                Ok(register) if register < REGISTERS => format!("{:02x}", registers(cpu)[register]),
+                _ => String::from("E01"),
+            },
+
+            "P" => {
+                let parsed = arguments.split_once('=').and_then(|(register, value)| Some((usize::from_str_radix(register, 16).ok()?, hex_bytes(value)?)));
+                match parsed {
+                    Some((register, value)) if register < REGISTERS && !value.is_empty() => {
+                        let mut bytes = registers(cpu);
+                        bytes[register] = value[0];
+                        if register == 5 && value.len() > 1 {
+                            bytes[6] = value[1];
+                        }
+                        set_registers(cpu, &bytes);
+                        String::from("OK")
+                    }
+                    _ => String::from("E01"),
+                }
+            }
+
+            "m" => match address_length(arguments) {
+                Some((address, length)) => (0..length.min(MAX_READ))
+                    .map(|offset| format!("{:02x}", cpu.peek_memory_u8(address.wrapping_add(offset as u16))))
+                    .collect(),
+                None => String::from("E01"),
+            },
+
+            "M" => {
+                let parsed = arguments.split_once(':').and_then(|(range, data)| Some((address_length(range)?, hex_bytes(data)?)));
+                match parsed {
+                    Some(((address, length), data)) if data.len() == length => {
+                        for (offset, byte) in data.iter().enumerate() {
+                            cpu.write_memory_u8(address.wrapping_add(offset as u16), *byte);
+                        }
+                        cpu.watches.hits.clear(); // GDB's own writes don't count
+                        String::from("OK")
+                    }
+                    _ => String::from("E01"),
+                }
+            }
+
+            "Z" | "z" => self.breakpoint(cpu, command == "Z", arguments),
+
+            // A resume address is allowed, it's where the program counter goes first
+            "s" | "c" => {
+                if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                    cpu.program_counter = address;
+                }
+
+                let stop = if command == "s" { self.step(cpu) } else { self.resume(cpu, stream)? };
+                self.stop_reply(stop)
+            }
+
+            "q" if arguments.starts_with("Supported") => format!("PacketSize={:x}", MAX_READ * 2 + 16),
+            "q" if arguments == "Attached" => String::from("1"),
+            "q" if arguments == "C" => String::from("QC1"),
+            "q" if arguments == "fThreadInfo" => String::from("m1"),
+            "q" if arguments == "sThreadInfo" => String::from("l"),
+            "H" => String::from("OK"),
+
+            "D" | "k" => return Ok(None),
+
+            // Anything else isn't supported, which GDB understands an empty reply to mean
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+
+    // Z0 and Z1 are breakpoints, Z2 - Z4 watchpoints on writes, reads or both: Ztype,address,length
+    fn breakpoint(&mut self, cpu: &mut CPU, insert: bool, arguments: &str) -> String {
+        let mut parts = arguments.splitn(2, ',');
+        let kind = parts.next().unwrap_or("");
+        let (address, length) = match parts.next().and_then(address_length) {
+            Some(range) => range,
+            None => return String::from("E01"),
+        };
+
+        let access = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return String::from("OK");
+            }
+            "2" => watch::WRITE,
+            "3" => watch::READ,
+            "4" => watch::READ | watch::WRITE,
+            _ => return String::new(),
+        };
+
+        for (start, end) in watch_ranges(address, length) {
+            if insert {
+                cpu.watches.add(start, end, access);
+            } else {
+                cpu.watches.remove(start, end, access);
+            }
+        }
+        String::from("OK")
+    }
+
+    fn step(&mut self, cpu: &mut CPU) -> Stop {
+        // A stack error stops the program like a breakpoint would
+        if !self.finished && !cpu.step() {
+            if let Some(error) = cpu.calls.error.take() {
+                println!("{}", error);
+                return Stop::Step;
+            }
+            self.finished = true;
+        }
+
+        if self.finished {
+            return Stop::Finished;
+        }
+
+        match cpu.watches.take().iter().find(|hit| !hit.dummy) {
+            Some(hit) => Stop::Watch(*hit),
+            None => Stop::Step,
+        }
+    }
+
+    // Run until a breakpoint, a watchpoint, the end of the program or Ctrl+C
+    fn resume(&mut self, cpu: &mut CPU, stream: &mut TcpStream) -> io::Result<Stop> {
+        let mut count: u64 = 0;
+        loop {
+            match self.step(cpu) {
+                Stop::Step => {}
+                stop => return Ok(stop),
+            }
+
+            if self.breakpoints.contains(&cpu.program_counter) {
+                return Ok(Stop::Step);
+            }
+
+            count += 1;
+            if count.is_multiple_of(INTERRUPT_CHECK) && interrupted(stream)? {
+                return Ok(Stop::Interrupted);
+            }
+        }
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            _ if self.finished => String::from("W00"),
+            Stop::Finished => String::from("W00"),
+            Stop::Step => String::from("S05"),
+            Stop::Interrupted => String::from("S02"),
+            Stop::Watch(hit) => {
+                // GDB wants to hear which kind of watchpoint it set, not which access this was
+                let kind = match hit.watched {
+                    watch::WRITE => "watch",
+                    watch::READ => "rwatch",
+                    _ => "awatch",
+                };
+                format!("T05{}:{:04x};", kind, hit.address)
+            }
+        }
+    }
+}
+
+fn registers(cpu: &CPU) -> [u8; REGISTERS + 1] {
+    [cpu.register_a, cpu.register_x, cpu.register_y, cpu.stack_pointer, cpu.status_flags,
+        (cpu.program_counter & 0xFF) as u8, (cpu.program_counter >> 8) as u8]
+}
+
+fn set_registers(cpu: &mut CPU, bytes: &[u8]) {
+    cpu.register_a = bytes[0];
+    cpu.register_x = bytes[1];
+    cpu.register_y = bytes[2];
+    cpu.stack_pointer = bytes[3];
+    cpu.status_flags = bytes[4];
+    cpu.program_counter = u16::from_le_bytes([bytes[5], bytes[6]]);
+}
+
+// addr,length in hex, addresses past the top of memory aren't accepted
+fn address_length(text: &str) -> Option<(u16, usize)> {
+    let (address, length) = text.split_once(',')?;
+    let length = length.split(',').next()?;
+    Some((u16::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+// Watchpoints wrap around past $FFFF like m and M do, so one that would is split in two
+fn watch_ranges(address: u16, length: usize) -> Vec<(u16, u16)> {
+    let length = length.clamp(1, 0x10000);
+    if length == 0x10000 {
+        return vec![(0x0000, 0xFFFF)];
+    }
+    let end = address as usize + length - 1;
+    if end > 0xFFFF {
+        vec![(address, 0xFFFF), (0x0000, (end - 0x10000) as u16)]
+    } else {
+        vec![(address, end as u16)]
+    }
+}
+
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| text.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok())).collect()
+}
+
+/*
+    Packets look like $data#checksum, where the checksum is the sum of the data bytes as two hex digits.
+    Acks (+ and -) and Ctrl+C between packets are skipped. A packet with a bad checksum is answered with -
+    so GDB sends it again. Returns None once GDB hangs up
+*/
+fn read_packet(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(None),
+            Some(b'$') => {}
+            Some(_) => continue,
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+
+        let checksum = match (read_byte(stream)?, read_byte(stream)?) {
+            (Some(high), Some(low)) => u8::from_str_radix(&String::from_utf8_lossy(&[high, low]), 16).ok(),
+            _ => return Ok(None),
+        };
+
+        if checksum == Some(data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))) {
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    match stream.read(&mut byte) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(byte[0])),
+        Err(e) if e.kind() == ErrorKind::ConnectionReset => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn send_packet(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes())
+}
+
+// Did GDB send a Ctrl+C while the program was running? Looks without waiting
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0u8];
+    // Only takes the byte if it is the interrupt, anything else is the start of a packet
+    let result = match stream.peek(&mut byte) {
+        Ok(1) if byte[0] == CTRL_C => stream.read(&mut byte).map(|_| true),
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
@@ -0,0 +1,70 @@
+use crate::call_stack::Frame;
+use std::collections::VecDeque;
+
+pub const DEFAULT_CAPACITY: usize = 100_000; // Instructions kept unless --history says otherwise
+
+// The registers as they were before an instruction ran
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+    pub program_counter: u16,
+    pub cycles: u64,
+}
+
+// What one trip through step changed: the registers before it, and what was in memory before every write
+pub struct Delta {
+    pub registers: Registers,
+    pub writes: Vec<(u16, u8)>, // (address, old value), in the order they were written
+    pub frames: Option<Vec<Frame>>, // The call stack before, only kept if the instruction changed it
+}
+
+/*
+    A ring buffer of the last instructions run, so the debugger and the SDL front-end can go backwards.
+    Only the changes are kept, not whole copies of memory: the registers before each instruction and the old
+    value of every byte it wrote. Writes made between instructions (key presses from the front-ends) belong
+    to the instruction before them, so stepping back undoes them too.
+
+    Devices don't keep their own history, so only RAM is put back. The random byte device keeps going forward
+    too, so a program that's rewound and run again can take a different path
+*/
+pub struct History {
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History { deltas: VecDeque::new(), capacity: capacity.max(1) }
+    }
+
+    // An instruction is about to run, the oldest one is forgotten if the buffer is full
+    pub fn begin(&mut self, registers: Registers) {
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(Delta { registers, writes: Vec::new(), frames: None });
+    }
+
+    // A byte of RAM is about to be overwritten
+    pub fn write(&mut self, address: u16, old: u8) {
+        if let Some(delta) = self.deltas.back_mut() {
+            delta.writes.push((address, old));
+        }
+    }
+
+    // The call stack is about to change, so keep it as it was (only the first time for each instruction)
+    pub fn save_frames(&mut self, frames: &[Frame]) {
+        if let Some(delta) = self.deltas.back_mut() {
+            delta.frames.get_or_insert_with(|| frames.to_vec());
+        }
+    }
+
+    // The most recent instruction, taken out of the buffer
+    pub fn pop(&mut self) -> Option<Delta> {
+        self.deltas.pop_back()
+    }
+}
@@ -0,0 +1,483 @@
+use crate::via::Pins;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const COLUMNS: usize = 16;
+pub const ROWS: usize = 2;
+
+// Size of the rendered LCD in dots. Each character is 5 x 8 dots with a one dot gap, plus a border around the edge
+const BORDER: usize = 2;
+pub const DOTS_W: usize = COLUMNS * 6 - 1 + BORDER * 2;
+pub const DOTS_H: usize = ROWS * 9 - 1 + BORDER * 2;
+
+const BACKGROUND: (u8, u8, u8) = (0x7F, 0xB0, 0x2A);
+const DOT_OFF: (u8, u8, u8) = (0x74, 0xA3, 0x26);
+const DOT_ON: (u8, u8, u8) = (0x1A, 0x2A, 0x14);
+
+/*
+    The character set in the controller's ROM (the common A00 version), for codes 0x20 - 0x7F.
+    Each character is 5 columns, bit 0 is the top row. It's mostly ASCII, except for the yen sign and the two arrows at the end
+*/
+const FONT: [[u8; 5]; 96] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // )
+    [0x14, 0x08, 0x3E, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // 0
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // @
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // D
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // H
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // J
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // N
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // T
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // V
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // [
+    [0x15, 0x16, 0x7C, 0x16, 0x15], // yen
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // f
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // j
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // l
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // t
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // v
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x08, 0x2A, 0x1C, 0x08], // right arrow
+    [0x08, 0x1C, 0x2A, 0x08, 0x08], // left arrow
+];
+
+/*
+    How the LCD is hooked up to the VIA, following the breadboard tutorials.
+
+    Parallel - D0 - D7 on port B, E, RW and RS on PA7, PA6 and PA5 (the "hello world" videos)
+    Nibble - 4-bit mode with everything on port B: D4 - D7 on PB0 - PB3, RS, RW and E on PB4, PB5 and PB6 (the later videos)
+*/
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Wiring {
+    #[default]
+    Parallel,
+    Nibble,
+}
+
+/*
+    An HD44780 character LCD controller, driving a 16x2 display.
+
+    The controller acts on the falling edge of E: with RW low it takes an instruction (RS low) or a character (RS high)
+    from the data lines, with RW high it puts the busy flag and address counter (RS low) or the character at the
+    address counter (RS high) on them while E is high. Instructions finish instantly, so it's never busy.
+
+    In 4-bit mode every byte goes over D4 - D7 as two halves, high half first. It starts out in 8-bit mode,
+    and the usual function set that switches to 4-bit arrives as a single half, same as on the real chip.
+
+    Display memory is 40 characters per line, at 0x00 - 0x27 and 0x40 - 0x67. The first 16 of each line are shown,
+    counting from the display shift. A blinking cursor is shown the same as an underline cursor
+*/
+pub struct Lcd {
+    ddram: [u8; 0x80],
+    cgram: [u8; 64], // Eight custom characters, 8 rows each
+    address: u8, // Address counter
+    in_cgram: bool, // Set CGRAM address points the address counter at the custom characters until the next Set DDRAM address
+    increment: bool,
+    shift_on_write: bool,
+    shift: usize, // How far the display is shifted left (0 - 39)
+    display_on: bool,
+    cursor_on: bool,
+    eight_bit: bool,
+    two_lines: bool,
+    high_half: Option<u8>, // First half of a byte in 4-bit mode
+    reading_low_half: bool,
+    enable: bool,
+    rs: bool,
+    rw: bool,
+    changed: bool,
+}
+
+impl Default for Lcd {
+    fn default() -> Self {
+        Lcd::new()
+    }
+}
+
+impl Lcd {
+    pub fn new() -> Self {
+        Lcd {
+            ddram: [0x20; 0x80],
+            cgram: [0; 64],
+            address: 0,
+            in_cgram: false,
+            increment: true,
+            shift_on_write: false,
+            shift: 0,
+            display_on: false,
+            cursor_on: false,
+            eight_bit: true,
+            two_lines: false,
+            high_half: None,
+            reading_low_half: false,
+            enable: false,
+            rs: false,
+            rw: false,
+            changed: true,
+        }
+    }
+
+    // The levels on the control and data lines, whenever they change
+    pub fn pins(&mut self, enable: bool, rs: bool, rw: bool, data: u8) {
+        let falling_edge = self.enable && !enable;
+        self.enable = enable;
+        self.rs = rs;
+        self.rw = rw;
+
+        if !falling_edge {
+            return;
+        }
+
+        if rw {
+            self.finish_read();
+            return;
+        }
+
+        let byte = if self.eight_bit {
+            data
+        } else {
+            match self.high_half.take() {
+                Some(high) => high | (data >> 4),
+                None => {
+                    self.high_half = Some(data & 0xF0);
+                    return;
+                }
+            }
+        };
+
+        if rs {
+            self.write_data(byte);
+        } else {
+            self.instruction(byte);
+        }
+    }
+
+    // What the LCD puts on D0 - D7 while E is high for a read (in 4-bit mode, the half being read is on D4 - D7)
+    pub fn data_out(&self) -> u8 {
+        if !(self.enable && self.rw) {
+            return 0;
+        }
+
+        // Bit 7 is the busy flag, always clear
+        let value = if self.rs { self.read_memory() } else { self.address & 0x7F };
+        if self.eight_bit || !self.reading_low_half {
+            value
+        } else {
+            value << 4
+        }
+    }
+
+    // Whether anything on the display has changed since the last time this was called
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    // The text on a row, as close as the terminal gets to the LCD's characters
+    pub fn line(&self, row: usize) -> String {
+        (0..COLUMNS).map(|column| {
+            if !self.display_on {
+                return ' ';
+            }
+
+            match self.ddram[self.visible_address(row, column) as usize] {
+                0x5C => '¥',
+                0x7E => '→',
+                0x7F => '←',
+                code @ 0x20..=0x7D => code as char,
+                0x00..=0x0F => '▒', // Custom characters can't be shown as text
+                _ => ' ',
+            }
+        }).collect()
+    }
+
+    // The display as RGB, DOTS_W x DOTS_H, ready to be scaled up and shown in a window
+    pub fn render(&self) -> Vec<u8> {
+        let mut frame: Vec<u8> = [BACKGROUND.0, BACKGROUND.1, BACKGROUND.2].repeat(DOTS_W * DOTS_H);
+
+        for row in 0..ROWS {
+            for column in 0..COLUMNS {
+                let address = self.visible_address(row, column);
+                let mut glyph = self.glyph(self.ddram[address as usize]);
+
+                // Underline cursor on the bottom row of the character
+                if self.cursor_on && !self.in_cgram && address == self.address {
+                    glyph[7] = 0x1F;
+                }
+
+                for (y, bits) in glyph.iter().enumerate() {
+                    for x in 0..5 {
+                        let on = self.display_on && bits & (0x10 >> x) != 0;
+                        let color = if on { DOT_ON } else { DOT_OFF };
+                        let index = ((BORDER + row * 9 + y) * DOTS_W + BORDER + column * 6 + x) * 3;
+                        frame[index..index + 3].copy_from_slice(&[color.0, color.1, color.2]);
+                    }
+                }
+            }
+        }
+
+        frame
+    }
+
+    // A character as 8 rows of 5 dots (bit 4 is the leftmost)
+    fn glyph(&self, code: u8) -> [u8; 8] {
+        let mut rows = [0u8; 8];
+        match code {
+            0x00..=0x0F => {
+                let start = (code as usize & 0x07) * 8;
+                for (y, row) in rows.iter_mut().enumerate() {
+                    *row = self.cgram[start + y] & 0x1F;
+                }
+            }
+
+            0x20..=0x7F => {
+                let columns = FONT[code as usize - 0x20];
+                for (y, row) in rows.iter_mut().enumerate().take(7) {
+                    for (x, column) in columns.iter().enumerate() {
+                        if column & (1 << y) != 0 {
+                            *row |= 0x10 >> x;
+                        }
+                    }
+                }
+            }
+
+            _ => {}
+        }
+
+        rows
+    }
+
+    fn visible_address(&self, row: usize, column: usize) -> u8 {
+        let line_start = if row == 0 { 0x00 } else { 0x40 };
+        line_start + ((column + self.shift) % 40) as u8
+    }
+
+    fn instruction(&mut self, data: u8) {
+        if data & 0x80 != 0 {
+            // Set DDRAM address
+            self.address = self.fold_address(data & 0x7F);
+            self.in_cgram = false;
+        } else if data & 0x40 != 0 {
+            // Set CGRAM address
+            self.address = data & 0x3F;
+            self.in_cgram = true;
+        } else if data & 0x20 != 0 {
+            // Function set (the font size bit only matters for 1-line displays)
+            self.eight_bit = data & 0x10 != 0;
+            self.two_lines = data & 0x08 != 0;
+            self.high_half = None;
+            if !self.in_cgram {
+                self.address = self.fold_address(self.address);
+            }
+        } else if data & 0x10 != 0 {
+            // Move the cursor or shift the whole display, right if bit 2 is set
+            let right = data & 0x04 != 0;
+            if data & 0x08 != 0 {
+                self.shift_display(!right);
+            } else {
+                self.move_address(right);
+            }
+        } else if data & 0x08 != 0 {
+            // Display on/off, cursor on/off (bit 0, blinking, is ignored)
+            self.display_on = data & 0x04 != 0;
+            self.cursor_on = data & 0x02 != 0;
+        } else if data & 0x04 != 0 {
+            // Entry mode set
+            self.increment = data & 0x02 != 0;
+            self.shift_on_write = data & 0x01 != 0;
+        } else if data & 0x02 != 0 {
+            // Return home
+            self.address = 0;
+            self.in_cgram = false;
+            self.shift = 0;
+        } else if data & 0x01 != 0 {
+            // Clear display
+            self.ddram = [0x20; 0x80];
+            self.address = 0;
+            self.in_cgram = false;
+            self.increment = true;
+            self.shift = 0;
+        }
+
+        self.changed = true;
+    }
+
+    fn write_data(&mut self, data: u8) {
+        if self.in_cgram {
+            self.cgram[self.address as usize] = data;
+        } else {
+            self.ddram[self.address as usize] = data;
+            if self.shift_on_write {
+                self.shift_display(self.increment);
+            }
+        }
+
+        self.move_address(self.increment);
+        self.changed = true;
+    }
+
+    fn read_memory(&self) -> u8 {
+        if self.in_cgram {
+            self.cgram[self.address as usize]
+        } else {
+            self.ddram[self.address as usize]
+        }
+    }
+
+    // Reading a character moves the address counter along, once the whole byte has been read
+    fn finish_read(&mut self) {
+        if !self.eight_bit {
+            self.reading_low_half = !self.reading_low_half;
+            if self.reading_low_half {
+                return;
+            }
+        }
+
+        if self.rs {
+            self.move_address(self.increment);
+        }
+    }
+
+    /*
+    DDRAM only goes up to 0x4F with one line, and with two it's 0x00 - 0x27 and 0x40 - 0x67. Addresses past
+    the end of a line fold back into it, the way the HD44780 wraps them
+    */
+    fn fold_address(&self, address: u8) -> u8 {
+        if self.two_lines {
+            (address & 0x40) | ((address & 0x3F) % 40)
+        } else {
+            address % 80
+        }
+    }
+
+    fn move_address(&mut self, forward: bool) {
+        self.address = if self.in_cgram {
+            if forward { self.address.wrapping_add(1) & 0x3F } else { self.address.wrapping_sub(1) & 0x3F }
+        } else if self.two_lines {
+            // The end of the first line goes to the second line and the other way around
+            match (forward, self.address) {
+                (true, 0x27) => 0x40,
+                (true, 0x67) => 0x00,
+                (false, 0x00) => 0x67,
+                (false, 0x40) => 0x27,
+                (true, address) => address.wrapping_add(1) & 0x7F,
+                (false, address) => address.wrapping_sub(1) & 0x7F,
+            }
+        } else if forward {
+            (self.address + 1) % 80
+        } else {
+            (self.address + 79) % 80
+        };
+
+        self.changed |= self.cursor_on;
+    }
+
+    // Shifting left moves everything on the display one character to the left
+    fn shift_display(&mut self, left: bool) {
+        self.shift = if left { (self.shift + 1) % 40 } else { (self.shift + 39) % 40 };
+    }
+}
+
+// The LCD as seen from the VIA's ports. It's shared, so the front-end can keep drawing it after it's wired up
+pub struct LcdPins {
+    lcd: Rc<RefCell<Lcd>>,
+    wiring: Wiring,
+}
+
+impl LcdPins {
+    pub fn new(lcd: Rc<RefCell<Lcd>>, wiring: Wiring) -> Self {
+        LcdPins { lcd, wiring }
+    }
+}
+
+impl Pins for LcdPins {
+    fn drive(&mut self, port_a: u8, port_b: u8) {
+        let mut lcd = self.lcd.borrow_mut();
+        match self.wiring {
+            Wiring::Parallel => lcd.pins(port_a & 0x80 != 0, port_a & 0x20 != 0, port_a & 0x40 != 0, port_b),
+            Wiring::Nibble => lcd.pins(port_b & 0x40 != 0, port_b & 0x10 != 0, port_b & 0x20 != 0, port_b << 4),
+        }
+    }
+
+    fn sense_b(&mut self) -> u8 {
+        let data = self.lcd.borrow().data_out();
+        match self.wiring {
+            Wiring::Parallel => data,
+            Wiring::Nibble => data >> 4,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+/*
+    Everything but the command line front-end lives in the library, so the fuzz targets (and anything else that
+    wants a 6502) can use the CPU and the script reader without going through main
+*/
+pub mod processor;
+pub mod opcode_info;
+pub mod snake;
+pub mod rng;
+pub mod cli;
+pub mod movie;
+pub mod screen;
+pub mod capture;
+pub mod disassembler;
+pub mod tui;
+pub mod devices;
+pub mod console;
+pub mod apple1;
+pub mod via;
+pub mod acia;
+pub mod lcd;
+pub mod breadboard;
+pub mod loader;
+pub mod symbols;
+pub mod debugger;
+pub mod source_map;
+pub mod profiler;
+pub mod coverage;
+pub mod watch;
+pub mod gdb;
+pub mod expression;
+pub mod history;
+pub mod call_stack;
+pub mod dump;
+pub mod expect;
+pub mod script;
+pub mod fuzzing;
+mod tests;
+#[cfg(test)]
+mod reference;
+#[cfg(test)]
+mod differential;
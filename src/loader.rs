@@ -0,0 +1,281 @@
+use crate::processor::CPU;
+use crate::source_map::SourceMap;
+use std::path::Path;
+
+pub const PROGRAM_START: u16 = 0x0600; // Where scripts (and raw binaries, unless told otherwise) are loaded
+
+// Bytes that go at one address
+pub struct Segment {
+    pub address: u16,
+    pub data: Vec<u8>,
+}
+
+/*
+    A program ready to go into memory: one or more segments, and maybe the address it starts at
+    (Intel HEX and S-record files can say). When it doesn't say, the program starts at the first segment,
+    unless it brings its own reset vector, in which case that is used. Scripts also bring the lines every byte came from
+*/
+#[derive(Default)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub start: Option<u16>,
+    pub source: SourceMap,
+}
+
+impl Image {
+    // A program at 0x0600, like the scripts
+    pub fn program(data: &[u8]) -> Self {
+        let mut image = Image::default();
+        image.add(PROGRAM_START, data);
+        image
+    }
+
+    // Bytes that follow straight on from the last segment are added to it
+    pub fn add(&mut self, address: u16, data: &[u8]) {
+        if let Some(last) = self.segments.last_mut() {
+            if last.address as usize + last.data.len() == address as usize {
+                last.data.extend_from_slice(data);
+                return;
+            }
+        }
+
+        self.segments.push(Segment { address, data: data.to_vec() });
+    }
+
+    fn covers(&self, address: u16) -> bool {
+        self.segments.iter().any(|segment| address >= segment.address && ((address - segment.address) as usize) < segment.data.len())
+    }
+
+    // Where execution should begin, None if the image's own reset vector says
+    pub fn entry_point(&self) -> Option<u16> {
+        if self.start.is_some() {
+            return self.start;
+        }
+
+        if self.covers(0xFFFC) && self.covers(0xFFFD) {
+            None
+        } else {
+            self.segments.first().map(|segment| segment.address)
+        }
+    }
+
+    // Copy every segment into memory and point the reset vector (and the program counter) at the entry point, same as CPU::load
+    pub fn load_into(&self, cpu: &mut CPU) {
+        cpu.source = self.source.clone();
+        for segment in &self.segments {
+            cpu.load_at(segment.address, &segment.data);
+        }
+
+        if let Some(entry) = self.entry_point() {
+            cpu.write_memory_u16(0xFFFC, entry);
+        }
+        cpu.program_counter = cpu.read_memory_u16(0xFFFC);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Script, // Space separated hex pairs, the original format
+    Binary, // Raw bytes, loaded at an origin
+    IntelHex,
+    SRecord,
+    Prg, // Commodore program, the first two bytes are the load address
+}
+
+/*
+    Figure out what kind of file this is, by the extension first and by looking at what's inside if that doesn't say.
+    .prg files can only be told apart by their extension, anything that doesn't look like text is loaded as a raw binary
+*/
+pub fn detect(path: &Path, bytes: &[u8]) -> Format {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+
+    match extension.as_str() {
+        "txt" => return Format::Script,
+        "hex" | "ihx" | "ihex" => return Format::IntelHex,
+        "srec" | "s19" | "s28" | "s37" | "mot" => return Format::SRecord,
+        "prg" => return Format::Prg,
+        "bin" | "rom" | "out" => return Format::Binary,
+        _ => {}
+    }
+
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return Format::Binary,
+    };
+
+    let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+    let first = match lines.next() {
+        Some(line) => line,
+        None => return Format::Binary,
+    };
+
+    if first.starts_with(':') {
+        Format::IntelHex
+    } else if first.len() > 1 && first.starts_with('S') && first.as_bytes()[1].is_ascii_digit() {
+        Format::SRecord
+    } else if text.lines().all(script_line) {
+        Format::Script
+    } else {
+        Format::Binary
+    }
+}
+
+// Hex pairs with // comments, or a directive (* = $C000, @C000, .byte and the like)
+fn script_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with(['*', '@', '.']) || line.split('/').next().unwrap_or("").chars().all(|ch| ch.is_ascii_hexdigit() || ch.is_whitespace())
+}
+
+// Read any format except scripts (those go through the script reader). Raw binaries are loaded at the origin
+pub fn parse(format: Format, bytes: &[u8], origin: u16) -> Result<Image, String> {
+    match format {
+        Format::Binary => binary(bytes, origin),
+        Format::Prg => prg(bytes),
+        Format::IntelHex => intel_hex(&text(bytes)?),
+        Format::SRecord => s_record(&text(bytes)?),
+        Format::Script => Err(String::from("Scripts aren't read by the binary loaders")),
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| String::from("File isn't text"))
+}
+
+// Make sure data at an address doesn't run off the end of memory
+fn check_fits(address: u32, length: usize) -> Result<u16, String> {
+    if address as usize + length > 0x10000 {
+        Err(format!("{} bytes at ${:04X} don't fit in 64K", length, address))
+    } else {
+        Ok(address as u16)
+    }
+}
+
+pub fn binary(bytes: &[u8], origin: u16) -> Result<Image, String> {
+    let mut image = Image::default();
+    image.add(check_fits(origin as u32, bytes.len())?, bytes);
+    Ok(image)
+}
+
+pub fn prg(bytes: &[u8]) -> Result<Image, String> {
+    if bytes.len() < 2 {
+        return Err(String::from("PRG file is too short to have a load address"));
+    }
+
+    let address = u16::from_le_bytes([bytes[0], bytes[1]]);
+    binary(&bytes[2..], address)
+}
+
+// Pairs of hex digits into bytes
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len()).step_by(2).map(|i| text.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok())).collect()
+}
+
+/*
+    Intel HEX, one record per line:  :LLAAAATT<data>CC
+    LL is the number of data bytes, AAAA the address, TT the record type and CC the checksum (all the bytes
+    of the record, checksum included, add up to 0). Segment and linear address records are followed,
+    as long as everything still lands in the first 64K
+*/
+pub fn intel_hex(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+    let mut base: u32 = 0;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| format!("Intel HEX line {}: {}", number + 1, message);
+
+        let record = line.strip_prefix(':').ok_or_else(|| error("records start with ':'"))?;
+        let bytes = hex_bytes(record).ok_or_else(|| error("invalid hex digits"))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("record length doesn't match its byte count"));
+        }
+
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("checksum mismatch"));
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            0x00 => image.add(check_fits(base + address, data.len()).map_err(|e| error(&e))?, data),
+            0x01 => break, // End of file
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+
+            // Start segment address (CS:IP) and start linear address
+            0x03 if data.len() == 4 => {
+                let start = ((u16::from_be_bytes([data[0], data[1]]) as u32) << 4) + u16::from_be_bytes([data[2], data[3]]) as u32;
+                image.start = Some(check_fits(start, 1).map_err(|e| error(&e))?);
+            }
+            0x05 if data.len() == 4 => {
+                image.start = Some(check_fits(u32::from_be_bytes([data[0], data[1], data[2], data[3]]), 1).map_err(|e| error(&e))?);
+            }
+
+            kind => return Err(error(&format!("unsupported record type {:02X}", kind))),
+        }
+    }
+
+    Ok(image)
+}
+
+/*
+    Motorola S-record, one record per line:  S<type><count><address><data><checksum>
+    The count covers the address, data and checksum bytes, and the checksum is the ones' complement of the sum
+    of all of them (count included). S1/S2/S3 hold data with 2, 3 or 4 byte addresses, S7/S8/S9 the start address.
+    S0 (header) and S5/S6 (record counts) are skipped
+*/
+pub fn s_record(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| format!("S-record line {}: {}", number + 1, message);
+
+        let record = line.strip_prefix('S').ok_or_else(|| error("records start with 'S'"))?;
+        let kind = record.chars().next().ok_or_else(|| error("missing record type"))?;
+        let bytes = record.get(1..).and_then(hex_bytes).ok_or_else(|| error("invalid hex digits"))?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error("record length doesn't match its byte count"));
+        }
+
+        let sum = bytes[..bytes.len() - 1].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum ^ 0xFF != bytes[bytes.len() - 1] {
+            return Err(error("checksum mismatch"));
+        }
+
+        let address_length = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(error(&format!("unsupported record type S{}", kind))),
+        };
+
+        if bytes.len() < address_length + 2 {
+            return Err(error("record is too short for its address"));
+        }
+
+        let address = bytes[1..=address_length].iter().fold(0u32, |address, byte| (address << 8) | *byte as u32);
+        let data = &bytes[address_length + 1..bytes.len() - 1];
+
+        match kind {
+            '1' | '2' | '3' => image.add(check_fits(address, data.len()).map_err(|e| error(&e))?, data),
+            '7' | '8' | '9' => image.start = Some(check_fits(address, 1).map_err(|e| error(&e))?),
+            _ => {}
+        }
+    }
+
+    Ok(image)
+}
@@ -1,15 +1,88 @@
-mod processor;
-mod tests;
-mod opcode_info;
-mod snake;
-use crate::processor::CPU;
+use astrobyte::processor::CPU;
+use astrobyte::{apple1, breadboard, cli, dump, expect, loader, snake, tui, watch};
+use astrobyte::script::read_script;
+use astrobyte::cli::{Machine, Options};
+use astrobyte::movie::Movie;
+use astrobyte::capture::Capture;
+use astrobyte::console::ConsoleDevice;
+use astrobyte::via::Via;
+use astrobyte::acia::Acia;
+use astrobyte::loader::{Format, Image};
+use astrobyte::symbols::Symbols;
+use astrobyte::debugger::Debugger;
+use astrobyte::gdb::GdbStub;
+use astrobyte::screen::{check_screen_state, FRAME_SIZE};
 use std::{env, fs};
-use std::io::{self, stdout, BufReader, BufRead, Write};
-use std::path::PathBuf;
+use std::io::{self, stdout, Write};
+use std::path::Path;
+use std::net::TcpListener;
 #[allow(unused)]
 
 fn main() {
 
+    // Read the command line options first, so mistakes are caught before the menu shows up
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Replaying a movie doesn't need a window or the menu, it just runs snake headlessly and checks the result
+    if let Some(path) = &options.replay {
+        match replay_movie(path, &options) {
+            Ok(()) => {
+                println!("Replay matched the recording");
+                std::process::exit(0);
+            }
+
+            Err(e) => {
+                eprintln!("Replay failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Check the scripts' //! expect lines instead of running anything interactively
+    if let Some(path) = &options.test {
+        match expect::run_tests(path, options.seed.unwrap_or(0)) {
+            Ok(passed) => std::process::exit(if passed { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // The built-in machines bring their own ROM and take over the terminal
+    if let Some(machine) = options.machine {
+        let result = match machine {
+            Machine::Apple1 => apple1::run_apple1(&options),
+            Machine::Breadboard => breadboard::run_breadboard(&options),
+        };
+
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+
+        std::process::exit(0);
+    }
+
+    // A program was given on the command line, so skip the menu and just run it
+    if let Some(path) = &options.program {
+        match read_program(path, &options) {
+            Ok(image) => run_program(&image, &options),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+
+        std::process::exit(0);
+    }
+
     // Introduction logo
     print!("\n ---------------------------------------------------------------------------------------------------------\n");
     print!(" ▄▄▄▄▄▄▄▄▄▄▄  ▄▄▄▄▄▄▄▄▄▄▄   ▄▄▄▄▄▄▄▄▄   ▄▄▄▄▄▄▄▄▄▄▄ 
@@ -37,7 +110,7 @@ fn main() {
     choice = choice.trim().to_lowercase();
 
     if choice == "snake" { // Run snake
-        snake::run_snake();
+        run_snake(&options);
         std::process::exit(0);  
 
     } else if choice != "script" { // Run user script in a text file
@@ -48,9 +121,10 @@ fn main() {
         // If user has chosen to run their script in a text file, this part of the program executes instead
         print!("\n ---------------------------------------------------------------------------------------------------------\n");
 
-        println!("\nEnter the text file name of the 6502 script you want to run and press enter.\n\nFor example: script.txt. You can just use the built-in script.txt file if you want.\n
-Your code must be in pairs of hex digits, with spaces between every two digits (or a byte).\n
-Remember that addresses are written in little-endian style. The code executes starting at memory 0x600.\n
+        println!("\nEnter the file name of the 6502 program you want to run and press enter.\n\nFor example: script.txt. You can just use the built-in script.txt file if you want.\n
+Scripts (.txt) must be in pairs of hex digits, with spaces between every two digits (or a byte).\n
+Remember that addresses are written in little-endian style. Scripts execute starting at memory 0x600.\n
+Raw binaries (.bin), Intel HEX (.hex), S-records (.s19, .srec) and Commodore .prg files can be run too.\n
 After each instruction (not values), relevant processor information will be printed.\n
 Alternatively, enter q to quit. \n");
 
@@ -75,12 +149,7 @@ Alternatively, enter q to quit. \n");
         // Remove any newlines
         let filename = user_input.trim();
 
-        if !filename.ends_with(".txt") {
-            println!("\nPlease provide a valid .txt file");
-            continue;
-        }
-
-        // Get full path of text file
+        // Get full path of the file
         let filepath = current_directory.join(filename);
 
         if !filepath.exists() {
@@ -88,29 +157,22 @@ Alternatively, enter q to quit. \n");
             continue;
         }
 
-        let mut instructions: Vec<String> = Vec::new();
-
-        // Get all of our instructions from reading the text file that the user entered
-        // We should get a vector of hex digits
-        match read_script(filepath) {
-            Ok(result) => { 
-                instructions = result;
-
-            }
-
+        // Work out what kind of file it is and read it into memory segments
+        let image = match read_program(&filepath, &options) {
+            Ok(image) => image,
             Err(e) => {
-                eprintln!("Failed to read script: {}", e);
+                eprintln!("Failed to read {}: {}", filename, e);
                 std::process::exit(1);
             }
-        }
+        };
         
-        // Our program works with uu8 elements, so now we have to convert our hex strings into u8 
-        let program_vec: Vec<u8> = string_to_u8_hex(instructions);
-        
-        // Now that we have an actual usable vector, load it into the program and execute it!
-        let mut cpu = CPU::new();
-        cpu.print_mode = true; // We want to print the info after each opcode
-        cpu.load_and_execute(program_vec);
+        // Now that we have an actual usable program, load it into memory and execute it!
+        run_program(&image, &options);
+
+        // The console reads stdin in the background, so it would swallow the answer
+        if options.console.is_some() {
+            break;
+        }
 
         // Ask user if they want to run another script
         println!("\nRun again with another script? y/n \n");
@@ -131,155 +193,137 @@ Alternatively, enter q to quit. \n");
     
 }
 
-// Take in the 6502 assembly text file, splits it into different lines, and processes it with helper function
-// Returns a Result<Vec<String>> (all the instructions in the text file)
-fn read_script(filepath: PathBuf) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    // The vector with all the instructions from the script
-    let mut instructions: Vec<String> = Vec::new();
-
-    // Read text file
-    let file = fs::File::open(&filepath)?;
-    let parser = BufReader::new(file);
-    for line in parser.lines() {
-        match line {
-            Ok(line) => {
-
-                match process_line(&line) {
-                    Ok(Some(hex_arr)) => {
-                        // Add instructions from the line to our instructions vector
-                        instructions.extend(hex_arr);
-                    }
-    
-                    Ok(None) => {
-                        // Do nothing, no valid instructions in this line
-                    }
-    
-                    Err(_) => {
-                        eprintln!("\nError processing line: {}", line);
-                        std::process::exit(1);
-                    }
-                }
-            }
-
-            // Unexpected input
-            Err(_) => {
-                eprintln!("\nError!");
-                std::process::exit(1);
-            }
+// Runs a script, either printing the registers after every instruction, stepping through it in the debugger (--debug) or GDB (--gdb), or drawing the screen in the terminal (--tui)
+fn run_program(image: &Image, options: &Options) {
+    if options.tui {
+        if let Err(e) = tui::run_tui(image, options.program_random_device(), options) {
+            eprintln!("{}", e);
         }
-        
+        return;
     }
 
-    Ok(instructions)
-}
+    let mut cpu = CPU::new();
+    cpu.print_mode = !options.no_trace; // We want to print the info after each opcode (unless told otherwise)
+    cpu.random = options.program_random_device();
+    options.configure(&mut cpu);
 
-// Process each line character by character, checking for valid input, and then returning
-// a Result<Option<Vec<String>> containing those valid hex pair inputs
-fn process_line(line: &str) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
-    let mut hex_arr: Vec<String> = Vec::new();
-    let mut current_pair = String::new();
+    if let Some((output_address, input_address)) = options.console {
+        cpu.attach(Box::new(ConsoleDevice::stdio(output_address, input_address)));
+    }
 
-    // Line is empty, skip it
-    if line.trim().is_empty() {
-        return Ok(None);
+    if let Some(address) = options.via {
+        cpu.attach(Box::new(Via::new(address)));
     }
 
-    for ch in line.chars() {
-        // Check for comment in line, if so, skip it
-        if ch == '/' {
-            if current_pair.len() >= 1 {
-                eprint!("\nInvalid input in line: {}.\n\nPlease don't put comments between hex digits\n", line);
-                std::process::exit(1);
+    if let Some((address, backend)) = &options.acia {
+        match Acia::with_backend(*address, backend) {
+            Ok(acia) => cpu.attach(Box::new(acia)),
+            Err(e) => {
+                eprintln!("Failed to set up the ACIA: {}", e);
+                return;
             }
+        }
+    }
 
-            break;
+    match Symbols::from_files(&options.symbols) {
+        Ok(symbols) => cpu.symbols = symbols,
+        Err(e) => {
+            eprintln!("Failed to read symbols: {}", e);
+            return;
         }
+    }
 
-        else if ch.is_ascii_hexdigit() {
-            // We only accept pairs of hex digits, so max pair length can only be 2
-            if current_pair.len() >= 2 {
-                eprintln!("\nInvalid input in line: {}.\n\nHex digits were not grouped in pairs or separated by a whitespace", line);
-                std::process::exit(1);
-            }
+    image.load_into(&mut cpu);
+    let before = dump::snapshots(&cpu, &options.diffs); // For --diff
 
-            // Add digit to pair
-            current_pair.push(ch);
+    // GDB takes over from here, it sets its own breakpoints
+    if let Some(port) = options.gdb {
+        let result = TcpListener::bind(("127.0.0.1", port)).and_then(|listener| GdbStub::new().serve(&mut cpu, listener));
+        if let Err(e) = result {
+            eprintln!("GDB stub failed: {}", e);
         }
+        cli::finish(&cpu, options, &before);
+        return;
+    }
 
-        // Whitespace, make sure there aren't any whitespaces between single hex digits
-        else if ch.is_whitespace() {
-            if current_pair.len() != 0 {
-                eprintln!("\nInvalid input in line: {}.\n\nSingle hex digits are not valid", line);
-                std::process::exit(1);
-            }
-        }
+    if !options.debug {
+        cpu.execute();
+        cli::finish(&cpu, options, &before);
+        return;
+    }
 
-        // Anything else
-        else {
-            eprintln!("\nInvalid input in line: {}", line);
-            std::process::exit(1);
-        }
+    // Keep a history so the program can be stepped backwards
+    cpu.history = Some(options.history());
 
-        // We've found a pair, add it to our result array
-        if current_pair.len() == 2 {
-            hex_arr.push(current_pair.clone());
-            current_pair.clear();
+    // Breakpoints can be labels, so they wait until the symbols are in
+    let mut debugger = Debugger::new();
+    for text in &options.breakpoints {
+        if let Err(e) = debugger.add(&mut cpu, watch::EXECUTE, text) {
+            eprintln!("Invalid breakpoint {}: {}", text, e);
+            return;
         }
     }
 
-    Ok(Some(hex_arr))
+    if let Err(e) = debugger.run(&mut cpu, io::stdin().lock(), &mut stdout()) {
+        eprintln!("{}", e);
+    }
+    cli::finish(&cpu, options, &before);
 }
 
-// Takes in a vector of hex strings and converts it into a vector of hex u8, returning that
-fn string_to_u8_hex(instructions: Vec<String>) -> Vec<u8>
-{
-    let mut program_vec: Vec<u8> = Vec::new();
+// Snake runs in the SDL window, or in the terminal with --tui (or if this was built without SDL)
+fn run_snake(options: &Options) {
+    #[cfg(feature = "sdl")]
+    if !options.tui {
+        snake::run_snake(options);
+        return;
+    }
 
-    // Now we have to convert 
-    for hex_pair in instructions {
+    #[cfg(not(feature = "sdl"))]
+    if !options.tui {
+        println!("Built without SDL, running snake in the terminal instead");
+    }
 
-        // Account for case insensitivity
-        let lower_hex_pair = hex_pair.to_lowercase();
-        let mut hex_result = 0;
-        let mut power: u8 = 1;
+    if let Err(e) = tui::run_tui(&Image::program(snake::SNAKE_OPCODES), Some(options.random_device()), options) {
+        eprintln!("{}", e);
+    }
+}
 
-          // Convert each array element into an actual, usable decimal value
-        for digit in lower_hex_pair.chars() {
-        
-            let value: u8 = match digit {
-                '0' => 0,
-                '1' => 1,
-                '2' => 2,
-                '3' => 3,
-                '4' => 4,
-                '5' => 5,
-                '6' => 6,
-                '7' => 7,
-                '8' => 8,
-                '9' => 9,
-                'a' => 10,
-                'b' => 11,
-                'c' => 12,
-                'd' => 13,
-                'e' => 14,
-                'f' => 15,
-                _ => 16,
-            };
-
-              // The last element of the array ends with \r\n, so this case is for when the code reaches that part
-            if value == 16 {
-                break
-            }
+// Replays a movie of snake without a window, capturing the screen (and profiling) along the way if asked to
+fn replay_movie(path: &Path, options: &Options) -> Result<(), String> {
+    let movie = Movie::load(path)?;
+    let mut capture = Capture::new(options)?;
+    let mut screen_state = [0u8; FRAME_SIZE];
 
-            hex_result += value * u8::pow(16, power as u32);
+    let mut cpu = CPU::new();
+    options.configure(&mut cpu);
+    cpu.symbols = Symbols::from_files(&options.symbols)?;
+    let before = dump::snapshots(&cpu, &options.diffs); // Blank memory, the movie loads snake itself
 
-            if power != 0 {
-                power -= 1;
-            } 
+    let result = movie.replay(&mut cpu, snake::SNAKE_OPCODES, |frame, cpu| {
+        if check_screen_state(cpu, &mut screen_state) {
+            capture.frame_changed(frame, &screen_state);
         }
+    });
 
-        program_vec.push(hex_result);    
+    if let Some(error) = cpu.calls.error.take() {
+        eprintln!("{}", error);
     }
+    capture.finish(&screen_state);
+    cli::finish(&cpu, options, &before);
+    result
+}
+
+// Scripts go through the script reader, everything else through the loader that matches its format
+fn read_program(path: &Path, options: &Options) -> Result<Image, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
 
-    program_vec
-}
\ No newline at end of file
+    match loader::detect(path, &bytes) {
+        Format::Script => {
+            let text = String::from_utf8(bytes).map_err(|_| String::from("Scripts should be plain text"))?;
+            let file = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            read_script(&text, &file).map_err(|e| e.to_string())
+        }
+
+        format => loader::parse(format, &bytes, options.origin.unwrap_or(loader::PROGRAM_START)),
+    }
+}
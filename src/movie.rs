@@ -0,0 +1,196 @@
+use crate::processor::CPU;
+use crate::rng::RandomDevice;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const KEY_ADDRESS: u16 = 0xFF; // Last pressed key is written here
+
+/*
+    Movie files record everything that can make two runs of an interactive program different:
+    the seed of the random byte device and every key written into 0xFF, along with the frame it happened on.
+    A frame is one trip through the callback (so one instruction). When the recording ends, hashes of
+    the framebuffer (0x0200 - 0x05FF) and of all of memory are saved, so a replay can check that it ended up
+    in exactly the same place.
+
+    The file is plain text, one entry per line:
+
+    seed 1234
+    full-range 0
+    input 5120 77       // Frame 5120, key 0x77 (w)
+    frames 20000
+    framebuffer 8a3fe0c1d2b4a597
+    memory 0c1d2b4a5978a3fe
+*/
+pub struct Movie {
+    pub seed: u64,
+    pub full_range: bool,
+    pub inputs: Vec<(u64, u8)>, // (frame, key)
+    pub frames: u64, // How many frames the recording lasted
+    pub framebuffer_hash: u64,
+    pub memory_hash: u64,
+}
+
+impl Movie {
+    // Start a new recording for a run that uses this random device
+    pub fn new(random: &RandomDevice) -> Self {
+        Movie {
+            seed: random.seed,
+            full_range: random.full_range,
+            inputs: Vec::new(),
+            frames: 0,
+            framebuffer_hash: 0,
+            memory_hash: 0,
+        }
+    }
+
+    pub fn record_input(&mut self, frame: u64, key: u8) {
+        self.inputs.push((frame, key));
+    }
+
+    // Recording is over, remember where the program ended up
+    pub fn finish(&mut self, frames: u64, cpu: &CPU) {
+        self.frames = frames;
+        self.framebuffer_hash = framebuffer_hash(cpu);
+        self.memory_hash = memory_hash(cpu);
+    }
+
+    pub fn random_device(&self) -> RandomDevice {
+        RandomDevice::new(self.seed, self.full_range)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut text = String::new();
+        text.push_str(&format!("seed {}\n", self.seed));
+        text.push_str(&format!("full-range {}\n", self.full_range as u8));
+
+        for (frame, key) in &self.inputs {
+            text.push_str(&format!("input {} {:02x}\n", frame, key));
+        }
+
+        text.push_str(&format!("frames {}\n", self.frames));
+        text.push_str(&format!("framebuffer {:016x}\n", self.framebuffer_hash));
+        text.push_str(&format!("memory {:016x}\n", self.memory_hash));
+
+        fs::write(path, text)
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read movie file: {}", e))?;
+        let mut movie = Movie::new(&RandomDevice::new(0, false));
+
+        for (number, line) in text.lines().enumerate() {
+            // Comments and empty lines are skipped, same as in scripts
+            let line = line.split("//").next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || format!("Invalid movie file, line {}: {}", number + 1, line);
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            match words.as_slice() {
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| invalid())?,
+                ["full-range", flag] => movie.full_range = *flag == "1",
+                ["input", frame, key] => {
+                    let frame = frame.parse().map_err(|_| invalid())?;
+                    let key = u8::from_str_radix(key, 16).map_err(|_| invalid())?;
+                    movie.record_input(frame, key);
+                }
+                ["frames", frames] => movie.frames = frames.parse().map_err(|_| invalid())?,
+                ["framebuffer", hash] => movie.framebuffer_hash = u64::from_str_radix(hash, 16).map_err(|_| invalid())?,
+                ["memory", hash] => movie.memory_hash = u64::from_str_radix(hash, 16).map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(movie)
+    }
+
+    /*
+        Run the program again without a window, feeding in the recorded keys on the same frames they were
+        pressed. Since the random device uses the recorded seed, the run should end in exactly the same state.
+        Returns an error describing the mismatch if it doesn't. The call is made with the frame number
+        and the CPU after every frame (to capture the screen, for example). The CPU can come with extra things
+        attached (like a profiler), otherwise a fresh CPU::new() will do
+    */
+    pub fn replay<F>(&self, cpu: &mut CPU, program: &[u8], mut call: F) -> Result<(), String> where F: FnMut(u64, &CPU), {
+        cpu.load(&program.to_vec());
+        cpu.random = Some(self.random_device());
+
+        let mut frame: u64 = 0;
+        let mut next_input = 0;
+
+        while frame < self.frames && cpu.step() {
+            frame += 1;
+
+            while next_input < self.inputs.len() && self.inputs[next_input].0 == frame {
+                cpu.write_memory_u8(KEY_ADDRESS, self.inputs[next_input].1);
+                next_input += 1;
+            }
+
+            call(frame, cpu);
+        }
+
+        if frame != self.frames {
+            return Err(format!("Program ended after {} frames, but the recording lasted {}", frame, self.frames));
+        }
+
+        let framebuffer = framebuffer_hash(cpu);
+        if framebuffer != self.framebuffer_hash {
+            return Err(format!("Framebuffer hash is {:016x}, expected {:016x}", framebuffer, self.framebuffer_hash));
+        }
+
+        let memory = memory_hash(cpu);
+        if memory != self.memory_hash {
+            return Err(format!("Memory hash is {:016x}, expected {:016x}", memory, self.memory_hash));
+        }
+
+        Ok(())
+    }
+}
+
+// A movie being recorded for --record, the front-ends hand it every key press and it's saved to the file when the run ends
+pub struct Recording {
+    movie: Movie,
+    path: PathBuf,
+}
+
+impl Recording {
+    pub fn new(path: &Path, random: &RandomDevice) -> Self {
+        Recording {
+            movie: Movie::new(random),
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn record_input(&mut self, frame: u64, key: u8) {
+        self.movie.record_input(frame, key);
+    }
+
+    pub fn finish(&mut self, frames: u64, cpu: &CPU) {
+        self.movie.finish(frames, cpu);
+        match self.movie.save(&self.path) {
+            Ok(()) => println!("Movie saved to {}", self.path.display()),
+            Err(e) => eprintln!("Failed to save movie: {}", e),
+        }
+    }
+}
+
+// FNV-1a, it's tiny and (unlike the standard library hasher) always gives the same result. Both ends are included
+fn hash_memory(cpu: &CPU, start: u16, end: u16) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for address in start..=end {
+        hash ^= cpu.peek_memory_u8(address) as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash
+}
+
+pub fn framebuffer_hash(cpu: &CPU) -> u64 {
+    hash_memory(cpu, 0x0200, 0x05FF)
+}
+
+pub fn memory_hash(cpu: &CPU) -> u64 {
+    hash_memory(cpu, 0x0000, 0xFFFF)
+}
@@ -67,7 +67,7 @@ lazy_static! {
         Opcode::new("CLI", AddressingMode::Implied, 0x58, 1, 2),                Opcode::new("CLV", AddressingMode::Implied, 0xB8, 1, 2),
 
         Opcode::new("CMP", AddressingMode::Immediate, 0xC9, 2, 2),              Opcode::new("CMP", AddressingMode::ZeroPage, 0xC5, 2, 3),
-        Opcode::new("CMP", AddressingMode::ZeroPageX, 0xD5, 2, 3),              Opcode::new("CMP", AddressingMode::Absolute, 0xCD, 2, 4),
+        Opcode::new("CMP", AddressingMode::ZeroPageX, 0xD5, 2, 3),              Opcode::new("CMP", AddressingMode::Absolute, 0xCD, 3, 4),
         Opcode::new("CMP", AddressingMode::AbsoluteX, 0xDD, 3, 4), /* + 1 */    Opcode::new("CMP", AddressingMode::AbsoluteY, 0xD9, 3, 4), // + 1
         Opcode::new("CMP", AddressingMode::IndexedIndirect, 0xC1, 2, 6),        Opcode::new("CMP", AddressingMode::IndirectIndexed, 0xD1, 2, 5), // + 1
 
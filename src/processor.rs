@@ -2,6 +2,15 @@
 #![allow(dead_code)]
 use core::panic;
 use crate::opcode_info::OPCODES_TABLE;
+use crate::rng::{RandomDevice, RNG_ADDRESS};
+use crate::devices::Device;
+use crate::symbols::Symbols;
+use crate::source_map::SourceMap;
+use crate::profiler::Profiler;
+use crate::coverage::{self, Coverage};
+use crate::watch::{self, Watches};
+use crate::history::{Delta, History, Registers};
+use crate::call_stack::{CallStack, Frame, FrameKind, Problem};
 
 const STACK_START: u16 = 0x0100; // Memory allocated for stack is 0100 - 01FF
 
@@ -44,7 +53,26 @@ pub struct CPU {
     pub stack_pointer: u8, // Points to the top of the stack. The stack for the 6502 grows top to bottom. Memory allocated for stack pointer is 0x0100 - 0x01FF
     pub info: Vec<u16>, // To store our info after the program terminates
     pub print_mode: bool,
-    ram: [u8; 0xFFFF]
+    pub random: Option<RandomDevice>, // Writes a new random byte into 0xFE after every instruction, if attached
+    pub cycles: u64, // Cycles run so far (without the extra cycles for page crossings and taken branches)
+    pub symbols: Symbols, // Labels from the assembler, shown in place of addresses in traces and disassembly
+    pub source: SourceMap, // The script lines the program came from, shown next to each instruction in traces and the debugger
+    pub profiler: Option<Profiler>, // Counts hits and cycles for every address and subroutine, if attached
+    pub coverage: Option<Coverage>, // Keeps track of which bytes were run, read and written, if attached
+    pub watches: Watches, // Memory watchpoints for the debuggers, checked on every read and write
+    pub history: Option<History>, // The last instructions run, for stepping backwards, if attached
+    pub calls: CallStack, // The JSRs and interrupts that haven't returned yet, for backtraces and --stack-check
+    pub dummy_accesses: bool, // Do the extra reads and writes the real 6502 does (on by default, off is a little faster)
+    instruction: Option<(u16, u16)>, // The instruction being run (address, length), None while the emulator itself touches memory
+    dummy: bool, // True during one of the extra accesses, so watchpoints can tell them apart
+    devices: Vec<Box<dyn Device>>, // Memory-mapped devices, these get first say over any address they map
+    ram: [u8; 0x10000]
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        CPU::new()
+    }
 }
 
 impl CPU { 
@@ -58,7 +86,20 @@ impl CPU {
             stack_pointer: 0xFF, 
             info: Vec::new(),
             print_mode: false,
-            ram: [0; 0xFFFF]
+            random: None,
+            cycles: 0,
+            symbols: Symbols::default(),
+            source: SourceMap::default(),
+            profiler: None,
+            coverage: None,
+            watches: Watches::default(),
+            history: None,
+            calls: CallStack::default(),
+            dummy_accesses: true,
+            instruction: None,
+            dummy: false,
+            devices: Vec::new(),
+            ram: [0; 0x10000]
         }
     }
 
@@ -69,25 +110,92 @@ impl CPU {
     We need these to fetch data from memory. Addresses are stored in little endian mode: least significant byte first, most significant byte second.
     If we want to fetch a 16-bit address, we have to keep that in mind
 */
-    pub fn read_memory_u8(&self, address: u16) -> u8 {
-        self.ram[address as usize]
+    pub fn read_memory_u8(&mut self, address: u16) -> u8 {
+        let value = match self.devices.iter_mut().find(|device| device.maps(address)) {
+            Some(device) => device.read(address),
+            None => self.ram[address as usize],
+        };
+
+        // Fetching the instruction itself doesn't count as reading memory (coverage counts it when it's fetched)
+        let fetch = matches!(self.instruction, Some((start, length)) if address.wrapping_sub(start) < length);
+        if !fetch {
+            if let (Some(coverage), Some(_)) = (self.coverage.as_mut(), self.instruction) {
+                coverage.mark(address, coverage::READ);
+            }
+
+            if !self.watches.is_empty() {
+                self.watches.check(address, watch::READ, value, self.access_source(), self.dummy);
+            }
+        }
+
+        value
     }
 
     pub fn write_memory_u8(&mut self, address: u16, data: u8) {
+        if let (Some(coverage), Some(_)) = (self.coverage.as_mut(), self.instruction) {
+            coverage.mark(address, coverage::WRITTEN);
+        }
+
+        if !self.watches.is_empty() {
+            self.watches.check(address, watch::WRITE, data, self.access_source(), self.dummy);
+        }
+
+        for device in self.devices.iter_mut() {
+            if device.maps(address) {
+                device.write(address, data);
+                return;
+            }
+        }
+
+        // Writing the old value back changes nothing, so there's nothing to undo
+        if let (Some(history), false) = (self.history.as_mut(), self.dummy) {
+            history.write(address, self.ram[address as usize]);
+        }
         self.ram[address as usize] = data;
     }
 
+    // Whether memory is being touched by an instruction, or by the emulator and front-ends in between
+    fn access_source(&self) -> watch::Source {
+        match self.instruction {
+            Some((address, _)) => watch::Source::Instruction(address),
+            None => watch::Source::Device,
+        }
+    }
+
+    // Same as reading, except devices aren't disturbed. For anything that only looks at memory (screens, dumps, disassembly)
+    pub fn peek_memory_u8(&self, address: u16) -> u8 {
+        for device in self.devices.iter() {
+            if device.maps(address) {
+                return device.peek(address);
+            }
+        }
+
+        self.ram[address as usize]
+    }
+
+    // Hook up a memory-mapped device
+    pub fn attach(&mut self, device: Box<dyn Device>) {
+        self.devices.push(device);
+    }
+
     pub fn read_memory_u16(&mut self, position: u16) -> u16 {
         let lsb = self.read_memory_u8(position) as u16;
-        let msb = self.read_memory_u8(position + 1) as u16;
+        let msb = self.read_memory_u8(position.wrapping_add(1)) as u16;
         (msb << 8) | (lsb as u16)
     }
 
+    // Pointers in the zero page wrap within it, so one at 0xFF has its msb at 0x00 rather than 0x0100
+    fn read_zero_page_u16(&mut self, pointer: u8) -> u16 {
+        let lsb = self.read_memory_u8(pointer as u16) as u16;
+        let msb = self.read_memory_u8(pointer.wrapping_add(1) as u16) as u16;
+        (msb << 8) | lsb
+    }
+
     pub fn write_memory_u16(&mut self, position: u16, data: u16) {
         let msb = (data >> 8) as u8;
         let lsb = (data & 0xFF) as u8;
         self.write_memory_u8(position, lsb);
-        self.write_memory_u8(position + 1, msb);
+        self.write_memory_u8(position.wrapping_add(1), msb);
     }   
 
     /*
@@ -103,237 +211,375 @@ impl CPU {
     pub fn callback<F>(&mut self, mut call: F) where F: FnMut(&mut CPU), {
 
         loop {
-            
-            let opcode = self.read_memory_u8(self.program_counter);
-            let opcode_info = match OPCODES_TABLE.get(&opcode) {
-                Some(info) => info,
-                None => {
-                    panic!("Error opcode is: {:X}", opcode);
+            if !self.step() {
+                if let Some(error) = self.calls.error.take() {
+                    eprintln!("{}", error);
                 }
-            };
+                return;
+            }
 
-            let mode = &opcode_info.mode;
+            call(self); // Return to the function that called this function
+        }
+    }
 
-            self.program_counter += 1;
+    // Execute a single instruction. Returns false once the program is over (BRK or an invalid opcode)
+    pub fn step(&mut self) -> bool {
+        let registers = self.registers();
+        if let Some(history) = self.history.as_mut() {
+            history.begin(registers);
+        }
 
-            match opcode {
- 
-                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                    let address = self.get_address(mode);
-                    let data = self.read_memory_u8(address);
-                    self.ADC(data);
-                }
+        let address = self.program_counter; // Where this instruction is, for the trace
+        self.instruction = Some((address, 1)); // Just the opcode until we know how long the instruction is
+        let opcode = self.read_memory_u8(self.program_counter);
+        let opcode_info = match OPCODES_TABLE.get(&opcode) {
+            Some(info) => info,
+            None => {
+                println!("{:X} is an invalid Opcode! Program terminated", opcode);
+                self.instruction = None;
+                return false;
+            }
+        };
 
-                0x0A => self.ASL_ACCUMULATOR(),
+        let mode = &opcode_info.mode;
 
-                0x06 | 0x16 | 0x0E | 0x1E => {
-                    self.ASL(mode);
-                }
+        // The operand bytes are part of the fetch too. Memory touched until the program counter is updated is the instruction's doing
+        self.instruction = Some((address, opcode_info.byte_length as u16));
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.instruction(address, opcode_info.byte_length as u16);
+        }
 
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
-                    self.AND(mode);
-                }
+        self.program_counter = self.program_counter.wrapping_add(1);
 
-                0x24 | 0x2C => {
-                    self.BIT(mode);
-                }
+        match opcode {
+ 
+            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
+                let address = self.get_address(mode);
+                let data = self.read_memory_u8(address);
+                self.ADC(data);
+            }
 
-                // BCC
-                0x90 => self.BRANCH(self.status_flags & 0b0000_0001 == 0b0000_0000),
+            0x0A => self.ASL_ACCUMULATOR(),
 
-                // BCS 
-                0xB0 => self.BRANCH(self.status_flags & 0b0000_0001 == 0b0000_0001),
+            0x06 | 0x16 | 0x0E | 0x1E => {
+                self.ASL(mode);
+            }
 
-                // BEQ 
-                0xF0 => self.BRANCH(self.status_flags & 0b0000_0010 == 0b0000_0010),
-                
-                // BMI 
-                0x30 => self.BRANCH(self.status_flags & 0b1000_0000 == 0b1000_0000),
+            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
+                self.AND(mode);
+            }
 
-                // BNE 
-                0xD0 => self.BRANCH(self.status_flags & 0b0000_0010 == 0b0000_0000),
-                
-                // BPL
-                0x10 => self.BRANCH(self.status_flags & 0b1000_0000 == 0b0000_0000),
-                
-                // BVC 
-                0x50 => self.BRANCH(self.status_flags & 0b0100_0000 == 0b0000_0000),
-                
-                // BVS 
-                0x70 => self.BRANCH(self.status_flags & 0b0100_0000 == 0b0100_0000),
-
-                // BRK
-                0x00 => {
-                    if self.print_mode { // We don't want our values to be printed while playing snake!
-                        self.save_and_print(&opcode_info.mnemonic, &opcode_info.hex_code); 
-                    }
-                    
-                    return
-                }
+            0x24 | 0x2C => {
+                self.BIT(mode);
+            }
 
-                // CLC
-                0x18 => self.clear_carry_flag(),
+            // BCC
+            0x90 => self.BRANCH(self.status_flags & 0b0000_0001 == 0b0000_0000),
 
-                0xD8 => self.CLD(),
+            // BCS 
+            0xB0 => self.BRANCH(self.status_flags & 0b0000_0001 == 0b0000_0001),
 
-                // CLI
-                0x58 => self.clear_interrupt_disable_flag(),
-                
-                // CLV
-                0xB8 => self.clear_overflow_flag(),
+            // BEQ 
+            0xF0 => self.BRANCH(self.status_flags & 0b0000_0010 == 0b0000_0010),
+            
+            // BMI 
+            0x30 => self.BRANCH(self.status_flags & 0b1000_0000 == 0b1000_0000),
 
-                0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
-                    self.COMPARE(mode, self.register_a);
-                }
+            // BNE 
+            0xD0 => self.BRANCH(self.status_flags & 0b0000_0010 == 0b0000_0000),
+            
+            // BPL
+            0x10 => self.BRANCH(self.status_flags & 0b1000_0000 == 0b0000_0000),
+            
+            // BVC 
+            0x50 => self.BRANCH(self.status_flags & 0b0100_0000 == 0b0000_0000),
+            
+            // BVS 
+            0x70 => self.BRANCH(self.status_flags & 0b0100_0000 == 0b0100_0000),
+
+            // BRK
+            0x00 => {
+                self.instruction = None;
 
-                0xE0 | 0xE4 | 0xEC => {
-                    self.COMPARE(mode, self.register_x);
+                if let Some(profiler) = self.profiler.as_mut() {
+                    profiler.instruction(address, opcode, opcode_info.num_cycles as u64, self.program_counter);
                 }
 
-                0xC0 | 0xC4 | 0xCC => {
-                    self.COMPARE(mode, self.register_y);
+                if self.print_mode { // We don't want our values to be printed while playing snake!
+                    self.save_and_print(&opcode_info.mnemonic, &opcode_info.hex_code, address); 
                 }
                 
-                0xC7 | 0xD7 | 0xCF | 0xDF | 0xDB | 0xC3 | 0xD3 => {
-                    self.DCP(mode);
-                }
+                return false
+            }
 
-                0xC6 | 0xD6 | 0xCE | 0xDE => {
-                    self.DEC(mode);
-                }
+            // CLC
+            0x18 => self.clear_carry_flag(),
 
-                0xCA => self.DEX(),
+            0xD8 => self.CLD(),
 
-                0x88 => self.DEY(),
+            // CLI
+            0x58 => self.clear_interrupt_disable_flag(),
+            
+            // CLV
+            0xB8 => self.clear_overflow_flag(),
 
-                0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
-                    self.EOR(mode);
-                }
+            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
+                self.COMPARE(mode, self.register_a);
+            }
 
-                0xE6 | 0xF6 | 0xEE | 0xFE => {
-                    self.INC(mode);
-                }
+            0xE0 | 0xE4 | 0xEC => {
+                self.COMPARE(mode, self.register_x);
+            }
 
-                0xE8 => self.INX(),
+            0xC0 | 0xC4 | 0xCC => {
+                self.COMPARE(mode, self.register_y);
+            }
+            
+            0xC7 | 0xD7 | 0xCF | 0xDF | 0xDB | 0xC3 | 0xD3 => {
+                self.DCP(mode);
+            }
 
-                0xC8 => self.INY(),
+            0xC6 | 0xD6 | 0xCE | 0xDE => {
+                self.DEC(mode);
+            }
 
-                0x4C => self.JMP_ABSOLUTE(),
+            0xCA => self.DEX(),
 
-                0x6C => self.JMP_INDIRECT(),
+            0x88 => self.DEY(),
 
-                0x20 => self.JSR(),
+            0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
+                self.EOR(mode);
+            }
 
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.LDA(mode);
-                }
+            0xE6 | 0xF6 | 0xEE | 0xFE => {
+                self.INC(mode);
+            }
 
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
-                    self.LDX(mode);
-                }
+            0xE8 => self.INX(),
 
-                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => { 
-                    self.LDY(mode);
-                }
+            0xC8 => self.INY(),
 
-                0x4A => self.LSR_ACCUMULATOR(),
+            0x4C => self.JMP_ABSOLUTE(),
 
-                0x46 | 0x56 | 0x4E | 0x5E => {
-                    self.LSR(mode);
-                }
+            0x6C => self.JMP_INDIRECT(),
 
-                // NOP
-                0xEA | 0x80 | 0x04 | 0x44 | 0x64 => self.program_counter = self.program_counter + 1,
+            0x20 => self.JSR(),
 
-                0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
-                    self.ORA(mode);
-                }
+            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
+                self.LDA(mode);
+            }
+
+            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
+                self.LDX(mode);
+            }
 
-                0x48 => self.PHA(),
+            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => { 
+                self.LDY(mode);
+            }
 
-                0x08 => self.PHP(),
+            0x4A => self.LSR_ACCUMULATOR(),
 
-                0x68 => self.PLA(),
+            0x46 | 0x56 | 0x4E | 0x5E => {
+                self.LSR(mode);
+            }
 
-                0x28 => self.PLP(),
+            // NOP
+            0xEA | 0x80 | 0x04 | 0x44 | 0x64 => {}
 
-                0x2A => self.ROL_ACCUMULATOR(),
+            0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
+                self.ORA(mode);
+            }
 
-                0x26 | 0x36 | 0x2E | 0x3E => {
-                    self.ROL(mode);
-                }
+            0x48 => self.PHA(),
 
-                0x6A => self.ROR_ACCUMULATOR(),
+            0x08 => self.PHP(),
 
-                0x66 | 0x76 | 0x6E | 0x7E => {
-                    self.ROR(mode);
-                }
+            0x68 => self.PLA(),
 
-                0x40 => self.RTI(),
+            0x28 => self.PLP(),
 
-                0x60 => self.RTS(),
+            0x2A => self.ROL_ACCUMULATOR(),
 
-                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                    self.SBC(mode);
-                }
+            0x26 | 0x36 | 0x2E | 0x3E => {
+                self.ROL(mode);
+            }
 
-                // SEC
-                0x38 => self.set_carry_flag(),
+            0x6A => self.ROR_ACCUMULATOR(),
 
-                // SED
-                0xF8 => self.SED(),
+            0x66 | 0x76 | 0x6E | 0x7E => {
+                self.ROR(mode);
+            }
 
-                // SEI
-                0x78 => self.set_interrupt_disable_flag(),
-                
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
-                    self.STA(mode);
-                }
+            0x40 => self.RTI(),
 
-                0x86 | 0x96 | 0x8E => {
-                    self.STX(mode);
-                }
+            0x60 => self.RTS(),
 
-                0x84 | 0x94 | 0x8C => {
-                    self.STY(mode);
-                }
+            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
+                self.SBC(mode);
+            }
 
-                0xAA => self.TAX(),
+            // SEC
+            0x38 => self.set_carry_flag(),
 
-                0xA8 => self.TAY(),
-                
-                0xBA => self.TSX(),
-                
-                0x8A => self.TXA(),
-                
-                0x9A => self.TXS(),
-                
-                0x98 => self.TYA(),
-                
-                _ => {
-                    println!("{} is an invalid Opcode! Program terminated", &opcode_info.hex_code);
-                    return;
-                }
+            // SED
+            0xF8 => self.SED(),
+
+            // SEI
+            0x78 => self.set_interrupt_disable_flag(),
+            
+            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
+                self.STA(mode);
             }
 
-            self.update_program_counter(&opcode);
+            0x86 | 0x96 | 0x8E => {
+                self.STX(mode);
+            }
 
-            if self.print_mode { // We don't want our values to be printed while playing snake!
-                self.save_and_print(&opcode_info.mnemonic, &opcode_info.hex_code); 
+            0x84 | 0x94 | 0x8C => {
+                self.STY(mode);
             }
 
-            call(self); // Return to the function that called this function
+            0xAA => self.TAX(),
+
+            0xA8 => self.TAY(),
+            
+            0xBA => self.TSX(),
+            
+            0x8A => self.TXA(),
+            
+            0x9A => self.TXS(),
+            
+            0x98 => self.TYA(),
+            
+            _ => {
+                println!("{} is an invalid Opcode! Program terminated", &opcode_info.hex_code);
+                self.instruction = None;
+                return false;
+            }
+        }
+
+        self.update_program_counter(&opcode);
+        self.instruction = None;
+
+        // Let the devices know how much time went by, and answer them if one of them wants attention
+        self.cycles += opcode_info.num_cycles as u64;
+        for device in self.devices.iter_mut() {
+            device.tick(opcode_info.num_cycles);
+        }
+
+        // Before any interrupt, so the handler shows up as called from here
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.instruction(address, opcode, opcode_info.num_cycles as u64, self.program_counter);
         }
+
+        if self.devices.iter().any(|device| device.irq()) {
+            self.irq();
+        }
+
+        // Refresh the random byte, the seed decides what comes out so runs can be reproduced
+        if let Some(byte) = self.random.as_mut().map(|random| random.next_byte()) {
+            self.write_memory_u8(RNG_ADDRESS, byte);
+        }
+
+        if self.print_mode { // We don't want our values to be printed while playing snake!
+            self.save_and_print(&opcode_info.mnemonic, &opcode_info.hex_code, address); 
+        }
+
+        // With --stack-check error, a problem with the stack stops the program once the instruction is done
+        self.calls.error.is_none()
     }
 
-    // Load into program ROM without executing it
+    /*
+        Undo the last instruction from the history: RAM gets its old values back (newest write first) and the registers
+        go back to what they were. Returns what was undone, or None if there's no history left
+    */
+    pub fn step_back(&mut self) -> Option<Delta> {
+        let delta = self.history.as_mut()?.pop()?;
+        for (address, old) in delta.writes.iter().rev() {
+            self.ram[*address as usize] = *old;
+        }
+
+        if let Some(frames) = &delta.frames {
+            self.calls.frames = frames.clone();
+        }
+
+        let registers = delta.registers;
+        self.register_a = registers.a;
+        self.register_x = registers.x;
+        self.register_y = registers.y;
+        self.status_flags = registers.status;
+        self.stack_pointer = registers.stack_pointer;
+        self.program_counter = registers.program_counter;
+        self.cycles = registers.cycles;
+        Some(delta)
+    }
+
+    // A snapshot of the registers and the cycle count
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            status: self.status_flags,
+            stack_pointer: self.stack_pointer,
+            program_counter: self.program_counter,
+            cycles: self.cycles,
+        }
+    }
+
+    // Load into program ROM without executing it. Programs too big for the space after 0x0600 wrap around to 0x0000, like load_at
     pub fn load(&mut self, program: &Vec<u8>) {
-        self.ram[0x0600..(0x0600 + program.len())].copy_from_slice(&program[..]);
+        self.load_at(0x0600, program);
         self.write_memory_u16(0xFFFC, 0x0600);
 
         self.program_counter = self.read_memory_u16(0xFFFC); // 0xFFFC holds address of the starting instruction
     }
 
+    // Same as pressing the reset button: registers cleared, interrupts disabled, and execution starts wherever the reset vector points
+    pub fn reset(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.stack_pointer = 0xFD; // Reset does three dummy pushes on a real 6502, so the stack starts here
+        self.status_flags = 0b0000_0100;
+        self.program_counter = self.read_memory_u16(0xFFFC);
+    }
+
+    // A device is asking for attention. Ignored while the interrupt disable flag is set
+    pub fn irq(&mut self) {
+        if self.status_flags & 0b0000_0100 == 0 {
+            self.interrupt(0xFFFE);
+        }
+    }
+
+    // Non-maskable interrupt, can't be ignored
+    pub fn nmi(&mut self) {
+        self.interrupt(0xFFFA);
+    }
+
+    // Save where we were and the flags (with break clear, so the handler can tell it apart from BRK), then jump through the vector
+    fn interrupt(&mut self, vector: u16) {
+        let caller = self.program_counter;
+        self.push_stack_u16(self.program_counter);
+        self.push_stack_u8((self.status_flags & 0b1110_1111) | 0b0010_0000);
+        self.set_interrupt_disable_flag();
+        self.program_counter = self.read_memory_u16(vector);
+
+        self.save_frames();
+        self.calls.call(Frame { kind: FrameKind::Interrupt, caller, target: self.program_counter, return_address: caller, stack_pointer: self.stack_pointer });
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.interrupt(self.program_counter);
+        }
+    }
+
+    // Put bytes straight into memory at any address, for programs that don't start at 0x0600
+    pub fn load_at(&mut self, address: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.ram[address.wrapping_add(offset as u16) as usize] = *byte;
+        }
+    }
+
     // Load into program ROM and execute it, useful for testing
     pub fn load_and_execute(&mut self, program: Vec<u8>) {
         self.load(&program);
@@ -343,33 +589,58 @@ impl CPU {
         self.register_x = 0;
         self.register_y = 0;
         self.status_flags = 0b0000_0000;
+
+        self.execute();
+        self.clear(&program);
+    }
+
+    // Run from wherever the reset vector points until the program is over
+    pub fn execute(&mut self) {
         self.program_counter = self.read_memory_u16(0xFFFC); // 0xFFFC holds address of the starting instruction
+
+        // The seed goes at the top of the trace, so that the same run can be reproduced with --seed
+        if self.print_mode {
+            if let Some(random) = &self.random {
+                println!("\n Random Seed: {} \n", random.seed);
+            }
+        }
         
         self.callback(|_| {});
-        self.clear(&program);
     }
 
     // Program counter must be updated accordingly after every executed opcode
     pub fn update_program_counter(&mut self, opcode: &u8) {
         let opcode_info = OPCODES_TABLE.get(&opcode).unwrap();
         // Byte-length includes the opcode itself, which we don't want to include
-        self.program_counter += (opcode_info.byte_length as u16) - 1;
+        self.program_counter = self.program_counter.wrapping_add(opcode_info.byte_length as u16 - 1);
     }
 
     // Unloads program from ROM
     pub fn clear(&mut self, program: &Vec<u8>) {
-        for i in 0x0600..= 0x8000 + program.len() {
+        for i in 0x0600..=(0x8000 + program.len()).min(0xFFFF) {
             self.ram[i] = 0;
         }
     }
 
     // Stores current processor info and prints it to the command line
-    fn save_and_print(&mut self, mnemonic: &str, hexcode: &u8) {
+    fn save_and_print(&mut self, mnemonic: &str, hexcode: &u8, address: u16) {
         self.info = vec![self.register_a as u16, self.register_x as u16, self.register_y as u16, self.stack_pointer as u16, 
-        self.status_flags as u16, self.program_counter - 1];
+        self.status_flags as u16, self.program_counter.wrapping_sub(1)];
         
-        println!("\n AFTER {} - {:#04x} \n \n Register Accumulator: {:#04x} \n Register X: {:#04x} \n Register Y: {:#04x} \n Stack Pointer: {} \n Status Flags: {:#b} \n Program Counter: {:#06x} \n", 
-        mnemonic, hexcode, self.info[0], self.info[1], self.info[2], self.info[3], self.info[4], self.info[5]);
+        // Name the program counter too if the assembler gave it a label
+        let label = match self.symbols.name(self.info[5]) {
+            Some(name) => format!(" ({})", name),
+            None => String::new(),
+        };
+
+        // And the script line the instruction came from, comment included
+        let source = match self.source.lookup(address) {
+            Some(line) => format!(" {} \n", line.describe()),
+            None => String::new(),
+        };
+
+        println!("\n AFTER {} - {:#04x} \n{} \n Register Accumulator: {:#04x} \n Register X: {:#04x} \n Register Y: {:#04x} \n Stack Pointer: {} \n Status Flags: {:#b} \n Program Counter: {:#06x}{} \n", 
+        mnemonic, hexcode, source, self.info[0], self.info[1], self.info[2], self.info[3], self.info[4], self.info[5], label);
     }
 
 /*
@@ -381,14 +652,22 @@ impl CPU {
 */
 
     pub fn pop_stack_u8(&mut self) -> u8 {
+        if self.stack_pointer == 0xFF {
+            self.stack_problem(Problem::Underflow);
+        }
+
         // Start of the stack is at 0x01FF, so popping an item brings it closer to this address
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        self.read_memory_u8(STACK_START as u16 + self.stack_pointer as u16)
+        self.read_memory_u8(STACK_START | self.stack_pointer as u16)
     }
 
     pub fn push_stack_u8(&mut self, data: u8) {
+        if self.stack_pointer == 0x00 {
+            self.stack_problem(Problem::Overflow);
+        }
+
         // Similarly, pushing an item brings it further away from 0x01FF
-        self.write_memory_u8(STACK_START as u16 + self.stack_pointer as u16, data);
+        self.write_memory_u8(STACK_START | self.stack_pointer as u16, data);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
@@ -408,6 +687,27 @@ impl CPU {
         self.push_stack_u8(lsb);
     }
 
+    // The instruction being run is the one to blame, or wherever the program is if it's an interrupt
+    fn stack_problem(&mut self, problem: Problem) {
+        let at = self.instruction.map_or(self.program_counter, |(address, _)| address);
+        self.calls.report(at, problem);
+    }
+
+    // The call stack is about to change, the history needs a copy to step back to
+    fn save_frames(&mut self) {
+        if let Some(history) = self.history.as_mut() {
+            history.save_frames(&self.calls.frames);
+        }
+    }
+
+    // An RTS or RTI pulled this return address, with the stack pointer where it was before
+    fn returned(&mut self, kind: FrameKind, address: u16, stack_pointer: u8) {
+        self.save_frames();
+        if let Some(problem) = self.calls.ret(kind, address, stack_pointer) {
+            self.stack_problem(problem);
+        }
+    }
+
  /*
     ---------------------------------------------------------------------------------------------------------
     ADDRESSING MODES
@@ -416,6 +716,15 @@ impl CPU {
 */
 
     fn get_address(&mut self, mode: &AddressingMode) -> u16 {
+        self.effective_address(mode, false)
+    }
+
+    // Stores and read-modify-write instructions do their dummy read whether or not the index carried into the next page
+    fn get_write_address(&mut self, mode: &AddressingMode) -> u16 {
+        self.effective_address(mode, true)
+    }
+
+    fn effective_address(&mut self, mode: &AddressingMode, writing: bool) -> u16 {
         match mode {
 
             AddressingMode::Absolute => self.read_memory_u16(self.program_counter), // Little endian mode, get full address
@@ -423,13 +732,13 @@ impl CPU {
             AddressingMode::AbsoluteX => {
                 // Read address from program counter and add the offset from register x to get the resulting address
                 let base_address: u16 = self.read_memory_u16(self.program_counter);
-                base_address.wrapping_add(self.register_x as u16)
+                self.index_address(base_address, self.register_x, writing)
             }
 
             AddressingMode::AbsoluteY => {
                 // Same here but for register y
                 let base_address: u16 = self.read_memory_u16(self.program_counter);
-                base_address.wrapping_add(self.register_y as u16)
+                self.index_address(base_address, self.register_y, writing)
             }
 
             AddressingMode::Immediate | AddressingMode::Relative => self.program_counter, // Fetch the constant that comes after the opcode, no address needed
@@ -442,23 +751,17 @@ impl CPU {
 
                 // Get one-byte, add register x to it as an offset to get the lsb byte, what follows after is the msb byte. That is the address
                 let byte: u8 = self.read_memory_u8(self.program_counter);
+                self.dummy_read(byte as u16); // The 6502 reads the pointer before x is added to it
                 
                 let pointer: u8 = (byte).wrapping_add(self.register_x); 
-                let lsb = self.read_memory_u8(pointer as u16);
-                let msb = self.read_memory_u8(pointer.wrapping_add(1) as u16);
-                
-                (msb as u16) << 8 | (lsb as u16)
+                self.read_zero_page_u16(pointer)
             }
 
             AddressingMode::IndirectIndexed => {
-                let base_address = self.read_memory_u8(self.program_counter);
+                let pointer = self.read_memory_u8(self.program_counter);
                 
-                let lsb = self.read_memory_u8(base_address as u16);
-                let msb = self.read_memory_u8((base_address as u8).wrapping_add(1) as u16);
-                let unadded_address = (msb as u16) << 8 | (lsb as u16);
-                let added_address = unadded_address.wrapping_add(self.register_y as u16);
-                
-                added_address
+                let unadded_address = self.read_zero_page_u16(pointer);
+                self.index_address(unadded_address, self.register_y, writing)
             }
 
             // Address is located in the zero page, only one byte needed
@@ -467,6 +770,7 @@ impl CPU {
             // Get an address located in the zero page by adding the next byte with register x
             AddressingMode::ZeroPageX => {
                 let position = self.read_memory_u8(self.program_counter);
+                self.dummy_read(position as u16); // Same here, the address is read before x is added
                 let address = position.wrapping_add(self.register_x) as u16;
                 
                 address 
@@ -475,12 +779,47 @@ impl CPU {
             // Do the same but with register y
             AddressingMode::ZeroPageY => {
                 let position: u8 = self.read_memory_u8(self.program_counter);
+                self.dummy_read(position as u16);
                 let address: u16 = position.wrapping_add(self.register_y) as u16;
                 
                 address
             }
         }
     }
+
+    /*
+    The 6502 adds the index to the low byte first and reads from there while it fixes the high byte, which is the
+    wrong page if the low byte carried. Reads only take that extra step when it did carry, stores and
+    read-modify-writes always do
+    */
+    fn index_address(&mut self, base: u16, index: u8, writing: bool) -> u16 {
+        let address = base.wrapping_add(index as u16);
+        let uncarried = (base & 0xFF00) | (address & 0x00FF);
+        if writing || uncarried != address {
+            self.dummy_read(uncarried);
+        }
+
+        address
+    }
+
+    // A read that only happens for its side effects on devices (and watchpoints), the value isn't used
+    fn dummy_read(&mut self, address: u16) {
+        if self.dummy_accesses {
+            self.dummy = true;
+            self.read_memory_u8(address);
+            self.dummy = false;
+        }
+    }
+
+    // Read-modify-write instructions write the old value back while they work out the new one, then write the new one
+    fn write_modified_u8(&mut self, address: u16, old: u8, new: u8) {
+        if self.dummy_accesses {
+            self.dummy = true;
+            self.write_memory_u8(address, old);
+            self.dummy = false;
+        }
+        self.write_memory_u8(address, new);
+    }
     
 /* 
     ---------------------------------------------------------------------------------------------------------
@@ -533,12 +872,9 @@ impl CPU {
         self.status_flags = self.status_flags & 0b1111_1101;
     }
 
-    fn set_break_flag(&mut self) {
-        self.status_flags = self.status_flags | 0b0010_0000;
-    }
-
+    // Bits 4 and 5 only exist in the copies of P pushed on the stack
     fn clear_break_flag(&mut self) {
-        self.status_flags = self.status_flags & 0b1101_1111;
+        self.status_flags = self.status_flags & 0b1100_1111;
     }
 
     fn set_negative_flag(&mut self) {
@@ -623,8 +959,9 @@ impl CPU {
 
     // General arithmetic shift left
     fn ASL(&mut self, mode: &AddressingMode) {
-        let address = self.get_address(mode);
-        let mut data = self.read_memory_u8(address);
+        let address = self.get_write_address(mode);
+        let old = self.read_memory_u8(address);
+        let mut data = old;
 
         if data >> 7 == 1 {
             self.set_carry_flag();
@@ -634,7 +971,7 @@ impl CPU {
 
         data = data << 1;
 
-        self.write_memory_u8(address, data);
+        self.write_modified_u8(address, old, data);
         self.zero_and_negative_flags(data);
     }
 
@@ -670,7 +1007,7 @@ impl CPU {
         if condition {
             let offset = self.read_memory_u8(self.program_counter) as i8;
             let jump_address = self.program_counter.wrapping_add(1).wrapping_add(offset as u16); // 0x00 means the very next instruction
-            self.program_counter = jump_address - 1; // Since counter is incremented by one after this instruction
+            self.program_counter = jump_address.wrapping_sub(1); // Since counter is incremented by one after this instruction
         }
     }
 
@@ -684,7 +1021,10 @@ impl CPU {
         // Register a / x / y - memory
         let address: u16 = self.get_address(mode);
         let value = self.read_memory_u8(address);
+        self.compare_values(register, value);
+    }
 
+    fn compare_values(&mut self, register: u8, value: u8) {
         if register >= value {
             self.set_carry_flag();
         } else {
@@ -694,18 +1034,20 @@ impl CPU {
         self.zero_and_negative_flags(register.wrapping_sub(value));
     }
 
-    // DEC + CMP opcode, not really official
+    // DEC + CMP opcode, not really official. Memory is only read once, the compare uses what DEC wrote
     fn DCP(&mut self, mode: &AddressingMode) {
-        self.DEC(&mode);
-        self.COMPARE(&mode, self.register_a);
+        let result = self.DEC(mode);
+        self.compare_values(self.register_a, result);
     }
 
     // Decrement a value in memory by 1
-    fn DEC(&mut self, mode: &AddressingMode) {
-        let address = self.get_address(mode);
-        let result = self.read_memory_u8(address).wrapping_sub(1);
-        self.ram[address as usize] = result;
-        self.zero_and_negative_flags(result)
+    fn DEC(&mut self, mode: &AddressingMode) -> u8 {
+        let address = self.get_write_address(mode);
+        let old = self.read_memory_u8(address);
+        let result = old.wrapping_sub(1);
+        self.write_modified_u8(address, old, result);
+        self.zero_and_negative_flags(result);
+        result
     }
 
     // These two decrement their registers by 1 respectively
@@ -729,9 +1071,10 @@ impl CPU {
 
     // Increment value in memory by 1, same for registers x and y
     fn INC(&mut self, mode: &AddressingMode) {
-        let address = self.get_address(mode);
-        let result = self.read_memory_u8(address).wrapping_add(1);
-        self.ram[address as usize] = result;
+        let address = self.get_write_address(mode);
+        let old = self.read_memory_u8(address);
+        let result = old.wrapping_add(1);
+        self.write_modified_u8(address, old, result);
         self.zero_and_negative_flags(result)
     }
 
@@ -748,7 +1091,7 @@ impl CPU {
     // Jump to a location in memory
     fn JMP_ABSOLUTE(&mut self) {
         let specified_address = self.read_memory_u16(self.program_counter);
-        self.program_counter = specified_address - 2; // Since the counter is incremented by two after this
+        self.program_counter = specified_address.wrapping_sub(2); // Since the counter is incremented by two after this
     }
 
     fn JMP_INDIRECT(&mut self) {
@@ -768,14 +1111,24 @@ impl CPU {
             self.read_memory_u16(address)
         };
 
-        self.program_counter = indirect_reference - 2; // Since the counter is incremented by two after this
+        self.program_counter = indirect_reference.wrapping_sub(2); // Since the counter is incremented by two after this
     }
 
     // Jump to a subroutine
     fn JSR(&mut self) {
-        self.push_stack_u16(self.program_counter + 2); // Location of JSR opcode
+        self.push_stack_u16(self.program_counter.wrapping_add(1)); // The JSR's last byte, RTS adds the 1 back
         let target_address = self.read_memory_u16(self.program_counter);
-        self.program_counter = target_address - 2; // JSR byte length is 3 (counter jumps forward by 2), so it must be negated
+
+        self.save_frames();
+        self.calls.call(Frame {
+            kind: FrameKind::Subroutine,
+            caller: self.program_counter.wrapping_sub(1),
+            target: target_address,
+            return_address: self.program_counter.wrapping_add(1),
+            stack_pointer: self.stack_pointer,
+        });
+
+        self.program_counter = target_address.wrapping_sub(2); // JSR byte length is 3 (counter jumps forward by 2), so it must be negated
     }
 
     // Load values into the a, x, and y registers
@@ -817,8 +1170,9 @@ impl CPU {
     fn LSR(&mut self, mode: &AddressingMode) {
         // Data shifted to the right. Old bit 0 is carry flag
         // New bit 7 is set to 0
-        let address = self.get_address(mode);
-        let mut data = self.read_memory_u8(address);
+        let address = self.get_write_address(mode);
+        let old = self.read_memory_u8(address);
+        let mut data = old;
         let old_bit_zero =  data & 0b0000_0001;
 
         data = (data >> 1) & 0b0111_1111;
@@ -829,7 +1183,7 @@ impl CPU {
             self.set_carry_flag();
         }
 
-        self.write_memory_u8(address, data);
+        self.write_modified_u8(address, old, data);
         self.zero_and_negative_flags(data);
     }
 
@@ -848,9 +1202,8 @@ impl CPU {
 
     // Push status flag onto stack
     fn PHP(&mut self) {
-        // Break is pushed as 1
-        self.set_break_flag();
-        self.push_stack_u8(self.status_flags);
+        // The copy on the stack has the break bit (4) and bit 5 set, P itself doesn't change
+        self.push_stack_u8(self.status_flags | 0b0011_0000);
     }
 
     // The alternatives for pulling the accumulator and the status flag from the stack
@@ -891,8 +1244,9 @@ impl CPU {
 
     // Rotate to the left
     fn ROL(&mut self, mode: &AddressingMode) {
-        let address = self.get_address(mode);
-        let mut data = self.read_memory_u8(address);
+        let address = self.get_write_address(mode);
+        let old = self.read_memory_u8(address);
+        let mut data = old;
         let old_bit_seven = (data & 0b1000_0000) >> 7;
         let current_carry_flag = self.status_flags & 0b0000_0001;
 
@@ -913,25 +1267,25 @@ impl CPU {
         }
 
         self.zero_and_negative_flags(data);
-        self.write_memory_u8(address, data);
+        self.write_modified_u8(address, old, data);
     }
 
     // Rotate accumulator value to the right
     fn ROR_ACCUMULATOR(&mut self) {
-        let old_bit_seven = (self.register_a & 0b1000_0000) >> 7;
+        let old_bit_zero = self.register_a & 0b0000_0001;
         let current_carry_flag = self.status_flags & 0b0000_0001;
 
         self.register_a = self.register_a >> 1;
 
-        // Bit 0 is filled with the current carry flag value
-        // Old bit 7 becomes new carry flag value
+        // Bit 7 is filled with the current carry flag value
+        // Old bit 0 becomes new carry flag value
         if current_carry_flag == 0 {
-            self.register_a = self.register_a & 0b1111_1110;
+            self.register_a = self.register_a & 0b0111_1111;
         } else {
-            self.register_a = self.register_a | 0b0000_0001;
+            self.register_a = self.register_a | 0b1000_0000;
         }
 
-        if old_bit_seven == 0 {
+        if old_bit_zero == 0 {
             self.clear_carry_flag();
         } else {
             self.set_carry_flag();
@@ -942,36 +1296,39 @@ impl CPU {
 
     // Rotate value in memory to the right
     fn ROR(&mut self, mode: &AddressingMode) {
-        let address = self.get_address(&mode);
-        let mut data = self.read_memory_u8(address);
-        let old_bit_seven = (data & 0b1000_0000) >> 7;
+        let address = self.get_write_address(&mode);
+        let old = self.read_memory_u8(address);
+        let mut data = old;
+        let old_bit_zero = data & 0b0000_0001;
         let current_carry_flag = self.status_flags & 0b0000_0001;
 
         data = data >> 1;
 
-        // Bit 0 is filled with the current carry flag value
-        // Old bit 7 becomes new carry flag value
+        // Bit 7 is filled with the current carry flag value
+        // Old bit 0 becomes new carry flag value
         if current_carry_flag == 0 {
-            data = data & 0b1111_1110;
+            data = data & 0b0111_1111;
         } else {
-            data = data | 0b0000_0001;
+            data = data | 0b1000_0000;
         }
 
-        if old_bit_seven == 0 {
+        if old_bit_zero == 0 {
             self.clear_carry_flag();
         } else {
             self.set_carry_flag();
         }
 
         self.zero_and_negative_flags(data);
-        self.write_memory_u8(address, data);
+        self.write_modified_u8(address, old, data);
     }
 
     // Return from interrupt
     fn RTI(&mut self) {
         // Pulls flags followed by counter
+        let stack_pointer = self.stack_pointer;
         self.status_flags = self.pop_stack_u8();
         self.program_counter = self.pop_stack_u16();
+        self.returned(FrameKind::Interrupt, self.program_counter, stack_pointer);
 
         // Break discarded 
         self.clear_break_flag();
@@ -979,18 +1336,21 @@ impl CPU {
 
     // Return from subroutine
     fn RTS(&mut self) {
-        self.program_counter = self.pop_stack_u16(); 
+        let stack_pointer = self.stack_pointer;
+        let address = self.pop_stack_u16(); 
+        self.returned(FrameKind::Subroutine, address, stack_pointer);
+        self.program_counter = address.wrapping_add(1); // JSR pushed its last byte
     }
 
     // Subtract with carry 
     fn SBC(&mut self, mode: &AddressingMode) {
-        // We simply take the two's complement
+        // We simply take the one's complement
         // And call our ADC opcode (the borrow value will be added there)
         let address = self.get_address(&mode);
         let data = self.read_memory_u8(address);
 
         // A = A + NOT M + C (carry is added in ADC)
-        self.ADC((data as i8).wrapping_neg() as u8);
+        self.ADC(!data);
     }   
 
     // Set the decimal flag, even though we're never going to use this...
@@ -1000,17 +1360,17 @@ impl CPU {
 
     // Save the value of a register into a memory address
     fn STA(&mut self, mode: &AddressingMode) {
-        let address = self.get_address(mode);
+        let address = self.get_write_address(mode);
         self.write_memory_u8(address, self.register_a);
     }
 
     fn STX(&mut self, mode: &AddressingMode) {
-        let address: u16 = self.get_address(mode);
+        let address: u16 = self.get_write_address(mode);
         self.write_memory_u8(address, self.register_x);
     }
 
     fn STY(&mut self, mode: &AddressingMode) {
-        let address: u16 = self.get_address(mode);
+        let address: u16 = self.get_write_address(mode);
         self.write_memory_u8(address, self.register_y);
     }
 
@@ -0,0 +1,177 @@
+use crate::disassembler::disassemble;
+use crate::processor::CPU;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const HOT_SPOTS: usize = 20; // How many addresses and subroutines the report lists
+const MAX_DEPTH: usize = 256; // Programs that JSR without ever returning shouldn't grow the call stack forever
+
+// Totals for one subroutine, cycles counts everything it called too, self cycles only its own instructions
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    cycles: u64,
+    self_cycles: u64,
+}
+
+/*
+    Counts how many times every address was run and how many cycles were spent there (--profile).
+    The CPU hands it every instruction it runs. JSR starts a new subroutine and RTS ends it (and the same for
+    interrupts and RTI), so cycles are also added up per subroutine, both on their own and together with everything
+    they call. The call stacks are kept in the folded format flame graph tools read (main;draw;plot 1234)
+*/
+pub struct Profiler {
+    hits: Vec<u64>,
+    cycles: Vec<u64>,
+    instructions: u64,
+    total_cycles: u64,
+    stack: Vec<u16>, // Subroutines that haven't returned yet, the entry point at the bottom
+    subroutines: HashMap<u16, Subroutine>,
+    folded: HashMap<Vec<u16>, u64>, // Call stack -> cycles spent with it on top
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            hits: vec![0; 0x10000],
+            cycles: vec![0; 0x10000],
+            instructions: 0,
+            total_cycles: 0,
+            stack: Vec::new(),
+            subroutines: HashMap::new(),
+            folded: HashMap::new(),
+        }
+    }
+
+    // An instruction at address ran, taking this many cycles. The program counter is where it left off
+    pub fn instruction(&mut self, address: u16, opcode: u8, cycles: u64, program_counter: u16) {
+        // The first instruction run is the entry point, the bottom of every call stack
+        if self.stack.is_empty() {
+            self.enter(address);
+        }
+
+        self.hits[address as usize] += 1;
+        self.cycles[address as usize] += cycles;
+        self.instructions += 1;
+        self.total_cycles += cycles;
+
+        // Recursion shouldn't count the same cycles twice
+        let top = self.stack.len() - 1;
+        for (depth, subroutine) in self.stack.iter().enumerate() {
+            if !self.stack[..depth].contains(subroutine) {
+                let totals = self.subroutines.entry(*subroutine).or_default();
+                totals.cycles += cycles;
+                if depth == top {
+                    totals.self_cycles += cycles;
+                }
+            }
+        }
+
+        match self.folded.get_mut(&self.stack) {
+            Some(total) => *total += cycles,
+            None => {
+                self.folded.insert(self.stack.clone(), cycles);
+            }
+        }
+
+        // JSR lands on the subroutine, RTS and RTI go back to whoever called (the entry point never returns)
+        match opcode {
+            0x20 => self.enter(program_counter),
+            0x60 | 0x40 if self.stack.len() > 1 => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    // An interrupt handler starts, it's counted like a subroutine
+    pub fn interrupt(&mut self, handler: u16) {
+        if !self.stack.is_empty() {
+            self.enter(handler);
+        }
+    }
+
+    fn enter(&mut self, subroutine: u16) {
+        if self.stack.len() < MAX_DEPTH {
+            self.stack.push(subroutine);
+            self.subroutines.entry(subroutine).or_default().calls += 1;
+        }
+    }
+
+    // The hot spots and subroutines, biggest first, with labels if the CPU has symbols
+    pub fn report(&self, cpu: &CPU) -> String {
+        let mut report = String::new();
+        let percent = |cycles: u64| cycles as f64 * 100.0 / self.total_cycles.max(1) as f64;
+
+        let _ = writeln!(report, "\n Profile: {} instructions, {} cycles \n", self.instructions, self.total_cycles);
+
+        let mut addresses: Vec<usize> = (0..0x10000).filter(|address| self.hits[*address] > 0).collect();
+        addresses.sort_by(|a, b| self.cycles[*b].cmp(&self.cycles[*a]).then(a.cmp(b)));
+
+        let _ = writeln!(report, " Hot spots \n");
+        let _ = writeln!(report, " {:<7}{:>12}{:>12}{:>8}   Instruction", "Address", "Hits", "Cycles", "%");
+        for address in addresses.into_iter().take(HOT_SPOTS) {
+            let (text, _) = disassemble(cpu, address as u16);
+            let _ = writeln!(report, " ${:04X}  {:>12}{:>12}{:>7.2}%   {}", address, self.hits[address], self.cycles[address], percent(self.cycles[address]), text);
+        }
+
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+
+        let _ = writeln!(report, "\n Subroutines \n");
+        let _ = writeln!(report, " {:<20}{:>10}{:>12}{:>8}{:>12}{:>8}", "Name", "Calls", "Cycles", "%", "Self", "%");
+        for (address, totals) in subroutines.into_iter().take(HOT_SPOTS) {
+            let _ = writeln!(report, " {:<20}{:>10}{:>12}{:>7.2}%{:>12}{:>7.2}%", name(cpu, *address), totals.calls,
+                totals.cycles, percent(totals.cycles), totals.self_cycles, percent(totals.self_cycles));
+        }
+
+        report
+    }
+
+    // One line per call stack, outermost first and separated by semicolons, followed by the cycles spent in it
+    pub fn folded(&self, cpu: &CPU) -> String {
+        let mut lines: Vec<String> = self.folded.iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack.iter().map(|address| name(cpu, *address)).collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+
+    pub fn save_folded(&self, cpu: &CPU, path: &Path) -> Result<(), String> {
+        fs::write(path, self.folded(cpu)).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+// Subroutines go by their label, or their address if they don't have one
+fn name(cpu: &CPU, address: u16) -> String {
+    match cpu.symbols.name(address) {
+        Some(name) => name.to_string(),
+        None => format!("${:04X}", address),
+    }
+}
+
+// Print the report and save the folded stacks if the CPU was being profiled
+pub fn finish(cpu: &CPU, folded: Option<&Path>) {
+    if let Some(profiler) = &cpu.profiler {
+        println!("{}", profiler.report(cpu));
+
+        if let Some(path) = folded {
+            match profiler.save_folded(cpu, path) {
+                Ok(()) => println!("Folded stacks saved to {}", path.display()),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
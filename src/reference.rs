@@ -0,0 +1,385 @@
+/*
+    A second, much plainer 6502 to check the real one against. It shares nothing with processor.rs: opcodes are
+    decoded from their bit patterns (aaabbbcc) instead of the opcode table, and every instruction is one small
+    function of the state. It follows the documented NMOS 6502, with three differences that the emulator makes
+    on purpose:
+
+    - BRK ends the program instead of going through the IRQ vector
+    - Decimal mode isn't emulated, so ADC and SBC are always binary
+    - Only the opcodes the emulator knows are decoded (the official ones, the NOPs it has and DCP)
+
+    Cycles aren't counted, the emulator leaves out the page crossing extras anyway
+*/
+
+pub const N: u8 = 0b1000_0000;
+pub const V: u8 = 0b0100_0000;
+pub const D: u8 = 0b0000_1000;
+pub const I: u8 = 0b0000_0100;
+pub const Z: u8 = 0b0000_0010;
+pub const C: u8 = 0b0000_0001;
+pub const PUSHED: u8 = 0b0011_0000; // The break and unused bits, only there in the copies of P on the stack
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    IndexedIndirect, // (zp,X)
+    IndirectIndexed, // (zp),Y
+    Indirect,        // JMP ($addr)
+    Relative,
+}
+
+impl Mode {
+    // Opcode and operand bytes
+    pub fn length(self) -> u16 {
+        match self {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Adc, And, Asl, Bit, Branch(u8, bool), Brk, Cmp, Cpx, Cpy, Dcp, Dec, Dex, Dey, Eor, Flag(u8, bool), Inc, Inx, Iny,
+    Jmp, Jsr, Lda, Ldx, Ldy, Lsr, Nop, Ora, Pha, Php, Pla, Plp, Rol, Ror, Rti, Rts, Sbc, Sta, Stx, Sty,
+    Tax, Tay, Tsx, Txa, Txs, Tya,
+}
+
+// What an opcode does and how it finds its operand, or None for the ones the emulator doesn't have
+pub fn decode(opcode: u8) -> Option<(Operation, Mode)> {
+    use Operation::*;
+    let (a, b, c) = (opcode >> 5, (opcode >> 2) & 7, opcode & 3);
+
+    // The odd ones out first
+    let special = match opcode {
+        0x00 => Some((Brk, Mode::Implied)),
+        0x20 => Some((Jsr, Mode::Absolute)),
+        0x40 => Some((Rti, Mode::Implied)),
+        0x60 => Some((Rts, Mode::Implied)),
+        0x4C => Some((Jmp, Mode::Absolute)),
+        0x6C => Some((Jmp, Mode::Indirect)),
+        0x08 => Some((Php, Mode::Implied)),
+        0x28 => Some((Plp, Mode::Implied)),
+        0x48 => Some((Pha, Mode::Implied)),
+        0x68 => Some((Pla, Mode::Implied)),
+        0x88 => Some((Dey, Mode::Implied)),
+        0xA8 => Some((Tay, Mode::Implied)),
+        0xC8 => Some((Iny, Mode::Implied)),
+        0xE8 => Some((Inx, Mode::Implied)),
+        0x98 => Some((Tya, Mode::Implied)),
+        0x8A => Some((Txa, Mode::Implied)),
+        0x9A => Some((Txs, Mode::Implied)),
+        0xAA => Some((Tax, Mode::Implied)),
+        0xBA => Some((Tsx, Mode::Implied)),
+        0xCA => Some((Dex, Mode::Implied)),
+        0xEA => Some((Nop, Mode::Implied)),
+        0x80 => Some((Nop, Mode::Immediate)),
+        0x04 | 0x44 | 0x64 => Some((Nop, Mode::ZeroPage)),
+        0xB8 => Some((Flag(V, false), Mode::Implied)),
+        _ => None,
+    };
+    if special.is_some() {
+        return special;
+    }
+
+    // Branches are xxy10000: xx picks the flag, y what it has to be
+    if opcode & 0x1F == 0x10 {
+        let flag = [N, V, C, Z][(opcode >> 6) as usize];
+        return Some((Branch(flag, opcode & 0x20 != 0), Mode::Relative));
+    }
+
+    // Flag instructions are xxy11000, the same way round except V (done above)
+    if opcode & 0x1F == 0x18 {
+        let flag = [C, I, V, D][(opcode >> 6) as usize];
+        return Some((Flag(flag, opcode & 0x20 != 0), Mode::Implied));
+    }
+
+    match c {
+        1 => {
+            let operation = [Ora, And, Eor, Adc, Sta, Lda, Cmp, Sbc][a as usize];
+            let mode = [Mode::IndexedIndirect, Mode::ZeroPage, Mode::Immediate, Mode::Absolute,
+                        Mode::IndirectIndexed, Mode::ZeroPageX, Mode::AbsoluteY, Mode::AbsoluteX][b as usize];
+            if operation == Sta && mode == Mode::Immediate {
+                return None;
+            }
+            Some((operation, mode))
+        }
+
+        2 => {
+            let operation = [Asl, Rol, Lsr, Ror, Stx, Ldx, Dec, Inc][a as usize];
+            let indexed_by_y = operation == Stx || operation == Ldx;
+            let mode = match b {
+                0 if operation == Ldx => Mode::Immediate,
+                1 => Mode::ZeroPage,
+                2 if a < 4 => Mode::Accumulator,
+                3 => Mode::Absolute,
+                5 if indexed_by_y => Mode::ZeroPageY,
+                5 => Mode::ZeroPageX,
+                7 if operation == Ldx => Mode::AbsoluteY,
+                7 if operation != Stx => Mode::AbsoluteX,
+                _ => return None,
+            };
+            Some((operation, mode))
+        }
+
+        0 => {
+            let operation = match a {
+                1 => Bit,
+                4 => Sty,
+                5 => Ldy,
+                6 => Cpy,
+                7 => Cpx,
+                _ => return None,
+            };
+            let mode = match b {
+                0 if operation != Bit && operation != Sty => Mode::Immediate,
+                1 => Mode::ZeroPage,
+                3 => Mode::Absolute,
+                5 if operation == Sty || operation == Ldy => Mode::ZeroPageX,
+                7 if operation == Ldy => Mode::AbsoluteX,
+                _ => return None,
+            };
+            Some((operation, mode))
+        }
+
+        // DCP is the only one of the combined opcodes the emulator has
+        _ => {
+            let mode = match b {
+                0 => Mode::IndexedIndirect,
+                1 => Mode::ZeroPage,
+                3 => Mode::Absolute,
+                4 => Mode::IndirectIndexed,
+                5 => Mode::ZeroPageX,
+                6 => Mode::AbsoluteY,
+                7 => Mode::AbsoluteX,
+                _ => return None,
+            };
+            if a == 6 { Some((Dcp, mode)) } else { None }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Machine {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8, // Bits 4 and 5 mean nothing here, they only exist when P is pushed
+    pub s: u8,
+    pub pc: u16,
+    pub memory: Vec<u8>,
+    pub writes: Vec<u16>, // Every address written since this was last cleared
+}
+
+impl Machine {
+    pub fn new(memory: Vec<u8>) -> Self {
+        Machine { a: 0, x: 0, y: 0, p: 0, s: 0xFF, pc: 0, memory, writes: Vec::new() }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn read_word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    // A pointer in the zero page, the high byte comes from $00 after $FF
+    fn read_zero_page_word(&self, address: u8) -> u16 {
+        u16::from_le_bytes([self.read(address as u16), self.read(address.wrapping_add(1) as u16)])
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+        self.writes.push(address);
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x0100 | self.s as u16, value);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.read(0x0100 | self.s as u16)
+    }
+
+    fn set(&mut self, flag: u8, on: bool) {
+        if on { self.p |= flag } else { self.p &= !flag }
+    }
+
+    fn set_zn(&mut self, value: u8) {
+        self.set(Z, value == 0);
+        self.set(N, value & 0x80 != 0);
+    }
+
+    // Where the operand is, for every mode that has one in memory
+    fn address(&self, mode: Mode, operand: u16) -> u16 {
+        let byte = operand as u8;
+        match mode {
+            Mode::ZeroPage => byte as u16,
+            Mode::ZeroPageX => byte.wrapping_add(self.x) as u16,
+            Mode::ZeroPageY => byte.wrapping_add(self.y) as u16,
+            Mode::Absolute => operand,
+            Mode::AbsoluteX => operand.wrapping_add(self.x as u16),
+            Mode::AbsoluteY => operand.wrapping_add(self.y as u16),
+            Mode::IndexedIndirect => self.read_zero_page_word(byte.wrapping_add(self.x)),
+            Mode::IndirectIndexed => self.read_zero_page_word(byte).wrapping_add(self.y as u16),
+            Mode::Immediate => self.pc.wrapping_sub(1), // Already stepped past it
+            _ => unreachable!("{:?} has no operand address", mode),
+        }
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set(C, register >= value);
+        self.set_zn(register.wrapping_sub(value));
+    }
+
+    fn add(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + (self.p & C) as u16;
+        let result = sum as u8;
+        self.set(V, (self.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.set(C, sum > 0xFF);
+        self.a = result;
+        self.set_zn(result);
+    }
+
+    // The shifts and rotates, on a value from A or memory
+    fn shift(&mut self, operation: Operation, value: u8) -> u8 {
+        let carry = self.p & C;
+        let (result, out) = match operation {
+            Operation::Asl => (value << 1, value & 0x80),
+            Operation::Lsr => (value >> 1, value & 1),
+            Operation::Rol => (value << 1 | carry, value & 0x80),
+            _ => (value >> 1 | carry << 7, value & 1),
+        };
+        self.set(C, out != 0);
+        self.set_zn(result);
+        result
+    }
+
+    // Run one instruction. False if it's a BRK or an opcode the emulator doesn't have, which are left unrun
+    pub fn step(&mut self) -> bool {
+        use Operation::*;
+        let opcode = self.read(self.pc);
+        let (operation, mode) = match decode(opcode) {
+            Some((Brk, _)) | None => return false,
+            Some(decoded) => decoded,
+        };
+
+        let operand = match mode.length() {
+            2 => self.read(self.pc.wrapping_add(1)) as u16,
+            3 => self.read_word(self.pc.wrapping_add(1)),
+            _ => 0,
+        };
+        self.pc = self.pc.wrapping_add(mode.length());
+
+        // Read-modify-write instructions, on A or memory
+        let modify = |machine: &mut Machine, change: &dyn Fn(&mut Machine, u8) -> u8| {
+            if mode == Mode::Accumulator {
+                machine.a = change(machine, machine.a);
+            } else {
+                let address = machine.address(mode, operand);
+                let value = change(machine, machine.read(address));
+                machine.write(address, value);
+            }
+        };
+        let load = |machine: &Machine| machine.read(machine.address(mode, operand));
+
+        match operation {
+            Adc => { let value = load(self); self.add(value) }
+            Sbc => { let value = load(self); self.add(!value) }
+            And => { self.a &= load(self); self.set_zn(self.a) }
+            Ora => { self.a |= load(self); self.set_zn(self.a) }
+            Eor => { self.a ^= load(self); self.set_zn(self.a) }
+            Lda => { self.a = load(self); self.set_zn(self.a) }
+            Ldx => { self.x = load(self); self.set_zn(self.x) }
+            Ldy => { self.y = load(self); self.set_zn(self.y) }
+            Cmp => { let value = load(self); self.compare(self.a, value) }
+            Cpx => { let value = load(self); self.compare(self.x, value) }
+            Cpy => { let value = load(self); self.compare(self.y, value) }
+
+            Bit => {
+                let value = load(self);
+                self.set(N, value & 0x80 != 0);
+                self.set(V, value & 0x40 != 0);
+                self.set(Z, self.a & value == 0);
+            }
+
+            Sta => { let address = self.address(mode, operand); self.write(address, self.a) }
+            Stx => { let address = self.address(mode, operand); self.write(address, self.x) }
+            Sty => { let address = self.address(mode, operand); self.write(address, self.y) }
+
+            Asl | Lsr | Rol | Ror => modify(self, &|machine, value| machine.shift(operation, value)),
+            Inc => modify(self, &|machine, value| { machine.set_zn(value.wrapping_add(1)); value.wrapping_add(1) }),
+            Dec => modify(self, &|machine, value| { machine.set_zn(value.wrapping_sub(1)); value.wrapping_sub(1) }),
+
+            // The compare is with the byte DCP wrote, even if writing it moved where the operand points
+            Dcp => modify(self, &|machine, value| { machine.compare(machine.a, value.wrapping_sub(1)); value.wrapping_sub(1) }),
+
+            Inx => { self.x = self.x.wrapping_add(1); self.set_zn(self.x) }
+            Iny => { self.y = self.y.wrapping_add(1); self.set_zn(self.y) }
+            Dex => { self.x = self.x.wrapping_sub(1); self.set_zn(self.x) }
+            Dey => { self.y = self.y.wrapping_sub(1); self.set_zn(self.y) }
+            Tax => { self.x = self.a; self.set_zn(self.x) }
+            Tay => { self.y = self.a; self.set_zn(self.y) }
+            Txa => { self.a = self.x; self.set_zn(self.a) }
+            Tya => { self.a = self.y; self.set_zn(self.a) }
+            Tsx => { self.x = self.s; self.set_zn(self.x) }
+            Txs => self.s = self.x,
+
+            Flag(flag, on) => self.set(flag, on),
+
+            Branch(flag, on) => {
+                if (self.p & flag != 0) == on {
+                    self.pc = self.pc.wrapping_add(operand as u8 as i8 as u16);
+                }
+            }
+
+            Jmp if mode == Mode::Indirect => {
+                // The pointer's high byte comes from the start of the same page when it sits at $xxFF
+                let high = (operand & 0xFF00) | (operand as u8).wrapping_add(1) as u16;
+                self.pc = u16::from_le_bytes([self.read(operand), self.read(high)]);
+            }
+            Jmp => self.pc = operand,
+
+            // The return address on the stack is the last byte of the JSR, RTS adds the one back
+            Jsr => {
+                let [low, high] = self.pc.wrapping_sub(1).to_le_bytes();
+                self.push(high);
+                self.push(low);
+                self.pc = operand;
+            }
+            Rts => {
+                let low = self.pull();
+                let high = self.pull();
+                self.pc = u16::from_le_bytes([low, high]).wrapping_add(1);
+            }
+            Rti => {
+                self.p = self.pull();
+                let low = self.pull();
+                let high = self.pull();
+                self.pc = u16::from_le_bytes([low, high]);
+            }
+
+            Pha => self.push(self.a),
+            Php => self.push(self.p | PUSHED),
+            Pla => { self.a = self.pull(); self.set_zn(self.a) }
+            Plp => self.p = self.pull(),
+
+            Nop => {}
+            Brk => unreachable!(),
+        }
+
+        true
+    }
+}
@@ -0,0 +1,41 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+pub const RNG_ADDRESS: u16 = 0xFE; // Programs read a new random byte from here after every instruction, like on easy6502
+
+/*
+    The random byte device. It used to just pull from thread_rng(), which meant no two runs were ever the same.
+    Now it is driven by a seed, so giving it the same seed always reproduces the same stream of bytes (and the same run).
+
+    By default it writes numbers between 1 and 15 (the colors snake expects), but it can also write the
+    full 0 - 255 range, which is what easy6502 does
+*/
+pub struct RandomDevice {
+    pub seed: u64,
+    pub full_range: bool,
+    rng: StdRng,
+}
+
+impl RandomDevice {
+    pub fn new(seed: u64, full_range: bool) -> Self {
+        RandomDevice {
+            seed,
+            full_range,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // No seed given, so pick one. It is kept in the device so that it can be printed and the run reproduced later
+    pub fn from_entropy(full_range: bool) -> Self {
+        let seed: u64 = rand::thread_rng().gen();
+        RandomDevice::new(seed, full_range)
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        if self.full_range {
+            self.rng.gen()
+        } else {
+            self.rng.gen_range(1, 16)
+        }
+    }
+}
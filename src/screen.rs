@@ -0,0 +1,53 @@
+use crate::processor::CPU;
+
+pub const W: u32 = 32; // Width
+pub const H: u32 = 32; // Height
+pub const FRAME_SIZE: usize = W as usize * 3 * H as usize; // Each pixel is represented by three bytes (RGB)
+
+pub const SCREEN_START: u16 = 0x0200;
+pub const SCREEN_END: u16 = 0x0600;
+
+/*
+    The screen lives in memory, one byte per pixel from 0x0200 to 0x05FF (32 x 32 pixels).
+    None of this needs SDL, so the same frame can be drawn in a window, saved to a file or hashed
+*/
+pub const BLACK: (u8, u8, u8) = (0, 0, 0);
+pub const WHITE: (u8, u8, u8) = (255, 255, 255);
+pub const BLUE: (u8, u8, u8) = (0, 0, 255);
+pub const CYAN: (u8, u8, u8) = (0, 255, 255);
+pub const GREEN: (u8, u8, u8) = (0, 255, 0);
+pub const YELLOW: (u8, u8, u8) = (255, 255, 0);
+
+pub const PALETTE: [(u8, u8, u8); 6] = [BLACK, WHITE, BLUE, CYAN, GREEN, YELLOW];
+
+// 0x200 - 0x600 is dedicated to the colors of each pixel
+pub fn color(byte: u8) -> (u8, u8, u8) {
+    match byte {
+        0 => BLACK,
+        1 => WHITE,
+        2..=7 => BLUE,
+        9..=11 => CYAN,
+        12..=14 => GREEN,
+        _ => YELLOW,
+    }
+}
+
+// Copies the screen memory into an RGB frame, returns true if anything changed
+pub fn check_screen_state(cpu: &CPU, frame: &mut [u8; FRAME_SIZE]) -> bool {
+    let mut frame_idx = 0;
+    let mut update = false;
+    for i in SCREEN_START..SCREEN_END {
+        let color_idx = cpu.peek_memory_u8(i);
+        let (b1, b2, b3) = color(color_idx);
+        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
+            frame[frame_idx] = b1;
+            frame[frame_idx + 1] = b2;
+            frame[frame_idx + 2] = b3;
+            update = true;
+        }
+
+        frame_idx += 3;
+    }
+
+    update
+}
@@ -0,0 +1,269 @@
+use crate::cli;
+use crate::loader::{self, Image};
+use crate::source_map::SourceLine;
+
+// Where a script went wrong, lines and columns start at 1
+#[derive(Debug, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+// What a single line of a script asks for
+#[derive(Debug, PartialEq)]
+pub enum ScriptLine {
+    Bytes(Vec<u8>), // Hex pairs, .byte or .word data, placed at the current address
+    Origin(u16), // * = $C000 or @C000, the following bytes go here
+    Vector(u16, u16), // .reset, .irq or .nmi, (where the vector is, the address it points to)
+}
+
+/*
+    Take in the 6502 assembly text, split it into lines, and process each one with the helper function.
+    Bytes start at 0x600 and every origin directive starts a new segment. Vectors are written after everything else,
+    so a script that declares its reset vector starts there and one that doesn't starts at its first byte.
+    Every line that puts bytes in memory is remembered in the image's source map, along with its comment
+*/
+pub fn read_script(text: &str, file: &str) -> Result<Image, ScriptError> {
+    let mut image = Image::default();
+    let mut vectors = Image::default();
+    let mut address = loader::PROGRAM_START;
+
+    for (number, line) in text.lines().enumerate() {
+        match process_line(line, number + 1)? {
+            ScriptLine::Bytes(bytes) if bytes.is_empty() => {} // Empty line or just a comment
+
+            ScriptLine::Bytes(bytes) => {
+                if address as usize + bytes.len() > 0x10000 {
+                    return Err(ScriptError { line: number + 1, column: 1, message: format!("Bytes at ${:04X} run past the end of memory", address) });
+                }
+
+                image.add(address, &bytes);
+                image.source.add(address, bytes.len(), source_line(file, number + 1, line));
+                address = address.wrapping_add(bytes.len() as u16);
+            }
+
+            ScriptLine::Origin(origin) => address = origin,
+            ScriptLine::Vector(vector, target) => {
+                vectors.add(vector, &target.to_le_bytes());
+                image.source.add(vector, 2, source_line(file, number + 1, line));
+            }
+        }
+    }
+
+    for segment in vectors.segments {
+        image.add(segment.address, &segment.data);
+    }
+
+    Ok(image)
+}
+
+// A line split into its code and its comment, for the source map
+fn source_line(file: &str, number: usize, line: &str) -> SourceLine {
+    let code = strip_comment(line);
+    SourceLine {
+        file: file.to_string(),
+        line: number,
+        code: code.trim().to_string(),
+        comment: line[code.len()..].trim_start_matches('/').trim().to_string(),
+    }
+}
+
+/*
+    Process a line character by character, checking for valid input. Plain lines are pairs of hex digits separated
+    by whitespace, and lines starting with *, @ or . are directives:
+
+    * = $C000 or @C000         following bytes go at 0xC000
+    .reset $C000               reset vector (0xFFFC), also .irq (0xFFFE) and .nmi (0xFFFA)
+    .byte $0A, 10, %1010, 'A'  bytes, numbers are decimal unless they start with $ (hex) or % (binary)
+    .byte "Hello", 0           strings are written out byte by byte
+    .word $C000, 1000          16-bit values, little-endian
+*/
+pub fn process_line(line: &str, number: usize) -> Result<ScriptLine, ScriptError> {
+    let error = |column: usize, message: &str| ScriptError { line: number, column, message: message.to_string() };
+
+    let trimmed = line.trim_start();
+    let indent = line.chars().count() - trimmed.chars().count();
+    if trimmed.starts_with('*') || trimmed.starts_with('@') || trimmed.starts_with('.') {
+        return process_directive(trimmed, indent).map_err(|(column, message)| error(column, &message));
+    }
+
+    let mut hex_arr: Vec<u8> = Vec::new();
+    let mut current_pair = String::new();
+
+    for (index, ch) in line.chars().enumerate() {
+        let column = index + 1;
+
+        // Check for comment in line, if so, skip it
+        if ch == '/' {
+            if !current_pair.is_empty() {
+                return Err(error(column, "Please don't put comments between hex digits"));
+            }
+
+            break;
+        }
+
+        else if ch.is_ascii_hexdigit() {
+            // We only accept pairs of hex digits, so max pair length can only be 2
+            if current_pair.len() >= 2 {
+                return Err(error(column, "Hex digits were not grouped in pairs or separated by a whitespace"));
+            }
+
+            // Add digit to pair
+            current_pair.push(ch);
+        }
+
+        // Whitespace, make sure there aren't any whitespaces between single hex digits
+        else if ch.is_whitespace() {
+            if !current_pair.is_empty() {
+                return Err(error(column - 1, "Single hex digits are not valid"));
+            }
+        }
+
+        // Anything else
+        else {
+            return Err(error(column, &format!("Unexpected character '{}'", ch)));
+        }
+
+        // We've found a pair, add it to our result array
+        if current_pair.len() == 2 {
+            hex_arr.push(u8::from_str_radix(&current_pair, 16).unwrap());
+            current_pair.clear();
+        }
+    }
+
+    // A lone digit at the very end of the line
+    if !current_pair.is_empty() {
+        return Err(error(line.trim_end().chars().count(), "Single hex digits are not valid"));
+    }
+
+    Ok(ScriptLine::Bytes(hex_arr))
+}
+
+// A directive, with the column it starts at for error messages. Errors come back as (column, message)
+fn process_directive(text: &str, indent: usize) -> Result<ScriptLine, (usize, String)> {
+    let text = strip_comment(text);
+    let column = |offset: usize| indent + text[..offset].chars().count() + 1;
+
+    // Split off the name, for * = $C000 the name is "*" and the "=" is skipped
+    let name_end = text.find(|ch: char| ch.is_whitespace() || ch == '=').unwrap_or(text.len());
+    let (name, mut rest_start) = if text.starts_with('@') { ("@", 1) } else { (&text[..name_end], name_end) };
+
+    rest_start += text[rest_start..].len() - text[rest_start..].trim_start().len();
+    if name == "*" {
+        if !text[rest_start..].starts_with('=') {
+            return Err((column(rest_start), String::from("Expected '=' after '*'")));
+        }
+        rest_start += 1;
+        rest_start += text[rest_start..].len() - text[rest_start..].trim_start().len();
+    }
+
+    let rest = text[rest_start..].trim_end();
+    let address = || cli::parse_address(rest).ok_or((column(rest_start), format!("Invalid address: '{}'", rest)));
+
+    match name.to_lowercase().as_str() {
+        "*" | "@" => Ok(ScriptLine::Origin(address()?)),
+        ".reset" => Ok(ScriptLine::Vector(0xFFFC, address()?)),
+        ".irq" => Ok(ScriptLine::Vector(0xFFFE, address()?)),
+        ".nmi" => Ok(ScriptLine::Vector(0xFFFA, address()?)),
+
+        ".byte" | ".word" => {
+            let word = name.eq_ignore_ascii_case(".word");
+            let mut bytes = Vec::new();
+
+            for (offset, value) in split_values(rest).map_err(|offset| (column(rest_start + offset), String::from("Unterminated string or character")))? {
+                let error = |message: String| (column(rest_start + offset), message);
+
+                if let Some(string) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+                    if word || !string.is_ascii() {
+                        return Err(error(String::from("Strings can only be ASCII and only go in .byte")));
+                    }
+                    bytes.extend_from_slice(string.as_bytes());
+                    continue;
+                }
+
+                let number = parse_value(value).ok_or_else(|| error(format!("Invalid value: '{}'", value)))?;
+                if word {
+                    let number = u16::try_from(number).map_err(|_| error(format!("{} doesn't fit in a word", value)))?;
+                    bytes.extend_from_slice(&number.to_le_bytes());
+                } else {
+                    let number = u8::try_from(number).map_err(|_| error(format!("{} doesn't fit in a byte", value)))?;
+                    bytes.push(number);
+                }
+            }
+
+            if bytes.is_empty() {
+                return Err((column(rest_start), format!("{} needs at least one value", name)));
+            }
+            Ok(ScriptLine::Bytes(bytes))
+        }
+
+        _ => Err((column(0), format!("Unknown directive '{}'", name))),
+    }
+}
+
+// Everything before the first / that isn't inside quotes
+pub fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (index, ch) in text.char_indices() {
+        match (ch, quote) {
+            ('"' | '\'', None) => quote = Some(ch),
+            (_, Some(open)) if ch == open => quote = None,
+            ('/', None) => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+// Comma separated values along with where each one starts. Err is where an unterminated quote starts
+fn split_values(text: &str) -> Result<Vec<(usize, &str)>, usize> {
+    let mut values = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+
+    for (index, ch) in text.char_indices() {
+        match (ch, quote) {
+            ('"' | '\'', None) => quote = Some((ch, index)),
+            (_, Some((open, _))) if ch == open => quote = None,
+            (',', None) => {
+                values.push((start, &text[start..index]));
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((_, index)) = quote {
+        return Err(index);
+    }
+    if !text.trim().is_empty() {
+        values.push((start, &text[start..]));
+    }
+
+    // Point at the value itself, not the spaces in front of it
+    Ok(values.into_iter().map(|(start, value)| (start + value.len() - value.trim_start().len(), value.trim())).collect())
+}
+
+// Numbers in .byte and .word: decimal, $ or 0x for hex, % for binary, or a character in single quotes
+fn parse_value(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix('%') {
+        u32::from_str_radix(binary, 2).ok()
+    } else if let Some(ch) = text.strip_prefix('\'').and_then(|text| text.strip_suffix('\'')) {
+        let mut chars = ch.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) if ch.is_ascii() => Some(ch as u32),
+            _ => None,
+        }
+    } else {
+        text.parse().ok()
+    }
+}
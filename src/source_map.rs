@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+// A line of a script, split into the bytes and the comment next to them
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize, // Starting at 1
+    pub code: String,
+    pub comment: String,
+}
+
+impl SourceLine {
+    // script.txt:6  a9 0a  // Load 0x0A into register a
+    pub fn describe(&self) -> String {
+        if self.comment.is_empty() {
+            format!("{}:{}  {}", self.file, self.line, self.code)
+        } else {
+            format!("{}:{}  {}  // {}", self.file, self.line, self.code, self.comment)
+        }
+    }
+}
+
+/*
+    Which line of the script every byte in memory came from, so the trace and the debugger can show
+    the original line and its comment next to each instruction. A line can hold more than one instruction,
+    so any address inside a line's bytes finds that line
+*/
+#[derive(Clone, Default)]
+pub struct SourceMap {
+    lines: BTreeMap<u16, (usize, SourceLine)>, // Start address -> (number of bytes, line)
+}
+
+impl SourceMap {
+    pub fn add(&mut self, address: u16, length: usize, line: SourceLine) {
+        if length > 0 {
+            self.lines.insert(address, (length, line));
+        }
+    }
+
+    // The line the byte at this address came from
+    pub fn lookup(&self, address: u16) -> Option<&SourceLine> {
+        let (start, (length, line)) = self.lines.range(..=address).next_back()?;
+        if ((address - start) as usize) < *length {
+            Some(line)
+        } else {
+            None
+        }
+    }
+}
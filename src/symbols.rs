@@ -0,0 +1,139 @@
+use crate::cli::parse_address;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/*
+    Labels from an assembler, so addresses can be shown (and typed in) by name: JSR $0606 becomes JSR init.
+    Three kinds of files are understood:
+
+    VICE labels (ca65 -Ln, ACME, 64tass):   al C:0606 .init
+    ld65 debug info (ld65 --dbgfile):       sym id=0,name="init",...,val=0x606,...,type=lab
+    A plain list (ACME -l, 64tass -l):      init = $0606
+
+    When an address has more than one name the first one is shown, except that ld65 labels win over constants
+*/
+#[derive(Default)]
+pub struct Symbols {
+    names: HashMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    // Read every file given, later files add to the earlier ones
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for path in paths {
+            let path = path.as_ref();
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            symbols.parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(symbols)
+    }
+
+    // Add the symbols in a file, whichever of the three kinds it is
+    pub fn parse(&mut self, text: &str) -> Result<(), String> {
+        let first = text.lines().map(|line| line.trim()).find(|line| !line.is_empty()).unwrap_or("");
+
+        if first.starts_with("version") {
+            self.parse_dbg(text);
+            return Ok(());
+        }
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+
+            let (name, address) = if let Some(rest) = line.strip_prefix("al ") {
+                parse_vice(rest)
+            } else {
+                parse_assignment(line)
+            }.ok_or(format!("line {}: not a label: {}", number + 1, line))?;
+
+            self.add(name, address);
+        }
+
+        Ok(())
+    }
+
+    // Only the sym lines matter, everything else (files, lines, segments, scopes) is skipped
+    fn parse_dbg(&mut self, text: &str) {
+        let mut constants = Vec::new();
+
+        for line in text.lines() {
+            let fields = match line.strip_prefix("sym") {
+                Some(fields) if fields.starts_with(char::is_whitespace) => fields.trim(),
+                _ => continue,
+            };
+
+            let field = |key: &str| fields.split(',').find_map(|field| field.strip_prefix(key)?.strip_prefix('='));
+            let name = field("name").map(|name| name.trim_matches('"'));
+            let value = field("val").and_then(|value| value.strip_prefix("0x")).and_then(|value| u16::from_str_radix(value, 16).ok());
+
+            if let (Some(name), Some(address)) = (name, value) {
+                match field("type") {
+                    Some("lab") => self.add(name, address),
+                    Some("equ") => constants.push((name, address)),
+                    _ => {}
+                }
+            }
+        }
+
+        for (name, address) in constants {
+            self.add(name, address);
+        }
+    }
+
+    pub fn add(&mut self, name: &str, address: u16) {
+        self.names.entry(address).or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    // The name shown for an address
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(|name| name.as_str())
+    }
+
+    // The address a label stands for
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    // An address typed in by the user: a label, or a hex address with or without a $ in front
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        let text = text.trim();
+        self.addresses.get(text).copied().or_else(|| parse_address(text))
+    }
+
+    // The label if there is one, otherwise $XXXX (or $XX in the zero page)
+    pub fn format(&self, address: u16, zero_page: bool) -> String {
+        match self.name(address) {
+            Some(name) => name.to_string(),
+            None if zero_page => format!("${:02X}", address),
+            None => format!("${:04X}", address),
+        }
+    }
+}
+
+// C:0606 .init (the C: memory space is optional)
+fn parse_vice(rest: &str) -> Option<(&str, u16)> {
+    let mut parts = rest.split_whitespace();
+    let address = parts.next()?;
+    let address = address.strip_prefix("C:").unwrap_or(address);
+    let name = parts.next()?;
+
+    Some((name.strip_prefix('.').unwrap_or(name), u16::from_str_radix(address, 16).ok()?))
+}
+
+// init = $0606
+fn parse_assignment(line: &str) -> Option<(&str, u16)> {
+    let (name, address) = line.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some((name, parse_address(address)?))
+}
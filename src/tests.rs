@@ -1,5 +1,67 @@
 #[allow(unused_imports)]
 use crate::processor::*;
+#[allow(unused_imports)]
+use crate::rng::*;
+#[allow(unused_imports)]
+use crate::movie::*;
+#[allow(unused_imports)]
+use crate::snake::SNAKE_OPCODES;
+#[allow(unused_imports)]
+use crate::screen::*;
+#[allow(unused_imports)]
+use crate::capture::*;
+#[allow(unused_imports)]
+use crate::disassembler::*;
+#[allow(unused_imports)]
+use crate::console::*;
+#[allow(unused_imports)]
+use crate::apple1::*;
+#[allow(unused_imports)]
+use crate::via::*;
+#[allow(unused_imports)]
+use crate::acia::*;
+#[allow(unused_imports)]
+use crate::lcd::*;
+#[allow(unused_imports)]
+use crate::breadboard::*;
+#[allow(unused_imports)]
+use crate::devices::Device;
+#[allow(unused_imports)]
+use crate::loader::*;
+#[allow(unused_imports)]
+use crate::script::{read_script, process_line, ScriptLine};
+#[allow(unused_imports)]
+use crate::symbols::*;
+#[allow(unused_imports)]
+use crate::debugger::*;
+#[allow(unused_imports)]
+use crate::source_map::*;
+#[allow(unused_imports)]
+use crate::profiler::*;
+#[allow(unused_imports)]
+use crate::coverage::*;
+#[allow(unused_imports)]
+use crate::gdb::*;
+#[allow(unused_imports)]
+use crate::expression::*;
+#[allow(unused_imports)]
+use crate::watch::{self, Source};
+#[allow(unused_imports)]
+use crate::history::*;
+#[allow(unused_imports)]
+use crate::call_stack::*;
+#[allow(unused_imports)]
+use crate::dump::*;
+#[allow(unused_imports)]
+use crate::expect::{self, After};
+#[allow(unused_imports)]
+use crate::cli::Options;
+#[allow(unused_imports)]
+use crate::opcode_info::OPCODES_TABLE;
+#[allow(unused_imports)]
+use crate::fuzzing;
+#[allow(unused_imports)]
+use std::path::Path;
 
 #[cfg(test)]
 mod test {
@@ -186,6 +248,11 @@ mod test {
         let mut cpu: CPU = CPU::new();
         cpu.load_and_execute(vec![0xA9, 0x0A, 0x20, 0x06, 0x06, 0x00, 0xA9, 0x01, 0x60, 0x00]);
         assert_eq!(cpu.register_a, 1); 
+        assert_eq!((cpu.peek_memory_u8(0x01FF), cpu.peek_memory_u8(0x01FE)), (0x06, 0x04)); // JSR pushed its last byte, $0604
+
+        // NOPs are as long as the opcode table says, and so is CMP absolute: NOP, NOP #$A9, CMP $0200, LDA #$01
+        cpu.load_and_execute(vec![0xEA, 0x80, 0xA9, 0xCD, 0x00, 0x02, 0xA9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 1);
 
         cpu.load_and_execute(vec![0xA2, 0x0A, 0x4C, 0x07, 0x06, 0xA2, 0x05, 0xE8, 0x00]);
         assert_eq!(cpu.register_x, 11); 
@@ -199,6 +266,12 @@ mod test {
 
         cpu.load_and_execute(vec![0x08, 0x38, 0x28, 0x00]);
         assert_eq!(cpu.status_flags, 0b0000_0000);
+
+        // PHP pushes the break bit and bit 5 as 1, but neither ends up in P
+        cpu.load(&vec![0x08, 0x00]);
+        cpu.program_counter = 0x0600;
+        cpu.step();
+        assert_eq!((cpu.peek_memory_u8(0x01FF), cpu.status_flags), (0b0011_0000, 0b0000_0000));
     }
 
     #[test]
@@ -214,7 +287,11 @@ mod test {
         assert_eq!(cpu.register_a, 0x02);
 
         cpu.load_and_execute(vec![0xA9, 0x06, 0x6A, 0x00]);
-        assert_eq!(cpu.register_a, 0x02);
+        assert_eq!(cpu.register_a, 0x03);
+
+        // The carry goes into bit 7 and bit 0 comes out into the carry
+        cpu.load_and_execute(vec![0xA9, 0x01, 0x38, 0x6A, 0x00]);
+        assert_eq!((cpu.register_a, cpu.status_flags & 0b1000_0001), (0x80, 0b1000_0001));
     }
 
     #[test]
@@ -226,20 +303,21 @@ mod test {
         cpu.load_and_execute(vec![0xA9, 0x81, 0x38, 0x69, 0x7F, 0x00]);
         assert_eq!(cpu.register_a, 0x01);
 
-        cpu.load_and_execute(vec![0xA9, 0x0A, 0xE9, 0x03, 0x00]); // 10 - 3
-        assert_eq!(cpu.register_a, 0x07);
+        cpu.load_and_execute(vec![0xA9, 0x0A, 0xE9, 0x03, 0x00]); // 10 - 3, borrowing 1 since carry is clear
+        assert_eq!(cpu.register_a, 0x06);
 
         cpu.load_and_execute(vec![0xA9, 0x05, 0x38, 0xE9, 0x0A, 0x00]); // 5 - 10 (with carry)
-        assert_eq!(cpu.register_a, 0xFC); 
+        assert_eq!(cpu.register_a, 0xFB); 
 
         cpu.load_and_execute(vec![0xA9, 0x05, 0xE9, 0x0A, 0x00]); // 5 - 10 (without carry)
-        assert_eq!(cpu.register_a, 0xFB); 
+        assert_eq!(cpu.register_a, 0xFA); 
 
         cpu.load_and_execute(vec![0xA9, 0x0A, 0x38, 0xE9, 0x05, 0x00]); // 10 - 5 (with carry)
-        assert_eq!(cpu.register_a, 0x06); 
+        assert_eq!(cpu.register_a, 0x05); 
+        assert_eq!(cpu.status_flags & 0b0000_0001, 1); // No borrow
 
         cpu.load_and_execute(vec![0xA9, 0x0A, 0xE9, 0x05, 0x00]); // 10 - 5 without carry
-        assert_eq!(cpu.register_a, 0x05); 
+        assert_eq!(cpu.register_a, 0x04); 
     }
 
     #[test]
@@ -251,4 +329,1233 @@ mod test {
         cpu.load_and_execute(vec![0x10, 0x02, 0xA9, 0x0A, 0xA9, 0x05, 0x00]);
         assert_eq!(cpu.register_a, 0x05);
     }
+
+    #[test]
+    fn test_seeded_random_device() {
+        // The same seed always gives the same bytes
+        let mut first = RandomDevice::new(42, false);
+        let mut second = RandomDevice::new(42, false);
+        for _ in 0..100 {
+            let byte = first.next_byte();
+            assert_eq!(byte, second.next_byte());
+            assert!((1..16).contains(&byte));
+        }
+
+        // And a seeded run writes the same byte into 0xFE every time
+        let mut cpu = CPU::new();
+        cpu.random = Some(RandomDevice::new(7, true));
+        cpu.load_and_execute(vec![0xEA, 0xA5, 0xFE, 0x00]);
+        let first_run = cpu.register_a;
+
+        cpu.random = Some(RandomDevice::new(7, true));
+        cpu.load_and_execute(vec![0xEA, 0xA5, 0xFE, 0x00]);
+        assert_eq!(cpu.register_a, first_run);
+    }
+
+    #[test]
+    fn test_movie_replay() {
+        // Play snake for a while without a window, pressing down at frame 2000
+        let random = RandomDevice::new(1234, false);
+        let mut movie = Movie::new(&random);
+        let mut cpu = CPU::new();
+        cpu.load(&SNAKE_OPCODES.to_vec());
+        cpu.random = Some(random);
+
+        let mut frame = 0;
+        while frame < 5000 && cpu.step() {
+            frame += 1;
+            if frame == 2000 {
+                cpu.write_memory_u8(KEY_ADDRESS, 0x73);
+                movie.record_input(frame, 0x73);
+            }
+        }
+
+        movie.finish(frame, &cpu);
+        assert!(movie.replay(&mut CPU::new(), SNAKE_OPCODES, |_, _| {}).is_ok());
+
+        // The movie survives being written to a file and read back
+        let path = std::env::temp_dir().join("astrobyte_test.movie");
+        movie.save(&path).unwrap();
+        let loaded = Movie::load(&path).unwrap();
+        assert!(loaded.replay(&mut CPU::new(), SNAKE_OPCODES, |_, _| {}).is_ok());
+
+        // Without the key press the run ends up somewhere else
+        movie.inputs.clear();
+        assert!(movie.replay(&mut CPU::new(), SNAKE_OPCODES, |_, _| {}).is_err());
+
+        // The hashes cover both ends of their ranges, right up to the IRQ vector's last byte
+        let mut cpu = CPU::new();
+        let (memory, framebuffer) = (memory_hash(&cpu), framebuffer_hash(&cpu));
+        cpu.write_memory_u8(0xFFFF, 0x01);
+        assert_ne!(memory_hash(&cpu), memory);
+        cpu.write_memory_u8(0x05FF, 0x01);
+        assert_ne!(framebuffer_hash(&cpu), framebuffer);
+    }
+
+    #[test]
+    fn test_capture_frames() {
+        // Paint the top left pixel white
+        let mut cpu = CPU::new();
+        cpu.write_memory_u8(0x0200, 0x01);
+        let mut frame = [0u8; FRAME_SIZE];
+        assert!(check_screen_state(&cpu, &mut frame));
+        assert_eq!(&frame[0..6], &[255, 255, 255, 0, 0, 0]);
+        assert!(!check_screen_state(&cpu, &mut frame));
+
+        // Only frames 2 to 3 get written as raw frames
+        let directory = std::env::temp_dir().join("astrobyte_test_frames");
+        let _ = std::fs::remove_dir_all(&directory);
+        let mut recorder = Recorder::new(&directory, 1, 2, 3).unwrap();
+        for frame_number in 0..5 {
+            recorder.capture(frame_number, &frame).unwrap();
+        }
+        assert_eq!(recorder.frames_written(), 2);
+        assert_eq!(std::fs::read(directory.join("frame_00000.rgb")).unwrap(), frame.to_vec());
+
+        // Screenshots are scaled up
+        let path = std::env::temp_dir().join("astrobyte_test.png");
+        save_png(&frame, 2, &path).unwrap();
+        let png = std::fs::read(&path).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert_eq!(&png[16..24], &[0, 0, 0, 64, 0, 0, 0, 64]);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xA9, 0x0A, 0x9D, 0x00, 0x02, 0xD0, 0xFB, 0x6C, 0xFC, 0xFF, 0x0A, 0xFF]);
+
+        assert_eq!(disassemble(&cpu, 0x0600), (String::from("LDA #$0A"), 2));
+        assert_eq!(disassemble(&cpu, 0x0602), (String::from("STA $0200,X"), 3));
+        assert_eq!(disassemble(&cpu, 0x0605), (String::from("BNE $0602"), 2));
+        assert_eq!(disassemble(&cpu, 0x0607), (String::from("JMP ($FFFC)"), 3));
+        assert_eq!(disassemble(&cpu, 0x060A), (String::from("ASL A"), 1));
+        assert_eq!(disassemble(&cpu, 0x060B), (String::from(".byte $FF"), 1));
+    }
+
+    // Lets a test see what a device printed
+    struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_console_echo() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let output = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        for byte in b"hi\n" {
+            sender.send(*byte).unwrap();
+        }
+
+        let mut cpu = CPU::new();
+        cpu.attach(Box::new(ConsoleDevice::new(CONSOLE_OUTPUT, CONSOLE_INPUT, receiver, Box::new(SharedOutput(output.clone())))));
+
+        // Echo characters until a newline comes through, waiting while nothing is typed
+        cpu.load_and_execute(vec![0xAD, 0x04, 0xF0, 0xF0, 0xFB, 0x8D, 0x01, 0xF0, 0xC9, 0x0A, 0xD0, 0xF4, 0x00]);
+        assert_eq!(output.borrow().as_slice(), b"hi\n");
+
+        // Nothing left to read
+        assert_eq!(cpu.read_memory_u8(CONSOLE_INPUT), 0);
+    }
+
+    #[test]
+    fn test_apple1_wozmon() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let output = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        for byte in b"300: a9 42 85 10 00\nff00\n300R\n" {
+            sender.send(*byte).unwrap();
+        }
+        drop(sender);
+
+        let (mut cpu, input_closed) = apple1(receiver, Box::new(SharedOutput(output.clone())));
+        assert_eq!(cpu.program_counter, WOZMON_START);
+
+        // Store a program, examine the ROM, then run the program (which ends with BRK)
+        let mut steps = 0;
+        while !input_closed.load(std::sync::atomic::Ordering::Relaxed) && cpu.step() {
+            steps += 1;
+            assert!(steps < 1_000_000, "WozMon never finished reading the input");
+        }
+
+        let text = String::from_utf8(output.borrow().clone()).unwrap();
+        assert!(text.contains("300: A9 42 85 10 00"));
+        assert!(text.contains("FF00: D8"));
+        assert_eq!(cpu.peek_memory_u8(0x0010), 0x42);
+
+        // Only the first 4K is RAM
+        cpu.write_memory_u8(0x2000, 0x55);
+        assert_eq!(cpu.peek_memory_u8(0x2000), 0x00);
+        cpu.write_memory_u8(0xFF00, 0x55);
+        assert_eq!(cpu.peek_memory_u8(0xFF00), 0xD8);
+    }
+
+    #[test]
+    fn test_via_timer_interrupt() {
+        let mut cpu = CPU::new();
+        cpu.attach(Box::new(Via::new(0x6000)));
+        cpu.write_memory_u16(0xFFFE, 0x0620); // IRQ handler
+
+        // Free-running timer 1 with interrupts on, wait until the handler has run three times
+        cpu.load_and_execute(vec![
+            0xA9, 0x40, 0x8D, 0x0B, 0x60, // ACR = free-run
+            0xA9, 0xC0, 0x8D, 0x0E, 0x60, // IER = timer 1
+            0xA9, 0x10, 0x8D, 0x04, 0x60, // Latch low
+            0xA9, 0x00, 0x8D, 0x05, 0x60, // Latch high, starts the timer
+            0x58, // CLI
+            0xA5, 0x10, 0xC9, 0x03, 0xD0, 0xFA, // Loop until [$10] == 3
+            0x00, 0xEA, 0xEA, 0xEA, 0xEA,
+            0xE6, 0x10, 0x2C, 0x04, 0x60, 0x40, // Handler: INC $10, BIT T1C_L (clears the flag), RTI
+        ]);
+
+        assert_eq!(cpu.register_a, 0x03);
+    }
+
+    #[test]
+    fn test_via_registers() {
+        let mut via = Via::new(0x6000);
+
+        // Only the output pins show what was written, inputs are pulled up
+        via.write(0x6000 + DDRB, 0x0F);
+        via.write(0x6000 + ORB, 0xA5);
+        assert_eq!(via.read(0x6000 + ORB), 0xF5);
+
+        // Something pulling every input pin low
+        struct Grounded;
+        impl Pins for Grounded {
+            fn sense_b(&mut self) -> u8 {
+                0x00
+            }
+        }
+
+        via.connect(Box::new(Grounded));
+        assert_eq!(via.read(0x6000 + ORB), 0x05);
+
+        // One-shot timer 2 flags once it runs past zero
+        via.write(0x6000 + IER, 0x80 | IRQ_T2);
+        via.write(0x6000 + T2C_L, 0x05);
+        via.write(0x6000 + T2C_H, 0x00);
+        via.tick(4);
+        assert!(!via.irq());
+        via.tick(2);
+        assert!(via.irq());
+        assert_eq!(via.peek(0x6000 + IFR), 0x80 | IRQ_T2);
+
+        // Reading the low byte clears the flag, and it doesn't go off again
+        via.read(0x6000 + T2C_L);
+        via.tick(200);
+        assert!(!via.irq());
+
+        // Shifting out under the system clock, the byte comes back around after 8 bits
+        via.write(0x6000 + ACR, 0b0001_1000);
+        via.write(0x6000 + SR, 0x81);
+        via.tick(7);
+        assert_eq!(via.peek(0x6000 + IFR) & IRQ_SR, 0);
+        via.tick(1);
+        assert_eq!(via.peek(0x6000 + IFR) & IRQ_SR, IRQ_SR);
+        assert_eq!(via.peek(0x6000 + SR), 0x81);
+    }
+
+    #[test]
+    fn test_acia_echo() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let output = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        for byte in b"6551\n" {
+            sender.send(*byte).unwrap();
+        }
+
+        let mut cpu = CPU::new();
+        cpu.attach(Box::new(Acia::new(0x8000, receiver, Box::new(SharedOutput(output.clone())))));
+
+        // Wait for a byte, send it back, stop after the newline
+        cpu.load_and_execute(vec![0xAD, 0x01, 0x80, 0x29, 0x08, 0xF0, 0xF9, 0xAD, 0x00, 0x80, 0x8D, 0x00, 0x80, 0xC9, 0x0A, 0xD0, 0xEF, 0x00]);
+        assert_eq!(output.borrow().as_slice(), b"6551\n");
+    }
+
+    #[test]
+    fn test_acia_receive_irq() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut acia = Acia::new(0x8000, receiver, Box::new(std::io::sink()));
+
+        // Nothing happens with the receiver off
+        sender.send(b'A').unwrap();
+        acia.tick(2);
+        assert!(!acia.irq());
+        assert_eq!(acia.read(0x8000 + DATA), b'A');
+
+        acia.write(0x8000 + COMMAND, COMMAND_DTR);
+        sender.send(b'B').unwrap();
+        sender.send(b'C').unwrap();
+        acia.tick(2);
+        assert!(acia.irq());
+
+        // Reading the status clears the interrupt, reading the data makes room for the next byte
+        assert_eq!(acia.read(0x8000 + STATUS), STATUS_IRQ | STATUS_TRANSMIT_EMPTY | STATUS_RECEIVE_FULL);
+        assert!(!acia.irq());
+        assert_eq!(acia.read(0x8000 + DATA), b'B');
+        acia.tick(2);
+        assert_eq!(acia.read(0x8000 + DATA), b'C');
+        acia.read(0x8000 + STATUS);
+
+        // Interrupts can be turned off while still receiving
+        acia.write(0x8000 + COMMAND, COMMAND_DTR | COMMAND_RECEIVE_IRQ_OFF);
+        sender.send(b'D').unwrap();
+        acia.tick(2);
+        assert!(!acia.irq());
+        assert_eq!(acia.read(0x8000 + STATUS) & STATUS_RECEIVE_FULL, STATUS_RECEIVE_FULL);
+    }
+
+    #[test]
+    fn test_breadboard_hello_world() {
+        // The tutorial's first LCD program: set the LCD up, then print a message one character at a time,
+        // waiting on the busy flag before every write
+        let program = [
+            0xA2, 0xFF, 0x9A, 0xA9, 0xFF, 0x8D, 0x02, 0x60, 0xA9, 0xE0, 0x8D, 0x03, 0x60, 0xA9, 0x38, 0x20,
+            0x53, 0x80, 0xA9, 0x0E, 0x20, 0x53, 0x80, 0xA9, 0x06, 0x20, 0x53, 0x80, 0xA9, 0x01, 0x20, 0x53,
+            0x80, 0xA2, 0x00, 0xBD, 0x7F, 0x80, 0xF0, 0x07, 0x20, 0x69, 0x80, 0xE8, 0x4C, 0x23, 0x80, 0x00,
+            0x48, 0xA9, 0x00, 0x8D, 0x02, 0x60, 0xA9, 0x40, 0x8D, 0x01, 0x60, 0xA9, 0xC0, 0x8D, 0x01, 0x60,
+            0xAD, 0x00, 0x60, 0x29, 0x80, 0xD0, 0xEF, 0xA9, 0x40, 0x8D, 0x01, 0x60, 0xA9, 0xFF, 0x8D, 0x02,
+            0x60, 0x68, 0x60, 0x20, 0x30, 0x80, 0x8D, 0x00, 0x60, 0xA9, 0x00, 0x8D, 0x01, 0x60, 0xA9, 0x80,
+            0x8D, 0x01, 0x60, 0xA9, 0x00, 0x8D, 0x01, 0x60, 0x60, 0x20, 0x30, 0x80, 0x8D, 0x00, 0x60, 0xA9,
+            0x20, 0x8D, 0x01, 0x60, 0xA9, 0xA0, 0x8D, 0x01, 0x60, 0xA9, 0x20, 0x8D, 0x01, 0x60, 0x60, 0x48,
+            0x65, 0x6C, 0x6C, 0x6F, 0x2C, 0x20, 0x77, 0x6F, 0x72, 0x6C, 0x64, 0x21, 0x00,
+
+        ];
+
+        let mut rom = vec![0xFF; ROM_SIZE];
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0x7FFC] = 0x00; // Reset vector, 0x8000
+        rom[0x7FFD] = 0x80;
+
+        let (mut cpu, lcd) = breadboard(&rom, Wiring::Parallel);
+        while cpu.step() {}
+
+        assert_eq!(lcd.borrow().line(0), "Hello, world!   ");
+        assert_eq!(lcd.borrow().line(1), " ".repeat(16));
+
+        // The ROM can't be written to, the RAM can
+        cpu.write_memory_u8(0x8000, 0x00);
+        assert_eq!(cpu.peek_memory_u8(0x8000), 0xA2);
+        cpu.write_memory_u8(0x7FFF, 0x12);
+        assert_eq!(cpu.peek_memory_u8(0x7FFF), 0x12);
+    }
+
+    #[test]
+    fn test_lcd_4bit() {
+        let lcd = std::rc::Rc::new(std::cell::RefCell::new(Lcd::new()));
+        let mut pins = LcdPins::new(lcd.clone(), Wiring::Nibble);
+
+        // Pulse E with RS and RW set as given, the data half is on PB0 - PB3
+        let mut send = |half: u8, rs: bool| {
+            let control = if rs { 0x10 } else { 0x00 };
+            pins.drive(0, control | half);
+            pins.drive(0, control | 0x40 | half);
+            pins.drive(0, control | half);
+        };
+
+        send(0x2, false); // Function set, 4-bit (arrives as a single half while still in 8-bit mode)
+        for byte in [0x28, 0x0C, 0x06, 0x01, 0xC0] { // 2 lines, display on, increment, clear, second line
+            send(byte >> 4, false);
+            send(byte & 0x0F, false);
+        }
+        for byte in b"4-bit" {
+            send(byte >> 4, true);
+            send(byte & 0x0F, true);
+        }
+
+        assert_eq!(lcd.borrow().line(1), "4-bit           ");
+
+        // The address counter comes back in two halves as well, busy flag clear
+        pins.drive(0, 0x20 | 0x40);
+        assert_eq!(pins.sense_b(), 0x04);
+        pins.drive(0, 0x20);
+        pins.drive(0, 0x20 | 0x40);
+        assert_eq!(pins.sense_b(), 0x05);
+    }
+
+    #[test]
+    fn test_lcd_address_wrap() {
+        fn write(lcd: &mut Lcd, rs: bool, bytes: &[u8]) {
+            for byte in bytes {
+                lcd.pins(true, rs, false, *byte);
+                lcd.pins(false, rs, false, *byte);
+            }
+        }
+
+        // Past the end of the second line folds back into it, and writing there doesn't run off the end of DDRAM
+        let mut lcd = Lcd::new();
+        write(&mut lcd, false, &[0x38, 0x0C, 0xFF]); // 8-bit, 2 lines, display on, Set DDRAM address 0x7F
+        write(&mut lcd, true, b"AB");
+        write(&mut lcd, false, &[0xFF]);
+        write(&mut lcd, true, b"C");
+        write(&mut lcd, false, &[0xE7]); // 0x67, the last character of the second line
+        write(&mut lcd, true, b"DE"); // E goes on at the start of the first line
+
+        lcd.pins(true, false, true, 0);
+        assert_eq!(lcd.data_out(), 0x01);
+        assert_eq!(lcd.line(0), "E               ");
+
+        // One line has 80 characters, and going to two lines keeps the address inside them
+        let mut lcd = Lcd::new();
+        write(&mut lcd, false, &[0x30, 0xFF, 0x38]); // 8-bit and 1 line, Set DDRAM address 0x7F, 2 lines
+        write(&mut lcd, true, b"A");
+        lcd.pins(true, false, true, 0);
+        assert_eq!(lcd.data_out(), 0x08); // 0x7F is 0x2F with one line, which is column 7 of the first line with two
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        // LDA #$2A, STA $0200, BRK at $C000, then a start linear address record
+        let text = ":06C00000A92A8D000200D8\n:040000050000C00037\n:00000001FF\n";
+        let image = intel_hex(text).unwrap();
+
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].address, 0xC000);
+        assert_eq!(image.segments[0].data, vec![0xA9, 0x2A, 0x8D, 0x00, 0x02, 0x00]);
+        assert_eq!(image.entry_point(), Some(0xC000));
+
+        let mut cpu = CPU::new();
+        image.load_into(&mut cpu);
+        cpu.execute();
+        assert_eq!(cpu.peek_memory_u8(0x0200), 0x2A);
+
+        // A wrong checksum is reported with its line
+        let error = intel_hex(":06C00000A92A8D000200D9\n").err().unwrap();
+        assert!(error.contains("line 1") && error.contains("checksum"));
+    }
+
+    #[test]
+    fn test_s_record_and_prg() {
+        let text = "S00600004844521B\nS1070600A9078500BD\nS9030600F6\n";
+        let image = s_record(text).unwrap();
+        assert_eq!(image.segments[0].address, 0x0600);
+        assert_eq!(image.segments[0].data, vec![0xA9, 0x07, 0x85, 0x00]);
+        assert_eq!(image.start, Some(0x0600));
+        assert!(s_record("S1070600A9078500BE\n").is_err());
+
+        // The load address comes first, little-endian
+        let image = prg(&[0x01, 0x08, 0xA9, 0x01]).unwrap();
+        assert_eq!(image.segments[0].address, 0x0801);
+        assert_eq!(image.segments[0].data, vec![0xA9, 0x01]);
+
+        // Running off the end of memory is an error, not a wrap
+        assert!(binary(&[0; 4], 0xFFFE).is_err());
+
+        // A binary that brings its own reset vector starts there
+        let mut rom = vec![0xEA; 0x100];
+        rom[0xFC] = 0x34;
+        rom[0xFD] = 0x12;
+        assert_eq!(binary(&rom, 0xFF00).unwrap().entry_point(), None);
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect(Path::new("script.txt"), b"a9 01"), Format::Script);
+        assert_eq!(detect(Path::new("game.PRG"), b"\x01\x08"), Format::Prg);
+        assert_eq!(detect(Path::new("code"), b":00000001FF\n"), Format::IntelHex);
+        assert_eq!(detect(Path::new("code"), b"S9030600F6\n"), Format::SRecord);
+        assert_eq!(detect(Path::new("code"), b"a9 01 // LDA #$01\n00"), Format::Script);
+        assert_eq!(detect(Path::new("code"), &[0xA9, 0x01, 0x00]), Format::Binary);
+    }
+
+    #[test]
+    fn test_script_directives() {
+        let script = "\
+            // Code at $C000, message at $C100
+            * = $C000
+            a2 00       // LDX #$00
+            bd 00 c1    // LDA $C100,X
+            f0 06       // BEQ done
+            9d 00 02    // STA $0200,X
+            e8          // INX
+            d0 f5       // BNE loop
+            00          // done: BRK
+
+            @C100
+            .byte \"Hi/\", 0   // The / in the string isn't a comment
+            .word $1234, 513
+            .reset $C000
+        ";
+
+        let image = read_script(script, "test.txt").unwrap();
+        assert_eq!(image.segments.len(), 3);
+        assert_eq!(image.segments[1].address, 0xC100);
+        assert_eq!(image.segments[1].data, vec![b'H', b'i', b'/', 0x00, 0x34, 0x12, 0x01, 0x02]);
+        assert_eq!(image.segments[2].address, 0xFFFC);
+        assert_eq!(image.entry_point(), None);
+
+        let mut cpu = CPU::new();
+        image.load_into(&mut cpu);
+        cpu.execute();
+        assert_eq!(cpu.peek_memory_u8(0x0200), b'H');
+        assert_eq!(cpu.peek_memory_u8(0x0202), b'/');
+        assert_eq!(cpu.peek_memory_u8(0x0203), 0x00);
+
+        // Without a reset vector, scripts still start at 0x600
+        assert_eq!(read_script("a9 01\n.irq $0700", "test.txt").unwrap().entry_point(), Some(0x0600));
+        assert_eq!(process_line(".byte %1010, 'A', $FF", 1).unwrap(), ScriptLine::Bytes(vec![0x0A, 0x41, 0xFF]));
+        assert_eq!(process_line("  *=C000", 1).unwrap(), ScriptLine::Origin(0xC000));
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let error = |script: &str| {
+            let error = read_script(script, "test.txt").err().unwrap();
+            (error.line, error.column)
+        };
+
+        assert_eq!(error("a9 01\na9 0 1"), (2, 4));
+        assert_eq!(error("a9 01\na9 012"), (2, 6));
+        assert_eq!(error("a9 0/ comment"), (1, 5));
+        assert_eq!(error("a9 0"), (1, 4));
+        assert_eq!(error("lda #1"), (1, 1));
+        assert_eq!(error("\n\n  .byte 1, 300"), (3, 12));
+        assert_eq!(error(".word \"no\""), (1, 7));
+        assert_eq!(error(".byte \"open"), (1, 7));
+        assert_eq!(error("* $C000"), (1, 3));
+        assert_eq!(error("@XYZ"), (1, 2));
+        assert_eq!(error("  .fill 10"), (1, 3));
+        assert_eq!(error("@FFFF\nea ea"), (2, 1));
+
+        let message = read_script("a9 01\nzz", "test.txt").err().unwrap().to_string();
+        assert_eq!(message, "line 2, column 1: Unexpected character 'z'");
+    }
+
+    #[test]
+    fn test_symbol_files() {
+        let mut symbols = Symbols::default();
+        symbols.parse("al C:0606 .init\nal 0610 .loop\n").unwrap();
+        symbols.parse("; ACME\nscreen = $0200\t; ?\ncounter = $10\n").unwrap();
+        symbols.parse("version\tmajor=2,minor=0\n\
+            file\tid=0,name=\"main.s\",size=100\n\
+            sym\tid=0,name=\"PORTB\",addrsize=absolute,scope=0,def=1,val=0x6000,type=equ\n\
+            sym\tid=1,name=\"reset\",addrsize=absolute,scope=0,def=2,val=0x8000,seg=0,type=lab\n\
+            sym\tid=2,name=\"RESET_COPY\",addrsize=absolute,scope=0,def=3,val=0x8000,type=equ\n\
+            sym\tid=3,name=\"extern\",addrsize=absolute,scope=0,ref=4,type=imp\n").unwrap();
+
+        assert_eq!(symbols.name(0x0606), Some("init"));
+        assert_eq!(symbols.name(0x0200), Some("screen"));
+        assert_eq!(symbols.name(0x8000), Some("reset"));
+        assert_eq!(symbols.resolve("PORTB"), Some(0x6000));
+        assert_eq!(symbols.resolve("RESET_COPY"), Some(0x8000));
+        assert_eq!(symbols.resolve("$1234"), Some(0x1234));
+        assert_eq!(symbols.resolve("extern"), None);
+        assert!(symbols.parse("al C:zz .broken").unwrap_err().contains("line 1"));
+
+        // JSR $0606 -> JSR init, zero page and branch targets get names too
+        let mut cpu = CPU::new();
+        cpu.symbols = symbols;
+        cpu.load(&vec![0x20, 0x06, 0x06, 0xE6, 0x10, 0xD0, 0x09, 0x8D, 0x01, 0x02]);
+        assert_eq!(disassemble(&cpu, 0x0600).0, "JSR init");
+        assert_eq!(disassemble(&cpu, 0x0603).0, "INC counter");
+        assert_eq!(disassemble(&cpu, 0x0605).0, "BNE loop");
+        assert_eq!(disassemble(&cpu, 0x0607).0, "STA $0201");
+    }
+
+    #[test]
+    fn test_debugger_breakpoints() {
+        // LDX #$00, loop: INX, CPX #$03, BNE loop, BRK
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xA2, 0x00, 0xE8, 0xE0, 0x03, 0xD0, 0xFB, 0x00]);
+        cpu.symbols.add("loop", 0x0602);
+
+        let mut debugger = Debugger::new();
+        let mut output = Vec::new();
+        debugger.run(&mut cpu, "break loop\ncontinue\ncontinue\nregs\ndelete loop\ncontinue\nstep\nquit\n".as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("#1 break $0602 (loop)"));
+        assert!(output.contains("Breakpoint #1 at $0602 (loop)"));
+        assert!(output.contains("loop:\n> 0602  INX"));
+        assert!(output.contains("A=$00 X=$01 Y=$00"));
+        assert!(output.contains("Program finished, BRK at $0607"));
+        assert!(output.contains("The program has finished"));
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let script = "// Header\n\na9 0a 20 06 06  // Load and call\n00\n\n@0606\n.byte $A9, 1 // Data can be run too\n60\n";
+        let image = read_script(script, "script.txt").unwrap();
+
+        let line = image.source.lookup(0x0602).unwrap();
+        assert_eq!(line.line, 3);
+        assert_eq!(line.code, "a9 0a 20 06 06");
+        assert_eq!(line.comment, "Load and call");
+        assert_eq!(line.describe(), "script.txt:3  a9 0a 20 06 06  // Load and call");
+        assert_eq!(image.source.lookup(0x0605).unwrap().line, 4);
+        assert_eq!(image.source.lookup(0x0607).unwrap().comment, "Data can be run too");
+        assert_eq!(image.source.lookup(0x0608).unwrap().describe(), "script.txt:8  60");
+        assert!(image.source.lookup(0x0609).is_none());
+
+        // The debugger shows the line next to the instruction
+        let mut cpu = CPU::new();
+        image.load_into(&mut cpu);
+        let mut output = Vec::new();
+        Debugger::new().run(&mut cpu, "step 2\n".as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("> 0606  LDA #$01        ; script.txt:7  .byte $A9, 1  // Data can be run too"));
+    }
+
+    #[test]
+    fn test_profiler() {
+        // main: JSR outer, BRK / outer: LDX #$03, loop: JSR inner, DEX, BNE loop, RTS / inner: INY, RTS
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0x20, 0x04, 0x06, 0x00, 0xA2, 0x03, 0x20, 0x0F, 0x06, 0xCA, 0xD0, 0xFA, 0x60, 0x00, 0x00, 0xC8, 0x60]);
+        cpu.symbols.add("outer", 0x0604);
+        cpu.symbols.add("inner", 0x060F);
+        cpu.profiler = Some(Profiler::new());
+        cpu.execute();
+
+        let profiler = cpu.profiler.as_ref().unwrap();
+        let folded = profiler.folded(&cpu);
+        assert_eq!(folded, "$0600 13\n$0600;outer 38\n$0600;outer;inner 24\n");
+
+        // The loop is the hot spot, and outer's cycles include the three calls to inner
+        let report = profiler.report(&cpu);
+        assert!(report.contains("Profile: 19 instructions, 75 cycles"));
+        assert!(report.contains(" $0606             3          18  24.00%   JSR inner"));
+        assert!(report.contains(" outer                        1          62  82.67%          38  50.67%"));
+        assert!(report.contains(" inner                        3          24  32.00%          24  32.00%"));
+    }
+
+    #[test]
+    fn test_coverage() {
+        // LDA $0700, STA $0701, INC $0702, BRK, with the random device writing into 0xFE the whole time
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xAD, 0x00, 0x07, 0x8D, 0x01, 0x07, 0xEE, 0x02, 0x07, 0x00]);
+        cpu.random = Some(RandomDevice::new(1, false));
+        cpu.coverage = Some(Coverage::new());
+        cpu.symbols.add("start", 0x0600);
+        cpu.execute();
+
+        let map = &cpu.coverage.as_ref().unwrap().map;
+        assert_eq!(map[0x0600], EXECUTED);
+        assert_eq!(map[0x0601], OPERAND);
+        assert_eq!(map[0x0609], EXECUTED);
+        assert_eq!(map[0x0700], READ);
+        assert_eq!(map[0x0701], WRITTEN);
+        assert_eq!(map[0x0702], READ | WRITTEN);
+        assert_eq!(map[0x060A], 0);
+        assert_eq!(map[RNG_ADDRESS as usize], 0);
+
+        let listing = cpu.coverage.as_ref().unwrap().listing(&cpu);
+        assert!(listing.starts_with("; 4 opcodes, 6 operand bytes, 2 bytes read, 2 bytes written\n"));
+        assert!(listing.contains("start:\n0600  AD 00 07  LDA $0700"));
+        assert!(listing.contains("0700  00        .byte $00                                ; read\n"));
+        assert!(listing.contains("0702  01        .byte $01                                ; read, written\n"));
+    }
+
+    #[test]
+    fn test_gdb_stub() {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+
+        // LDX #$00, loop: INX, STX $0200, CPX #$03, BNE loop, BRK
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xA2, 0x00, 0xE8, 0x8E, 0x00, 0x02, 0xE0, 0x03, 0xD0, 0xF8, 0x00]);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // GDB's side: send a packet, check the ack, and give back the reply without its checksum
+        let gdb = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut request = |packet: &str| -> String {
+                let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+                stream.write_all(format!("${}#{:02x}", packet, checksum).as_bytes()).unwrap();
+
+                let mut reply = Vec::new();
+                let mut byte = [0u8];
+                while reply.len() < 3 || reply[reply.len() - 3] != b'#' {
+                    stream.read_exact(&mut byte).unwrap();
+                    reply.push(byte[0]);
+                }
+                let reply = String::from_utf8(reply).unwrap();
+                assert!(reply.starts_with("+$"));
+                reply[2..reply.len() - 3].to_string()
+            };
+
+            let replies = vec![
+                request("qSupported:swbreak+"),
+                request("?"),
+                request("g"),
+                request("Z0,602,1"),
+                request("c"),
+                request("s"),
+                request("p5"),
+                request("z0,602,1"),
+                request("Z2,200,1"),
+                request("c"),
+                request("m200,2"),
+                request("M200,2:aabb"),
+                request("m200,2"),
+                request("z2,200,1"),
+                request("Z4,200,1"),
+                request("c"),
+                request("z4,200,1"),
+                request("G01020304050607"),
+                request("g"),
+                request("P0=0a"),
+                request("c"),
+                request("Z2,ff00,10000"),
+                request("z2,ff00,10000"),
+                request("Z2,ff00,ffffffffffffffff"),
+                request("z2,ff00,ffffffffffffffff"),
+                request("Z2,fff0,20"),
+                request("D"),
+            ];
+            replies
+        });
+
+        GdbStub::new().serve(&mut cpu, listener).unwrap();
+        let replies = gdb.join().unwrap();
+
+        assert_eq!(replies[0], "PacketSize=2010");
+        assert_eq!(replies[1], "S05");
+        assert_eq!(replies[2], "000000ff000006");
+        assert_eq!(replies[4], "S05");                  // Stopped at the breakpoint on INX
+        assert_eq!(replies[6], "0306");                 // Stepped past it to STX $0200
+        assert_eq!(replies[9], "T05watch:0200;");        // STX wrote to the watched byte
+        assert_eq!(replies[10], "0100");
+        assert_eq!(replies[12], "aabb");
+        assert_eq!(replies[15], "T05awatch:0200;");       // A watchpoint on reads and writes, hit by a write
+        assert_eq!(replies[18], "01020304050607");
+        assert_eq!(replies[20], "W00");                 // Ran into the BRK
+        assert_eq!(replies[21], "OK");                  // A watchpoint longer than what's left of memory wraps around
+        assert_eq!(replies[22], "OK");
+        assert_eq!(replies[23], "OK");                  // Even one as long as a usize can be
+        assert_eq!(replies[24], "OK");
+        assert_eq!(replies[25], "OK");
+        assert_eq!(replies[26], "OK");
+        let ranges: Vec<(u16, u16)> = cpu.watches.points.iter().map(|point| (point.start, point.end)).collect();
+        assert_eq!(ranges, vec![(0xFFF0, 0xFFFF), (0x0000, 0x000F)]); // Only the last one is still there
+        assert_eq!(cpu.register_a, 0x0A);
+    }
+
+    #[test]
+    fn test_expressions() {
+        let mut cpu = CPU::new();
+        cpu.register_a = 0x10;
+        cpu.register_x = 0x03;
+        cpu.status_flags = 0b0000_0011;
+        cpu.program_counter = 0x0612;
+        cpu.write_memory_u8(0x02, 0x04);
+        cpu.write_memory_u8(0x0203, 0x07);
+        cpu.symbols.add("counter", 0x0203);
+
+        let evaluate = |text: &str| Expression::parse(text, &cpu.symbols).unwrap().evaluate(&cpu);
+        assert_eq!(evaluate("A == $10 && [$02] & 4"), 1);
+        assert_eq!(evaluate("A == $10 && [$02] & 8"), 0);
+        assert_eq!(evaluate("[$02] & 4 == 4"), 1);
+        assert_eq!(evaluate("[counter] == 7 || A"), 1);
+        assert_eq!(evaluate("[$0200 + X]"), 7);
+        assert_eq!(evaluate("PC - 2 >= $0610"), 1);
+        assert_eq!(evaluate("C && Z && !N"), 1);
+        assert_eq!(evaluate("%1010 | 1 ^ 2"), 9);
+
+        assert!(Expression::parse("A ==", &cpu.symbols).is_err());
+        assert!(Expression::parse("(A", &cpu.symbols).is_err());
+        assert!(Expression::parse("[$02", &cpu.symbols).is_err());
+        assert!(Expression::parse("nowhere == 1", &cpu.symbols).is_err());
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // LDX #$00, loop: INX, STX $0200, CPX #$03, BNE loop, BRK
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xA2, 0x00, 0xE8, 0x8E, 0x00, 0x02, 0xE0, 0x03, 0xD0, 0xF8, 0x00]);
+        cpu.symbols.add("loop", 0x0602);
+
+        let mut debugger = Debugger::new();
+        let mut output = Vec::new();
+        let commands = "break loop after 1\nwatch $0200 if X == 2\ncontinue\ncontinue\nregs\nbreak\ndelete #1\nrwatch $01FF-$0300\ncontinue\n";
+        debugger.run(&mut cpu, commands.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        // The first time past loop is let go, and the watchpoint only stops once X is 2
+        assert!(output.contains("Breakpoint #1 at $0602 (loop)\nloop:\n> 0602  INX"));
+        assert!(output.contains("Watchpoint #2: write of $02 to $0200 by the instruction at $0603\n> 0606  CPX #$03"));
+        assert!(output.contains("A=$00 X=$02 Y=$00"));
+        assert!(output.contains("#1 break $0602 (loop) after 1, hit 2 times\n#2 watch $0200 if X == 2, hit 1 time"));
+        assert!(output.contains("#3 rwatch $01FF-$0300"));
+        assert!(output.contains("Program finished"));
+        assert_eq!(debugger.breakpoints.len(), 2);
+
+        // Nothing reads the range, but a write from outside an instruction is a device's doing
+        assert!(cpu.watches.take().is_empty());
+        cpu.write_memory_u8(0x0200, 0x55);
+        let hits = cpu.watches.take();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].access, watch::WRITE);
+        assert_eq!(hits[0].source, Source::Device);
+
+        assert!(debugger.add(&mut cpu, watch::EXECUTE, "$0300-$0200").is_err());
+        assert!(debugger.add(&mut cpu, watch::WRITE, "$0200 if A ==").is_err());
+        assert!(debugger.add(&mut cpu, watch::WRITE, "$0200 after lots").is_err());
+
+        // INC writes the old value back before the new one, but that's one hit going forwards and one going back
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xEE, 0x00, 0x02, 0xEE, 0x00, 0x02, 0xEE, 0x00, 0x02, 0x00]);
+        cpu.history = Some(History::new(100));
+
+        let mut output = Vec::new();
+        Debugger::new().run(&mut cpu, "watch $0200 after 1
+continue
+break
+reverse
+".as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("Watchpoint #1: write of $02 to $0200 by the instruction at $0603
+> 0606  INC $0200"));
+        assert!(output.contains("#1 watch $0200 after 1, hit 2 times"));
+        assert_eq!(output.matches("this instruction writes to $0200 (it held $01 before)").count(), 1);
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        // LDX #$00, loop: INX, STX $0200, CPX #$03, BNE loop, BRK
+        let program = vec![0xA2, 0x00, 0xE8, 0x8E, 0x00, 0x02, 0xE0, 0x03, 0xD0, 0xF8, 0x00];
+        let mut cpu = CPU::new();
+        cpu.load(&program);
+        cpu.history = Some(History::new(100));
+
+        let start = cpu.registers();
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(cpu.peek_memory_u8(0x0200), 1);
+
+        let delta = cpu.step_back().unwrap(); // BNE, which didn't write anything
+        assert!(delta.writes.is_empty());
+        for _ in 0..4 {
+            cpu.step_back().unwrap();
+        }
+        assert_eq!(cpu.registers(), start);
+        assert_eq!(cpu.peek_memory_u8(0x0200), 0);
+        assert!(cpu.step_back().is_none());
+
+        // Only the newest instructions are kept
+        cpu.history = Some(History::new(2));
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert!(cpu.step_back().is_some() && cpu.step_back().is_some());
+        assert!(cpu.step_back().is_none());
+        assert_eq!(cpu.program_counter, 0x0602);
+
+        // The debugger goes back to watchpoints and breakpoints, and can go forwards again afterwards
+        let mut cpu = CPU::new();
+        cpu.load(&program);
+        cpu.symbols.add("loop", 0x0602);
+        cpu.history = Some(History::new(100));
+
+        let mut debugger = Debugger::new();
+        let mut output = Vec::new();
+        let commands = "continue\nback\nregs\nwatch $0200\nreverse\nregs\nbreak loop\nreverse\nreverse\ndelete\nrc\ncontinue\n";
+        debugger.run(&mut cpu, commands.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("A=$00 X=$03 Y=$00 SP=$FF P=%00000011 PC=$060A"));
+        assert!(output.contains("Watchpoint #1: this instruction writes to $0200 (it held $02 before)\n> 0603  STX $0200"));
+        assert!(output.contains("Breakpoint #2 at $0602 (loop)"));
+        assert!(output.contains("Watchpoint #1: this instruction writes to $0200 (it held $01 before)"));
+        assert!(output.contains("Reached the start of the history\n> 0600  LDX #$00"));
+        assert_eq!(output.matches("Program finished, BRK at $060A").count(), 2);
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_call_stack() {
+        // main: JSR outer, BRK / outer: JSR inner, RTS / inner: INY, RTS
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0x20, 0x04, 0x06, 0x00, 0x20, 0x08, 0x06, 0x60, 0xC8, 0x60]);
+        cpu.symbols.add("outer", 0x0604);
+        cpu.symbols.add("inner", 0x0608);
+        cpu.calls.check = StackCheck::Error;
+        cpu.history = Some(History::new(100));
+
+        // Stepping back puts the call stack back too
+        let mut output = Vec::new();
+        Debugger::new().run(&mut cpu, "step 3\nbacktrace\nback 2\nbt\ncontinue\n".as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("#0  $0609 in inner\n#1  $0604 in outer, JSR inner\n#2  $0600, JSR outer\n"));
+        assert!(output.contains("#0  $0604 in outer\n#1  $0600, JSR outer\n(astrobyte)"));
+        assert!(output.contains("Program finished, BRK at $0603"));
+        assert!(cpu.calls.frames.is_empty());
+        assert!(cpu.calls.error.is_none());
+
+        let run = |program: Vec<u8>, check: StackCheck| {
+            let mut cpu = CPU::new();
+            cpu.load(&program);
+            cpu.stack_pointer = 0xFD;
+            cpu.calls.check = check;
+            while cpu.step() {}
+            cpu.calls.error
+        };
+
+        // LDA #$06, PHA, LDA #$04, PHA, RTS: a jump through the stack, with no JSR to return from
+        let jump = vec![0xA9, 0x06, 0x48, 0xA9, 0x04, 0x48, 0x60];
+        assert_eq!(run(jump.clone(), StackCheck::Error).unwrap(), "Stack error at $0606: RTS without a matching JSR");
+        assert!(run(jump, StackCheck::Off).is_none());
+
+        // JSR sub, BRK / sub: PLA, PLA, LDA #$06, PHA, LDA #$09, PHA, RTS
+        let swap = vec![0x20, 0x04, 0x06, 0x00, 0x68, 0x68, 0xA9, 0x06, 0x48, 0xA9, 0x09, 0x48, 0x60];
+        assert_eq!(run(swap, StackCheck::Error).unwrap(),
+            "Stack error at $060C: return address changed, the call at $0600 pushed $0602 but $0609 was pulled");
+
+        // LDX #$00, TXS, PHA
+        assert_eq!(run(vec![0xA2, 0x00, 0x9A, 0x48], StackCheck::Error).unwrap(),
+            "Stack error at $0603: stack overflow, pushed past $0100 and wrapped around to $01FF");
+
+        // Snake only ever returns to where it was called from, all the way until it runs into the wall
+        let mut cpu = CPU::new();
+        cpu.load(&SNAKE_OPCODES.to_vec());
+        cpu.random = Some(RandomDevice::new(7, false));
+        cpu.calls.check = StackCheck::Error;
+        while cpu.step() {}
+        assert!(cpu.calls.error.is_none());
+    }
+
+    #[test]
+    fn test_memory_dump() {
+        let mut cpu = CPU::new();
+        cpu.load_at(0x0208, b"Hello\x00\x01 ");
+
+        // Rows start on multiples of 16, with blanks outside the range
+        let text = dump(&Snapshot::take(&cpu, 0x0204, 0x0213));
+        assert_eq!(text, "0200              00 00 00 00  48 65 6C 6C 6F 00 01 20  |    ....Hello.. |\n\
+                          0210  00 00 00 00                                       |....            |\n");
+
+        let before = Snapshot::take(&cpu, 0x0200, 0x022F);
+        cpu.write_memory_u8(0x0209, b'a');
+        cpu.write_memory_u8(0x0220, 0xFF);
+        let after = Snapshot::take(&cpu, 0x0200, 0x022F);
+
+        let text = diff(&before, &after, false);
+        assert_eq!(text, "0200  00 00 00 00 00 00 00 00  48 65 6C 6C 6F 00 01 20  |........Hello.. |\n\
+                          0200  00 00 00 00 00 00 00 00  48 61 6C 6C 6F 00 01 20  |........Hallo.. |\n\
+                          \x20                                 ^^                              ^\n\n\
+                          0220  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+                          0220  FF 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+                          \x20     ^^                                                 ^\n\n\
+                          2 bytes changed between $0200 and $022F\n");
+        assert!(diff(&before, &after, true).contains("48 \x1b[1;31m61\x1b[0m 6C"));
+        assert_eq!(diff(&before, &before, false), "0 bytes changed between $0200 and $022F\n");
+
+        // The whole of memory works too
+        let all = Snapshot::take(&cpu, 0x0000, 0xFFFF);
+        assert_eq!(all.end(), 0xFFFF);
+
+        // And from the debugger
+        let mut output = Vec::new();
+        let commands = "dump $0208-$020F\nsnapshot\ndiff\nregs\n";
+        cpu.load(&vec![0xA9, 0x01, 0x8D, 0x08, 0x02, 0x00]); // LDA #$01, STA $0208, BRK
+        Debugger::new().run(&mut cpu, format!("{}step 2\ndiff $0200-$02FF\n", commands).as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("0200                           48 61 6C 6C 6F 00 01 20  |        Hallo.. |"));
+        assert!(output.contains("0 bytes changed between $0000 and $FFFF"));
+        assert!(output.contains("0200  00 00 00 00 00 00 00 00  01 61 6C 6C 6F 00 01 20  |.........allo.. |"));
+        assert!(output.contains("1 byte changed between $0200 and $02FF"));
+    }
+
+    #[test]
+    fn test_expect_annotations() {
+        let script = "a9 0a aa //! expect A=$0A after 1\n8d 00 02 00 //! expect A=$0A X=$0A [$0200]=$0A Z=0 after BRK\n";
+        let expectations = expect::parse(script, &Symbols::default()).unwrap();
+        assert_eq!(expectations.len(), 2);
+        assert_eq!((expectations[0].line, expectations[0].after), (1, After::Instructions(1)));
+        assert_eq!((expectations[1].after, expectations[1].checks.len()), (After::Brk, 4));
+
+        let mut cpu = CPU::new();
+        read_script(script, "test.txt").unwrap().load_into(&mut cpu);
+        assert!(expect::run(&mut cpu, &expectations).is_empty());
+
+        // Failures say what was there instead
+        let script = "a9 0b 00 //! expect A=$0A X=0\n//! expect A=$0B after 5\n";
+        let expectations = expect::parse(script, &Symbols::default()).unwrap();
+        let mut cpu = CPU::new();
+        read_script(script, "test.txt").unwrap().load_into(&mut cpu);
+        assert_eq!(expect::run(&mut cpu, &expectations), vec![
+            String::from("line 2: the program finished after 2 instructions, before getting to 5"),
+            String::from("line 1: A is $0B, expected $0A"),
+        ]);
+
+        // A program that stops on an invalid opcode or a stack error instead of a BRK fails, whatever it left behind
+        let script = "a9 00 02 //! expect A=$00 after BRK\n//! expect A=$00 after 2";
+        let expectations = expect::parse(script, &Symbols::default()).unwrap();
+        let mut cpu = CPU::new();
+        read_script(script, "test.txt").unwrap().load_into(&mut cpu);
+        assert_eq!(expect::run(&mut cpu, &expectations), vec![
+            String::from("line 2: the program crashed: $02 at $0602 is an invalid opcode"),
+            String::from("line 1: the program crashed: $02 at $0602 is an invalid opcode"),
+        ]);
+
+        let script = "a2 00 9a 48 00 //! expect X=0";
+        let expectations = expect::parse(script, &Symbols::default()).unwrap();
+        let mut cpu = CPU::new();
+        cpu.calls.check = StackCheck::Error;
+        read_script(script, "test.txt").unwrap().load_into(&mut cpu);
+        assert_eq!(expect::run(&mut cpu, &expectations),
+            vec![String::from("line 1: the program crashed: Stack error at $0603: stack overflow, pushed past $0100 and wrapped around to $01FF")]);
+
+        // Mistakes in the annotations themselves
+        for (script, message) in [
+            ("//! check A=1", "line 1: Unknown //! directive 'check'"),
+            ("ea\n//! expect A", "line 2: Checks look like NAME=VALUE, not 'A'"),
+            ("//! expect A=1 after soon", "line 1: after should be BRK or a number of instructions, not 'soon'"),
+            ("//! expect after BRK", "line 1: expect needs at least one check, like A=$0A"),
+        ] {
+            assert_eq!(expect::parse(script, &Symbols::default()).err().unwrap(), message);
+        }
+
+        // A directory of scripts, one of which fails
+        let directory = std::env::temp_dir().join("astrobyte_test_expect");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("pass.txt"), "a2 05 00 //! expect X=5").unwrap();
+        std::fs::write(directory.join("plain.txt"), "a2 05 00").unwrap();
+        assert!(expect::run_tests(&directory, 0).unwrap());
+        std::fs::write(directory.join("fail.txt"), "a2 05 00 //! expect X=6").unwrap();
+        assert!(!expect::run_tests(&directory, 0).unwrap());
+        assert_eq!(expect::test_script(&directory.join("fail.txt"), 0).unwrap(), vec![String::from("line 1: X is $05, expected $06")]);
+        assert_eq!(expect::test_script(&directory.join("plain.txt"), 0), Err(expect::TestError::NoExpectations));
+        std::fs::write(directory.join("crash.txt"), "a9 00 02 //! expect A=$00 after BRK").unwrap();
+        assert_eq!(expect::test_script(&directory.join("crash.txt"), 0).unwrap().len(), 1);
+        std::fs::remove_file(directory.join("fail.txt")).unwrap();
+        assert!(!expect::run_tests(&directory, 0).unwrap());
+        std::fs::remove_file(directory.join("crash.txt")).unwrap();
+
+        let options = Options::parse(["test", "scripts"].map(String::from)).unwrap();
+        assert_eq!(options.test, Some(std::path::PathBuf::from("scripts")));
+        assert!(options.program.is_none());
+
+        // $FE is left alone unless the script asks for the random byte device
+        std::fs::write(directory.join("fail.txt"), "a9 2a 85 fe ea 00 //! expect [$FE]=$2A").unwrap();
+        assert!(expect::test_script(&directory.join("fail.txt"), 0).unwrap().is_empty());
+        std::fs::write(directory.join("fail.txt"), "//! random 7\na9 2a 85 fe ea 00 //! expect [$FE]=$2A").unwrap();
+        assert_eq!(expect::test_script(&directory.join("fail.txt"), 0).unwrap().len(), 1);
+        assert_eq!(expect::random_seed("//! random", 3), Some(3));
+        assert_eq!(expect::random_seed("ea //! random $10", 3), Some(16));
+        assert_eq!(expect::random_seed("ea", 3), None);
+        assert_eq!(expect::parse("//! random soon", &Symbols::default()).err().unwrap(), "line 1: random takes a seed, not 'soon'");
+
+        // And the same goes for programs run from the command line
+        assert!(Options::parse(["script.txt"].map(String::from)).unwrap().program_random_device().is_none());
+        assert!(Options::parse(["script.txt", "--random"].map(String::from)).unwrap().program_random_device().is_some());
+        assert_eq!(Options::parse(["script.txt", "--seed", "5"].map(String::from)).unwrap().program_random_device().map(|random| random.seed), Some(5));
+    }
+
+    #[test]
+    fn test_reference_model() {
+        use crate::reference;
+
+        // The reference decodes the same opcodes as the emulator's table, at the same lengths except for the known CMP one
+        for opcode in 0..=255u8 {
+            let table = OPCODES_TABLE.get(&opcode).map(|info| info.byte_length as u16);
+            let reference = reference::decode(opcode).map(|(_, mode)| mode.length());
+            if opcode != 0xCD {
+                assert_eq!(table, reference, "opcode {:02X}", opcode);
+            }
+        }
+
+        // LDA #$80, SBC #$01, JSR $0700 ... $0700: RTS
+        let mut memory = vec![0; 0x10000];
+        memory[0x0600..0x0607].copy_from_slice(&[0xA9, 0x80, 0xE9, 0x01, 0x20, 0x00, 0x07]);
+        memory[0x0700] = 0x60;
+        let mut machine = reference::Machine::new(memory);
+        machine.pc = 0x0600;
+        machine.p = reference::C;
+        while machine.step() {}
+        assert_eq!((machine.a, machine.p & (reference::V | reference::C), machine.pc), (0x7F, reference::V | reference::C, 0x0607));
+        assert_eq!(&machine.memory[0x01FE..0x0200], &[0x06, 0x06]); // JSR pushed $0606, its last byte
+    }
+
+    #[test]
+    fn test_differential() {
+        use crate::differential::{self, Case, Divergence};
+
+        // Random programs from every opcode behave the same on both
+        let opcodes = differential::tested_opcodes();
+        assert!(opcodes.len() > 100);
+        if let Err(reproducer) = differential::run(0x6502, 2000, &opcodes) {
+            panic!("The emulator and the reference disagree:\n{}", reproducer);
+        }
+
+        // A failure is cut down to the one instruction that shows it. There are none left to find, so here it's any SBC
+        let case = Case { a: 0x12, x: 0x34, y: 0x56, p: 0x01, s: 0xFD, start: 0xFFF0, memory: vec![(0x0010, 0x42), (0x0200, 0x07)],
+            program: vec![vec![0xA9, 0x10], vec![0xE9, 0x05], vec![0xAA]] };
+        let case = differential::minimize(&case, |case| case.program.iter().any(|instruction| instruction[0] == 0xE9));
+        let divergence = Divergence { step: 0, address: 0x0600, message: String::from("A is $00 here and $FF in the reference") };
+        assert_eq!(differential::reproducer(&case, &divergence), "// Start with A=$00 X=$00 Y=$00 P=$00 SP=$00\n\
+                                                                 * = $0600\n\
+                                                                 e9 00     // $0600 SBC #$00\n\
+                                                                 // Instruction 1, at $0600: A is $00 here and $FF in the reference");
+
+        // Every case can be checked on its own, and a clean one passes
+        let case = Case { a: 1, x: 2, y: 3, p: 0, s: 0xFF, start: 0x0600, memory: vec![(0x0010, 0x42)], program: vec![vec![0xA5, 0x10], vec![0x95, 0x20]] };
+        assert_eq!(differential::check(&case, &opcodes), Ok(()));
+    }
+
+    #[test]
+    fn test_fuzz_inputs() {
+        // Inputs that used to panic (or could), none of them should now
+        for text in ["* = $FFFF\nea ea", ".word 70000", ".byte '", "@", "* =", ".byte \"é\"", "é9 01", "a9 01 //! expect A=", "//! expect [[", "\u{0}\u{ff}"] {
+            fuzzing::script(text.as_bytes());
+        }
+        fuzzing::script(&[0xFF, 0xFE, 0x80]);
+
+        // A program bigger than the space after $0600 wraps around, and so does a program counter at $FFFF
+        fuzzing::cpu(&[&[1u8][..], &vec![0xE8; 0x10000]].concat());
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xEA; 0xFB00]);
+        assert_eq!((cpu.peek_memory_u8(0x00FF), cpu.peek_memory_u8(0x0100)), (0xEA, 0x00));
+
+        let mut cpu = CPU::new();
+        cpu.load_at(0xFFFF, &[0xE8]); // INX, then the BRK at $0000
+        cpu.program_counter = 0xFFFF;
+        assert!(cpu.step());
+        assert_eq!((cpu.register_x, cpu.program_counter), (1, 0x0000));
+        assert!(!cpu.step());
+
+        // Jumps and branches to the very bottom of memory, and an opcode that doesn't exist
+        for program in [&[0x4C, 0x00, 0x00][..], &[0x20, 0x01, 0x00], &[0x6C, 0x00, 0x07], &[0xA9, 0x00, 0xF0, 0xF8], &[0x02]] {
+            let mut cpu = CPU::new();
+            cpu.load(&program.to_vec());
+            cpu.load_at(0x0000, &[0xE8, 0x00]); // INX, BRK
+            cpu.execute();
+        }
+        fuzzing::cpu(&[0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0x02]);
+    }
+
+    #[test]
+    fn test_top_of_memory() {
+        // An operand that runs past $FFFF is finished at $0000: LDA $1234 with the $12 at $0000
+        let mut cpu = CPU::new();
+        cpu.load_at(0xFFFE, &[0xAD, 0x34]);
+        cpu.load_at(0x0000, &[0x12]);
+        cpu.write_memory_u8(0x1234, 0x42);
+        cpu.program_counter = 0xFFFE;
+        assert!(cpu.step());
+        assert_eq!((cpu.register_a, cpu.program_counter), (0x42, 0x0001));
+        assert_eq!(cpu.read_memory_u16(0xFFFF), 0x1234);
+
+        // Branches go forwards past $FFFF and backwards past $0000
+        let mut cpu = CPU::new();
+        cpu.load_at(0xFFF0, &[0xD0, 0x20]); // BNE +$20
+        cpu.load_at(0x0012, &[0xD0, 0xE8]); // BNE -$18
+        cpu.program_counter = 0xFFF0;
+        assert!(cpu.step());
+        assert_eq!(cpu.program_counter, 0x0012);
+        assert!(cpu.step());
+        assert_eq!(cpu.program_counter, 0xFFFC);
+
+        // Pointers at the end of the zero page take their msb from $00, not $0100
+        for (program, expected) in [([0xB1, 0xFF], 0x77), ([0xA1, 0xFE], 0x66)] { // LDA ($FF),Y and LDA ($FE,X)
+            let mut cpu = CPU::new();
+            cpu.load(&program.to_vec());
+            cpu.load_at(0x00FF, &[0x00, 0x03]);
+            cpu.load_at(0x0000, &[0x02]);
+            cpu.load_at(0x0200, &[0x66, 0, 0, 0, 0, 0x77]);
+            (cpu.register_x, cpu.register_y) = (1, 5);
+            assert!(cpu.step());
+            assert_eq!(cpu.register_a, expected, "{:02X?}", program);
+        }
+
+        // Indexing past $FFFF and past $FF
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xB9, 0xFF, 0xFF, 0xB5, 0xF0]); // LDA $FFFF,Y, LDA $F0,X
+        cpu.load_at(0x0000, &[0x00, 0x11, 0x00, 0x22]);
+        (cpu.register_x, cpu.register_y) = (0x13, 2);
+        assert!(cpu.step());
+        assert_eq!(cpu.register_a, 0x11);
+        assert!(cpu.step());
+        assert_eq!(cpu.register_a, 0x22);
+
+        // A push with the stack pointer at $00 writes $0100 and wraps to $FF, and a pull wraps back
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0x48, 0xA9, 0x00, 0x68]); // PHA, LDA #$00, PLA
+        (cpu.register_a, cpu.stack_pointer) = (0x5A, 0x00);
+        assert!(cpu.step());
+        assert_eq!((cpu.peek_memory_u8(0x0100), cpu.stack_pointer), (0x5A, 0xFF));
+        assert!(cpu.step());
+        assert!(cpu.step());
+        assert_eq!((cpu.register_a, cpu.stack_pointer), (0x5A, 0x00));
+
+        // JMP ($02FF) takes its msb from $0200, like the real thing
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0x6C, 0xFF, 0x02]);
+        cpu.load_at(0x02FF, &[0x34, 0x56]);
+        cpu.load_at(0x0200, &[0x12]);
+        assert!(cpu.step());
+        assert_eq!(cpu.program_counter, 0x1234);
+    }
+
+    #[test]
+    fn test_dummy_accesses() {
+        const R: u8 = watch::READ;
+        const W: u8 = watch::WRITE;
+
+        // Every read and write one instruction makes (the opcode and operand fetches aren't counted), in order
+        let accesses = |program: &[u8], x: u8, y: u8, dummy_accesses: bool| -> Vec<(u16, u8, u8)> {
+            let mut cpu = CPU::new();
+            cpu.load(&program.to_vec());
+            cpu.load_at(0x0010, &[0xF0, 0x02]);
+            cpu.load_at(0x0200, &[0x05]);
+            (cpu.register_x, cpu.register_y, cpu.dummy_accesses) = (x, y, dummy_accesses);
+            cpu.watches.add(0x0000, 0xFFFF, R | W);
+            assert!(cpu.step());
+            cpu.watches.take().iter().map(|hit| (hit.address, hit.access, hit.value)).collect()
+        };
+
+        // Read-modify-writes write the old value back first
+        assert_eq!(accesses(&[0xEE, 0x00, 0x02], 0, 0, true), vec![(0x0200, R, 5), (0x0200, W, 5), (0x0200, W, 6)]); // INC $0200
+        assert_eq!(accesses(&[0x0E, 0x00, 0x02], 0, 0, true), vec![(0x0200, R, 5), (0x0200, W, 5), (0x0200, W, 10)]); // ASL $0200
+        assert_eq!(accesses(&[0xCF, 0x00, 0x02], 0, 0, true), vec![(0x0200, R, 5), (0x0200, W, 5), (0x0200, W, 4)]); // DCP $0200
+
+        // Indexed reads only read the un-carried address when the page was crossed, stores and read-modify-writes always do
+        assert_eq!(accesses(&[0xBD, 0xF0, 0x01], 0x10, 0, true), vec![(0x0100, R, 0), (0x0200, R, 5)]); // LDA $01F0,X
+        assert_eq!(accesses(&[0xBD, 0xF0, 0x01], 0x01, 0, true), vec![(0x01F1, R, 0)]);
+        assert_eq!(accesses(&[0x9D, 0xFF, 0x01], 0x01, 0, true), vec![(0x0100, R, 0), (0x0200, W, 0)]); // STA $01FF,X
+        assert_eq!(accesses(&[0x9D, 0x00, 0x02], 0x00, 0, true), vec![(0x0200, R, 5), (0x0200, W, 0)]); // STA $0200,X
+        assert_eq!(accesses(&[0xFE, 0xF0, 0x01], 0x10, 0, true),
+            vec![(0x0100, R, 0), (0x0200, R, 5), (0x0200, W, 5), (0x0200, W, 6)]); // INC $01F0,X
+        assert_eq!(accesses(&[0xB1, 0x10], 0, 0x05, true), vec![(0x0010, R, 0xF0), (0x0011, R, 0x02), (0x02F5, R, 0)]); // LDA ($10),Y
+        assert_eq!(accesses(&[0xB1, 0x10], 0, 0x10, true),
+            vec![(0x0010, R, 0xF0), (0x0011, R, 0x02), (0x0200, R, 5), (0x0300, R, 0)]);
+
+        // Zero page indexing reads the address before the index is added, (zp,X) reads the pointer before x is added
+        assert_eq!(accesses(&[0xB5, 0x0F], 0x01, 0, true), vec![(0x000F, R, 0), (0x0010, R, 0xF0)]); // LDA $0F,X
+        assert_eq!(accesses(&[0xA1, 0x0E], 0x02, 0, true), vec![(0x000E, R, 0), (0x0010, R, 0xF0), (0x0011, R, 0x02), (0x02F0, R, 0)]); // LDA ($0E,X)
+
+        // The extra ones are marked, so the debuggers don't stop on them
+        let mut cpu = CPU::new();
+        cpu.load(&vec![0xEE, 0x00, 0x02]);
+        cpu.watches.add(0x0200, 0x0200, R | W);
+        cpu.step();
+        assert_eq!(cpu.watches.take().iter().map(|hit| hit.dummy).collect::<Vec<_>>(), vec![false, true, false]);
+
+        // And none of them when they're turned off
+        assert_eq!(accesses(&[0xEE, 0x00, 0x02], 0, 0, false), vec![(0x0200, R, 5), (0x0200, W, 6)]);
+        assert_eq!(accesses(&[0xFE, 0xF0, 0x01], 0x10, 0, false), vec![(0x0200, R, 5), (0x0200, W, 6)]);
+        assert_eq!(accesses(&[0xB5, 0x0F], 0x01, 0, false), vec![(0x0010, R, 0xF0)]);
+
+        let options = Options::parse(["--no-dummy-accesses"].map(String::from)).unwrap();
+        assert!(options.no_dummy_accesses);
+
+        // Every front-end and machine sets its CPU up from the options the same way
+        let options = Options::parse(["--profile", "--coverage", "cov.cdl", "--stack-check", "error", "--no-dummy-accesses"].map(String::from)).unwrap();
+        let (mut cpu, _) = apple1(std::sync::mpsc::channel().1, Box::new(std::io::sink()));
+        options.configure(&mut cpu);
+        assert!(cpu.profiler.is_some() && cpu.coverage.is_some());
+        assert_eq!((cpu.calls.check, cpu.dummy_accesses), (StackCheck::Error, false));
+    }
 }
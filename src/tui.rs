@@ -0,0 +1,242 @@
+use crate::capture::Capture;
+use crate::cli::{self, Options};
+use crate::disassembler::disassemble;
+use crate::loader::Image;
+use crate::movie::{Recording, KEY_ADDRESS};
+use crate::processor::CPU;
+use crate::rng::RandomDevice;
+use crate::dump;
+use crate::screen::{check_screen_state, W, H, FRAME_SIZE};
+use crate::symbols::Symbols;
+use crate::call_stack::StackCheck;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+const INPUT_INTERVAL: u64 = 100; // Checking the keyboard is slow, so only do it every 100 instructions
+const FRAME_TIME: Duration = Duration::from_millis(16); // Redraw at most ~60 times a second
+const DISASSEMBLY_LINES: u16 = 10;
+
+/*
+    The terminal front-end, for machines without a display (or over SSH). Works just like the SDL window:
+    the screen at 0x0200 - 0x05FF is drawn with half-block characters in 24-bit color, two pixels per character
+    (top and bottom), and every key pressed is written into 0xFF. Arrow keys are turned into w/a/s/d for snake.
+
+    Next to the screen are the registers and the disassembly of the instructions coming up.
+    Esc (or Ctrl+C) quits, F12 saves a screenshot. --record, --capture and --screenshot work here too.
+    Snake always has the random byte device, other programs only if they asked for it. A run without it can't be
+    recorded, since a movie is replayed from its seed
+*/
+pub fn run_tui(image: &Image, random: Option<RandomDevice>, options: &Options) -> Result<(), String> {
+    let mut capture = Capture::new(options)?;
+    let mut recording = options.record.as_ref().zip(random.as_ref()).map(|(path, random)| Recording::new(path, random));
+    let seed = random.as_ref().map(|random| random.seed);
+
+    let mut cpu = CPU::new();
+    image.load_into(&mut cpu);
+    let before = dump::snapshots(&cpu, &options.diffs); // For --diff
+    cpu.random = random;
+    cpu.symbols = Symbols::from_files(&options.symbols)?;
+    options.configure(&mut cpu);
+
+    // Warnings would scribble over the screen, so only errors are checked for (and shown once the terminal is back)
+    if options.stack_check == StackCheck::Warn {
+        cpu.calls.check = StackCheck::Off;
+    }
+
+    let mut screen_state = [0u8; FRAME_SIZE];
+    let mut frame: u64 = 0;
+
+    {
+        let _terminal = RawTerminal::enter().map_err(|e| format!("Failed to set up the terminal: {}", e))?;
+        let mut out = BufWriter::new(io::stdout());
+        let mut last_draw = Instant::now() - FRAME_TIME;
+        let mut screen_changed = true;
+        let mut finished = false;
+
+        loop {
+            if !cpu.step() {
+                finished = true;
+                break;
+            }
+
+            frame += 1;
+
+            if frame.is_multiple_of(INPUT_INTERVAL) {
+                match read_input().map_err(|e| e.to_string())? {
+                    UserInput::Key(key) => {
+                        cpu.write_memory_u8(KEY_ADDRESS, key);
+                        if let Some(recording) = recording.as_mut() {
+                            recording.record_input(frame, key);
+                        }
+                    }
+
+                    UserInput::Screenshot => capture.take_screenshot(&screen_state),
+                    UserInput::Quit => break,
+                    UserInput::Nothing => {}
+                }
+            }
+
+            if check_screen_state(&cpu, &mut screen_state) {
+                capture.frame_changed(frame, &screen_state);
+                screen_changed = true;
+            }
+
+            // The registers change all the time, so redraw on a timer rather than when the screen changes
+            if last_draw.elapsed() >= FRAME_TIME {
+                draw(&mut out, &cpu, &screen_state, screen_changed, seed).map_err(|e| e.to_string())?;
+                last_draw = Instant::now();
+                screen_changed = false;
+            }
+
+            ::std::thread::sleep(std::time::Duration::new(0, 200_000)); // Same speed as the SDL window
+        }
+
+        // Leave the final state up until a key is pressed
+        if finished {
+            draw(&mut out, &cpu, &screen_state, true, seed).map_err(|e| e.to_string())?;
+            queue!(out, cursor::MoveTo(0, H as u16 / 2 + 1), Print("Program finished, press any key")).map_err(|e| e.to_string())?;
+            out.flush().map_err(|e| e.to_string())?;
+            wait_for_key().map_err(|e| e.to_string())?;
+        }
+    }
+
+    // The terminal is back to normal here, so the messages from these show up
+    if let Some(error) = cpu.calls.error.take() {
+        eprintln!("{}", error);
+    }
+    if let Some(recording) = recording.as_mut() {
+        recording.finish(frame, &cpu);
+    }
+    capture.finish(&screen_state);
+    cli::finish(&cpu, options, &before);
+
+    Ok(())
+}
+
+// Raw mode and the alternate screen, both undone when this is dropped (even if something fails along the way)
+pub struct RawTerminal;
+
+impl RawTerminal {
+    pub fn enter() -> io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+enum UserInput {
+    Nothing,
+    Key(u8),
+    Screenshot,
+    Quit,
+}
+
+// Read every key waiting to be read without blocking, the last one pressed wins
+fn read_input() -> io::Result<UserInput> {
+    let mut input = UserInput::Nothing;
+
+    while event::poll(Duration::ZERO)? {
+        if let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? {
+            if kind == KeyEventKind::Release {
+                continue;
+            }
+
+            match code {
+                KeyCode::Esc => return Ok(UserInput::Quit),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(UserInput::Quit),
+                KeyCode::F(12) => return Ok(UserInput::Screenshot),
+
+                // Arrow keys work like w/a/s/d
+                KeyCode::Up => input = UserInput::Key(b'w'),
+                KeyCode::Down => input = UserInput::Key(b's'),
+                KeyCode::Left => input = UserInput::Key(b'a'),
+                KeyCode::Right => input = UserInput::Key(b'd'),
+
+                KeyCode::Enter => input = UserInput::Key(0x0D),
+                KeyCode::Backspace => input = UserInput::Key(0x08),
+                KeyCode::Char(ch) if ch.is_ascii() => input = UserInput::Key(ch as u8),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(input)
+}
+
+fn wait_for_key() -> io::Result<()> {
+    loop {
+        if let Event::Key(KeyEvent { kind: KeyEventKind::Press, .. }) = event::read()? {
+            return Ok(());
+        }
+    }
+}
+
+fn pixel(frame: &[u8; FRAME_SIZE], x: u32, y: u32) -> Color {
+    let idx = ((y * W + x) * 3) as usize;
+    Color::Rgb { r: frame[idx], g: frame[idx + 1], b: frame[idx + 2] }
+}
+
+// Status flags as letters, a dot for every flag that is clear
+fn flags_string(flags: u8) -> String {
+    "NV-BDIZC".chars().enumerate()
+        .map(|(i, letter)| if flags & (0b1000_0000 >> i) != 0 { letter } else { '.' })
+        .collect()
+}
+
+// The screen itself is only redrawn when it changed, the registers and disassembly every time
+fn draw<T: Write>(out: &mut T, cpu: &CPU, frame: &[u8; FRAME_SIZE], redraw_screen: bool, seed: Option<u64>) -> io::Result<()> {
+    // Each character is two pixels tall (the top half is the foreground color, the bottom half the background)
+    // and two characters wide, so the pixels come out square
+    if redraw_screen {
+        for row in 0..H / 2 {
+            queue!(out, cursor::MoveTo(0, row as u16))?;
+            for x in 0..W {
+                queue!(out, SetForegroundColor(pixel(frame, x, row * 2)), SetBackgroundColor(pixel(frame, x, row * 2 + 1)), Print("▀▀"))?;
+            }
+            queue!(out, ResetColor)?;
+        }
+    }
+
+    let mut lines = vec![
+        format!("A  ${:02X}   X  ${:02X}   Y  ${:02X}", cpu.register_a, cpu.register_x, cpu.register_y),
+        format!("SP ${:02X}   PC ${:04X}", cpu.stack_pointer, cpu.program_counter),
+        format!("Flags {}", flags_string(cpu.status_flags)),
+        seed.map(|seed| format!("Seed  {}", seed)).unwrap_or_default(),
+        cpu.source.lookup(cpu.program_counter).map(|line| line.describe()).unwrap_or_default(), // The script line being run, if it came from one
+        String::new(),
+    ];
+
+    // Labels get a line of their own, like in the assembly source
+    let mut address = cpu.program_counter;
+    let listing_end = lines.len() + DISASSEMBLY_LINES as usize;
+    while lines.len() < listing_end {
+        if let Some(name) = cpu.symbols.name(address) {
+            lines.push(format!("        {}:", name));
+        }
+
+        let (text, length) = disassemble(cpu, address);
+        let marker = if address == cpu.program_counter { ">" } else { " " };
+        lines.push(format!("{} {:04X}  {}", marker, address, text));
+        address = address.wrapping_add(length);
+    }
+
+    lines.push(String::new());
+    lines.push(String::from("Esc: quit   F12: screenshot"));
+
+    let pane = (W * 2 + 2) as u16;
+    for (i, line) in lines.iter().enumerate() {
+        queue!(out, cursor::MoveTo(pane, i as u16), terminal::Clear(terminal::ClearType::UntilNewLine), Print(line))?;
+    }
+
+    out.flush()
+}
@@ -0,0 +1,411 @@
+use crate::devices::Device;
+
+// Registers, as offsets from the address the VIA is attached at
+pub const ORB: u16 = 0x0; // Port B
+pub const ORA: u16 = 0x1; // Port A
+pub const DDRB: u16 = 0x2; // Data direction of port B, a 1 bit makes that pin an output
+pub const DDRA: u16 = 0x3;
+pub const T1C_L: u16 = 0x4; // Timer 1 counter
+pub const T1C_H: u16 = 0x5;
+pub const T1L_L: u16 = 0x6; // Timer 1 latch, what the counter is reloaded with
+pub const T1L_H: u16 = 0x7;
+pub const T2C_L: u16 = 0x8; // Timer 2 counter
+pub const T2C_H: u16 = 0x9;
+pub const SR: u16 = 0xA; // Shift register
+pub const ACR: u16 = 0xB; // Auxiliary control, picks the timer and shift register modes
+pub const PCR: u16 = 0xC; // Peripheral control (handshake lines)
+pub const IFR: u16 = 0xD; // Interrupt flags
+pub const IER: u16 = 0xE; // Interrupt enable
+pub const ORA_NO_HANDSHAKE: u16 = 0xF;
+
+// Bits of IFR and IER
+pub const IRQ_CA2: u8 = 0b0000_0001;
+pub const IRQ_CA1: u8 = 0b0000_0010;
+pub const IRQ_SR: u8 = 0b0000_0100;
+pub const IRQ_CB2: u8 = 0b0000_1000;
+pub const IRQ_CB1: u8 = 0b0001_0000;
+pub const IRQ_T2: u8 = 0b0010_0000;
+pub const IRQ_T1: u8 = 0b0100_0000;
+
+/*
+    Whatever is wired to the VIA's ports (an LCD, LEDs, buttons...).
+
+    drive - The pin levels of both ports, called whenever the VIA changes them. Pins set as inputs show up as 1 (pulled up)
+    sense_a / sense_b - The levels of the pins the VIA reads as inputs
+    shift_out / shift_in - Bits going out of, or coming into, the shift register on CB2
+*/
+pub trait Pins {
+    fn drive(&mut self, _port_a: u8, _port_b: u8) {}
+
+    fn sense_a(&mut self) -> u8 {
+        0xFF
+    }
+
+    fn sense_b(&mut self) -> u8 {
+        0xFF
+    }
+
+    fn shift_out(&mut self, _bit: bool) {}
+
+    fn shift_in(&mut self) -> bool {
+        true
+    }
+}
+
+/*
+    A 6522 Versatile Interface Adapter, taking up 16 addresses from wherever it's attached.
+
+    Timer 1 counts down every cycle and flags an interrupt when it runs out. In one-shot mode that happens once
+    per write to T1C_H, in free-run mode (bit 6 of ACR) it reloads from the latch and keeps going. With bit 7 of ACR
+    set, it also flips PB7 every time it runs out. Timer 2 is always one-shot, counting pulses on PB6 isn't supported.
+
+    The shift register shifts a bit every cycle or at timer 2's rate (bits 2 - 4 of ACR), and flags an interrupt once
+    8 bits are done. The external clock (CB1) modes never shift, since nothing here drives CB1.
+
+    An interrupt reaches the CPU when a flag is set in IFR and its bit is enabled in IER
+*/
+pub struct Via {
+    base: u16,
+    pins: Option<Box<dyn Pins>>,
+
+    output_a: u8,
+    output_b: u8,
+    ddr_a: u8,
+    ddr_b: u8,
+
+    t1_counter: u16,
+    t1_latch: u16,
+    t1_armed: bool, // Cleared once a one-shot has gone off, so it only interrupts once
+    pb7: bool,
+
+    t2_counter: u16,
+    t2_latch_low: u8,
+    t2_armed: bool,
+
+    shift: u8,
+    shift_bits_left: u8, // 0 when the shift register is idle
+    shift_timer: i32, // Cycles until the next bit is shifted
+
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+}
+
+impl Via {
+    pub fn new(base: u16) -> Self {
+        Via {
+            base,
+            pins: None,
+            output_a: 0,
+            output_b: 0,
+            ddr_a: 0,
+            ddr_b: 0,
+            t1_counter: 0,
+            t1_latch: 0,
+            t1_armed: false,
+            pb7: true,
+            t2_counter: 0,
+            t2_latch_low: 0,
+            t2_armed: false,
+            shift: 0,
+            shift_bits_left: 0,
+            shift_timer: 0,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0,
+        }
+    }
+
+    // Wire something up to the ports
+    pub fn connect(&mut self, pins: Box<dyn Pins>) {
+        self.pins = Some(pins);
+        self.drive_pins();
+    }
+
+    // Output pins show what was written to them, input pins are pulled up
+    fn port_a_levels(&self) -> u8 {
+        (self.output_a & self.ddr_a) | !self.ddr_a
+    }
+
+    fn port_b_levels(&self) -> u8 {
+        self.with_pb7((self.output_b & self.ddr_b) | !self.ddr_b)
+    }
+
+    // Timer 1 takes over PB7 when bit 7 of ACR is set
+    fn with_pb7(&self, port_b: u8) -> u8 {
+        if self.acr & 0b1000_0000 != 0 {
+            (port_b & 0x7F) | if self.pb7 { 0x80 } else { 0 }
+        } else {
+            port_b
+        }
+    }
+
+    fn drive_pins(&mut self) {
+        let (port_a, port_b) = (self.port_a_levels(), self.port_b_levels());
+        if let Some(pins) = self.pins.as_mut() {
+            pins.drive(port_a, port_b);
+        }
+    }
+
+    fn read_port_a(&mut self) -> u8 {
+        let input = self.pins.as_mut().map_or(0xFF, |pins| pins.sense_a());
+        (self.output_a & self.ddr_a) | (input & !self.ddr_a)
+    }
+
+    fn read_port_b(&mut self) -> u8 {
+        let input = self.pins.as_mut().map_or(0xFF, |pins| pins.sense_b());
+        self.with_pb7((self.output_b & self.ddr_b) | (input & !self.ddr_b))
+    }
+
+    fn shift_mode(&self) -> u8 {
+        (self.acr >> 2) & 0b111
+    }
+
+    // How many cycles each bit takes, or None if the shift register is off or clocked from outside
+    fn shift_period(&self) -> Option<i32> {
+        match self.shift_mode() {
+            0b010 | 0b110 => Some(1),
+            0b001 | 0b100 | 0b101 => Some(self.t2_latch_low as i32 + 2),
+            _ => None,
+        }
+    }
+
+    // Reading or writing the shift register starts a new round of 8 bits
+    fn start_shift(&mut self) {
+        self.ifr &= !IRQ_SR;
+        if let Some(period) = self.shift_period() {
+            self.shift_bits_left = 8;
+            self.shift_timer = period;
+        }
+    }
+
+    fn shift_bit(&mut self) {
+        if self.shift_mode() & 0b100 != 0 {
+            // Shifting out, the bits go round so the byte comes back after 8 shifts
+            let bit = self.shift & 0x80 != 0;
+            self.shift = (self.shift << 1) | bit as u8;
+            if let Some(pins) = self.pins.as_mut() {
+                pins.shift_out(bit);
+            }
+        } else {
+            let bit = self.pins.as_mut().is_none_or(|pins| pins.shift_in());
+            self.shift = (self.shift << 1) | bit as u8;
+        }
+    }
+
+    fn tick_timer1(&mut self, cycles: u8) {
+        let mut counter = self.t1_counter as i32 - cycles as i32;
+
+        while counter < 0 {
+            if self.t1_armed {
+                self.ifr |= IRQ_T1;
+                if self.acr & 0b1000_0000 != 0 {
+                    self.pb7 = !self.pb7;
+                    self.drive_pins();
+                }
+            }
+
+            if self.acr & 0b0100_0000 != 0 {
+                counter += self.t1_latch as i32 + 2; // Free-run, reloading takes two extra cycles
+            } else {
+                self.t1_armed = false;
+                counter += 0x10000; // One-shot, the counter just keeps rolling over
+            }
+        }
+
+        self.t1_counter = counter as u16;
+    }
+
+    fn tick_timer2(&mut self, cycles: u8) {
+        if self.acr & 0b0010_0000 != 0 {
+            return; // Counting pulses on PB6, which nothing drives
+        }
+
+        if cycles as u16 > self.t2_counter && self.t2_armed {
+            self.ifr |= IRQ_T2;
+            self.t2_armed = false;
+        }
+
+        self.t2_counter = self.t2_counter.wrapping_sub(cycles as u16);
+    }
+
+    fn tick_shift(&mut self, cycles: u8) {
+        let period = match self.shift_period() {
+            Some(period) if self.shift_bits_left > 0 => period,
+            _ => return,
+        };
+
+        self.shift_timer -= cycles as i32;
+        while self.shift_timer <= 0 && self.shift_bits_left > 0 {
+            self.shift_bit();
+            self.shift_timer += period;
+            self.shift_bits_left -= 1;
+
+            if self.shift_bits_left == 0 {
+                if self.shift_mode() == 0b100 {
+                    self.shift_bits_left = 8; // Free-running, never stops and never interrupts
+                } else {
+                    self.ifr |= IRQ_SR;
+                }
+            }
+        }
+    }
+}
+
+impl Device for Via {
+    fn maps(&self, address: u16) -> bool {
+        address >= self.base && address - self.base < 0x10
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        match address - self.base {
+            ORB => {
+                self.ifr &= !(IRQ_CB1 | IRQ_CB2);
+                self.read_port_b()
+            }
+
+            ORA => {
+                self.ifr &= !(IRQ_CA1 | IRQ_CA2);
+                self.read_port_a()
+            }
+
+            ORA_NO_HANDSHAKE => self.read_port_a(),
+
+            T1C_L => {
+                self.ifr &= !IRQ_T1;
+                self.t1_counter as u8
+            }
+
+            T2C_L => {
+                self.ifr &= !IRQ_T2;
+                self.t2_counter as u8
+            }
+
+            SR => {
+                let data = self.shift;
+                self.start_shift();
+                data
+            }
+
+            register => self.peek(self.base + register),
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address - self.base {
+            ORB => {
+                self.ifr &= !(IRQ_CB1 | IRQ_CB2);
+                self.output_b = data;
+                self.drive_pins();
+            }
+
+            ORA => {
+                self.ifr &= !(IRQ_CA1 | IRQ_CA2);
+                self.output_a = data;
+                self.drive_pins();
+            }
+
+            ORA_NO_HANDSHAKE => {
+                self.output_a = data;
+                self.drive_pins();
+            }
+
+            DDRB => {
+                self.ddr_b = data;
+                self.drive_pins();
+            }
+
+            DDRA => {
+                self.ddr_a = data;
+                self.drive_pins();
+            }
+
+            T1C_L | T1L_L => self.t1_latch = (self.t1_latch & 0xFF00) | data as u16,
+            T1L_H => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (data as u16) << 8;
+                self.ifr &= !IRQ_T1;
+            }
+
+            // Writing the high byte of the counter starts timer 1
+            T1C_H => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (data as u16) << 8;
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.ifr &= !IRQ_T1;
+
+                if self.acr & 0b1000_0000 != 0 {
+                    self.pb7 = false;
+                    self.drive_pins();
+                }
+            }
+
+            T2C_L => self.t2_latch_low = data,
+            T2C_H => {
+                self.t2_counter = (data as u16) << 8 | self.t2_latch_low as u16;
+                self.t2_armed = true;
+                self.ifr &= !IRQ_T2;
+            }
+
+            SR => {
+                self.shift = data;
+                self.start_shift();
+            }
+
+            ACR => {
+                self.acr = data;
+                self.drive_pins();
+            }
+
+            PCR => self.pcr = data,
+
+            // Writing a 1 clears that flag
+            IFR => self.ifr &= !(data & 0x7F),
+
+            // Bit 7 says whether the other bits that are 1 get enabled or disabled
+            IER => {
+                if data & 0x80 != 0 {
+                    self.ier |= data & 0x7F;
+                } else {
+                    self.ier &= !(data & 0x7F);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        match address - self.base {
+            ORB => self.port_b_levels(),
+            ORA | ORA_NO_HANDSHAKE => self.port_a_levels(),
+            DDRB => self.ddr_b,
+            DDRA => self.ddr_a,
+            T1C_L => self.t1_counter as u8,
+            T1C_H => (self.t1_counter >> 8) as u8,
+            T1L_L => self.t1_latch as u8,
+            T1L_H => (self.t1_latch >> 8) as u8,
+            T2C_L => self.t2_counter as u8,
+            T2C_H => (self.t2_counter >> 8) as u8,
+            SR => self.shift,
+            ACR => self.acr,
+            PCR => self.pcr,
+
+            // Bit 7 is set when any enabled interrupt is flagged
+            IFR => self.ifr | if self.irq() { 0x80 } else { 0 },
+            IER => self.ier | 0x80,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.tick_timer1(cycles);
+        self.tick_timer2(cycles);
+        self.tick_shift(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.ifr & self.ier & 0x7F != 0
+    }
+}
@@ -0,0 +1,68 @@
+// Kinds of memory access, one bit each so a watchpoint can be on more than one
+pub const READ: u8 = 0b001;
+pub const WRITE: u8 = 0b010;
+pub const EXECUTE: u8 = 0b100; // Only used by breakpoints, the CPU doesn't check instruction fetches
+
+// A range of addresses (both ends included) to stop on when they're accessed
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub access: u8,
+}
+
+// Who touched the memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Instruction(u16), // The instruction at this address
+    Device,           // The emulator or a front-end, like the random byte in 0xFE or a key press
+}
+
+// An access that hit a watchpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub address: u16,
+    pub access: u8,
+    pub value: u8, // The byte read or written
+    pub source: Source,
+    pub watched: u8, // The accesses the watchpoint it hit is on, which can be more than this one
+    pub dummy: bool, // One of the 6502's extra accesses (like INC writing the old value back), which don't stop the debuggers
+}
+
+/*
+    Watchpoints on memory. The CPU checks every read and write against them, and the accesses that hit one are kept
+    until whoever is running the program (the debugger or the GDB stub) takes them and decides whether to stop
+*/
+#[derive(Default)]
+pub struct Watches {
+    pub points: Vec<Watchpoint>,
+    pub hits: Vec<Hit>,
+}
+
+impl Watches {
+    pub fn add(&mut self, start: u16, end: u16, access: u8) {
+        self.points.push(Watchpoint { start, end, access });
+    }
+
+    // Returns false if there was no such watchpoint
+    pub fn remove(&mut self, start: u16, end: u16, access: u8) -> bool {
+        let count = self.points.len();
+        self.points.retain(|point| !(point.start == start && point.end == end && point.access == access));
+        self.points.len() != count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn check(&mut self, address: u16, access: u8, value: u8, source: Source, dummy: bool) {
+        let point = self.points.iter().find(|point| point.access & access != 0 && (point.start..=point.end).contains(&address));
+        if let Some(point) = point {
+            self.hits.push(Hit { address, access, value, source, watched: point.access, dummy });
+        }
+    }
+
+    // The hits since the last time they were taken, oldest first
+    pub fn take(&mut self) -> Vec<Hit> {
+        std::mem::take(&mut self.hits)
+    }
+}